@@ -1,16 +1,13 @@
+use crate::crypt::{CryptErr, EncryptionKey, EncryptionLayer};
 use crate::utils::{create_temp_file, make_permanent, UtilsErr};
 use bzip2::write::BzEncoder;
-use std::{
-    error::Error,
-    fs::File,
-    io::{self, Write},
-    path::Path,
-};
+use std::{error::Error, fs::File, io, path::Path};
 
 #[derive(Debug)]
 pub enum BzipErr {
     TempFileCreationFailed(UtilsErr),
     CompressionFailed(io::Error),
+    EncryptionFailed(CryptErr),
     TempFileFinalizationFailed(UtilsErr),
 }
 
@@ -23,6 +20,7 @@ impl std::fmt::Display for BzipErr {
                 write!(f, "Temporary file creation failed: {}", e)
             }
             BzipErr::CompressionFailed(e) => write!(f, "Bzip2 compression failed: {}", e),
+            BzipErr::EncryptionFailed(e) => write!(f, "Encryption failed: {}", e),
             BzipErr::TempFileFinalizationFailed(e) => {
                 write!(f, "Failed to finalize the temporary file: {}", e)
             }
@@ -31,21 +29,38 @@ impl std::fmt::Display for BzipErr {
 }
 
 pub fn create_file_bzip2<P: AsRef<Path>>(input_path: P, output_path: P) -> Result<(), BzipErr> {
+    write_file_bzip2(input_path.as_ref(), output_path.as_ref(), None)
+}
+
+/// Like `create_file_bzip2`, with the compressed output encrypted with age.
+pub fn create_file_bzip2_encrypted<P: AsRef<Path>>(
+    input_path: P,
+    output_path: P,
+    key: &EncryptionKey,
+) -> Result<(), BzipErr> {
+    write_file_bzip2(input_path.as_ref(), output_path.as_ref(), Some(key))
+}
+
+fn write_file_bzip2(
+    input_path: &Path,
+    output_path: &Path,
+    key: Option<&EncryptionKey>,
+) -> Result<(), BzipErr> {
     let (temp_dir, temp_file_path) =
         create_temp_file("bz2").map_err(BzipErr::TempFileCreationFailed)?;
     let temp_file = File::create(&temp_file_path).map_err(BzipErr::CompressionFailed)?;
-    let mut encoder = BzEncoder::new(temp_file, bzip2::Compression::best());
+    let output = EncryptionLayer::new(temp_file, key).map_err(BzipErr::EncryptionFailed)?;
+    let mut encoder = BzEncoder::new(output, bzip2::Compression::best());
 
     let mut input_file = File::open(input_path).map_err(BzipErr::CompressionFailed)?;
     io::copy(&mut input_file, &mut encoder).map_err(BzipErr::CompressionFailed)?;
-    encoder.finish().map_err(|_| {
-        BzipErr::CompressionFailed(io::Error::new(
-            io::ErrorKind::Other,
-            "Failed to finish compression",
-        ))
-    })?;
-
-    make_permanent(temp_dir, &temp_file_path, output_path.as_ref())
+    encoder
+        .finish()
+        .map_err(|_| BzipErr::CompressionFailed(io::Error::other("Failed to finish compression")))?
+        .finish()
+        .map_err(BzipErr::CompressionFailed)?;
+
+    make_permanent(temp_dir, &temp_file_path, output_path)
         .map_err(BzipErr::TempFileFinalizationFailed)?;
     Ok(())
 }
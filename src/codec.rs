@@ -0,0 +1,376 @@
+use crate::gzip::{GzipOptions, GzipWriter};
+use crate::utils::{create_temp_file, make_permanent, UtilsErr};
+use bzip2::read::MultiBzDecoder;
+use bzip2::write::BzEncoder;
+use flate2::bufread::{MultiGzDecoder, ZlibDecoder};
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use sha2::{Digest, Sha256};
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+use zstd::stream::read::Decoder as ZstdDecoder;
+use zstd::stream::write::Encoder as ZstdEncoder;
+
+#[derive(Debug)]
+pub enum RecompressErr {
+    FileOpenFailed(io::Error),
+    UnknownCodec(PathBuf),
+    OutputExists(PathBuf),
+    InvalidLevel { codec: Codec, level: u32 },
+    TempFileCreationFailed(UtilsErr),
+    DecompressionFailed(io::Error),
+    CompressionFailed(io::Error),
+    VerificationFailed(PathBuf),
+    TempFileFinalizationFailed(UtilsErr),
+    RemoveFailed(io::Error),
+}
+
+impl fmt::Display for RecompressErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecompressErr::FileOpenFailed(e) => {
+                write!(f, "Failed to open the file to recompress: {}", e)
+            }
+            RecompressErr::UnknownCodec(path) => {
+                write!(
+                    f,
+                    "{} is not gzip, bzip2, xz, zlib or zstd data",
+                    path.display()
+                )
+            }
+            RecompressErr::OutputExists(path) => {
+                write!(f, "{} already exists", path.display())
+            }
+            RecompressErr::InvalidLevel { codec, level } => {
+                let levels = codec.levels();
+                write!(
+                    f,
+                    "Invalid {:?} level {}: expected {} to {}",
+                    codec,
+                    level,
+                    levels.start(),
+                    levels.end()
+                )
+            }
+            RecompressErr::TempFileCreationFailed(e) => {
+                write!(f, "Temporary file creation failed: {}", e)
+            }
+            RecompressErr::DecompressionFailed(e) => write!(f, "Decompression failed: {}", e),
+            RecompressErr::CompressionFailed(e) => write!(f, "Compression failed: {}", e),
+            RecompressErr::VerificationFailed(path) => write!(
+                f,
+                "The recompressed data does not match the contents of {}",
+                path.display()
+            ),
+            RecompressErr::TempFileFinalizationFailed(e) => {
+                write!(f, "Failed to finalize the temporary file: {}", e)
+            }
+            RecompressErr::RemoveFailed(e) => {
+                write!(f, "Failed to remove the original file: {}", e)
+            }
+        }
+    }
+}
+
+impl Error for RecompressErr {}
+
+/// Compression format of a single compressed file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Gzip,
+    Bzip2,
+    Xz,
+    /// A zlib (RFC 1950) stream, as written by `encode_file_zlib`.
+    Zlib,
+    Zstd,
+}
+
+impl Codec {
+    /// Codec of data starting with `magic`. Zlib streams are recognised by
+    /// their two-byte header, which any data has a small chance of matching.
+    pub fn detect(magic: &[u8]) -> Option<Self> {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Some(Codec::Gzip)
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+            Some(Codec::Xz)
+        } else if magic.starts_with(b"BZh") {
+            Some(Codec::Bzip2)
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Codec::Zstd)
+        } else {
+            match magic {
+                // Deflate with a window of at most 32 KiB, no preset dictionary
+                // and a header that is a multiple of 31.
+                [cmf, flg, ..]
+                    if cmf & 0x0f == 8
+                        && cmf >> 4 <= 7
+                        && flg & 0x20 == 0
+                        && u16::from_be_bytes([*cmf, *flg]) % 31 == 0 =>
+                {
+                    Some(Codec::Zlib)
+                }
+                _ => None,
+            }
+        }
+    }
+
+    /// File extension, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Codec::Gzip => "gz",
+            Codec::Bzip2 => "bz2",
+            Codec::Xz => "xz",
+            Codec::Zlib => "zz",
+            Codec::Zstd => "zst",
+        }
+    }
+
+    /// Compression levels the codec accepts.
+    pub fn levels(self) -> RangeInclusive<u32> {
+        match self {
+            Codec::Gzip | Codec::Zlib | Codec::Xz => 0..=9,
+            Codec::Bzip2 => 1..=9,
+            Codec::Zstd => 1..=22,
+        }
+    }
+
+    /// Level used when none is given: the best one, except for zstd, whose
+    /// levels above 19 need much more memory to decompress.
+    pub fn default_level(self) -> u32 {
+        match self {
+            Codec::Zstd => 19,
+            codec => *codec.levels().end(),
+        }
+    }
+}
+
+/// Encoder writing one of the codecs, or the data as is.
+pub(crate) enum CodecWriter<W: Write> {
+    Plain(W),
+    Gzip(GzipWriter<W>),
+    Bzip2(BzEncoder<W>),
+    Xz(XzEncoder<W>),
+    Zlib(ZlibEncoder<W>),
+    Zstd(ZstdEncoder<'static, W>),
+}
+
+impl<W: Write> CodecWriter<W> {
+    /// Encoder for `codec` at `level`, which must be one of `codec.levels()`.
+    pub(crate) fn new(output: W, codec: Codec, level: u32) -> io::Result<Self> {
+        Ok(match codec {
+            Codec::Gzip => CodecWriter::Gzip(
+                GzipWriter::with_level(output, &GzipOptions::default(), Compression::new(level))
+                    .map_err(io::Error::other)?,
+            ),
+            Codec::Bzip2 => {
+                CodecWriter::Bzip2(BzEncoder::new(output, bzip2::Compression::new(level)))
+            }
+            Codec::Xz => CodecWriter::Xz(XzEncoder::new(output, level)),
+            Codec::Zlib => CodecWriter::Zlib(ZlibEncoder::new(output, Compression::new(level))),
+            Codec::Zstd => CodecWriter::Zstd(ZstdEncoder::new(output, level as i32)?),
+        })
+    }
+
+    /// Writes the end of the compressed stream and returns the output.
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            CodecWriter::Plain(output) => Ok(output),
+            CodecWriter::Gzip(encoder) => encoder.finish(),
+            CodecWriter::Bzip2(encoder) => encoder.finish(),
+            CodecWriter::Xz(encoder) => encoder.finish(),
+            CodecWriter::Zlib(encoder) => encoder.finish(),
+            CodecWriter::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for CodecWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CodecWriter::Plain(output) => output.write(buf),
+            CodecWriter::Gzip(encoder) => encoder.write(buf),
+            CodecWriter::Bzip2(encoder) => encoder.write(buf),
+            CodecWriter::Xz(encoder) => encoder.write(buf),
+            CodecWriter::Zlib(encoder) => encoder.write(buf),
+            CodecWriter::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CodecWriter::Plain(output) => output.flush(),
+            CodecWriter::Gzip(encoder) => encoder.flush(),
+            CodecWriter::Bzip2(encoder) => encoder.flush(),
+            CodecWriter::Xz(encoder) => encoder.flush(),
+            CodecWriter::Zlib(encoder) => encoder.flush(),
+            CodecWriter::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Decodes `codec` data, including concatenated gzip members and bzip2, xz
+/// and zstd streams.
+pub(crate) fn decoder<R: BufRead + 'static>(reader: R, codec: Codec) -> io::Result<Box<dyn Read>> {
+    Ok(match codec {
+        Codec::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Codec::Bzip2 => Box::new(MultiBzDecoder::new(reader)),
+        Codec::Xz => Box::new(XzDecoder::new_multi_decoder(reader)),
+        Codec::Zlib => Box::new(ZlibDecoder::new(reader)),
+        Codec::Zstd => Box::new(ZstdDecoder::with_buffer(reader)?),
+    })
+}
+
+/// Settings for `recompress_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct RecompressOptions {
+    /// Decompresses the new file again and checks that its contents have the
+    /// same SHA-256 as those of the original before the original is replaced.
+    pub verify: bool,
+    /// Leaves the original in place when the new file has another name.
+    pub keep_original: bool,
+    /// Replaces an existing file at the new name instead of failing with
+    /// `RecompressErr::OutputExists`.
+    pub overwrite: bool,
+}
+
+pub fn recompress<P: AsRef<Path>>(
+    input_path: P,
+    output_format: Codec,
+    level: Option<u32>,
+) -> Result<PathBuf, RecompressErr> {
+    recompress_with_options(
+        input_path,
+        output_format,
+        level,
+        &RecompressOptions::default(),
+    )
+}
+
+/// Decodes a gzip, bzip2, xz, zlib or zstd file (detected from its magic
+/// bytes) and encodes it as `output_format` at `level` (or the codec's
+/// default level) in one streaming pass. The new file replaces the original
+/// under the extension of the new codec, `logs.json.gz` becoming
+/// `logs.json.xz`, with the original's permissions and modification time.
+/// An existing file under the new name is only replaced with
+/// `RecompressOptions::overwrite`. Returns the path of the new file.
+pub fn recompress_with_options<P: AsRef<Path>>(
+    input_path: P,
+    output_format: Codec,
+    level: Option<u32>,
+    options: &RecompressOptions,
+) -> Result<PathBuf, RecompressErr> {
+    let input_path = input_path.as_ref();
+    let level = level.unwrap_or(output_format.default_level());
+    if !output_format.levels().contains(&level) {
+        return Err(RecompressErr::InvalidLevel {
+            codec: output_format,
+            level,
+        });
+    }
+    let input = File::open(input_path).map_err(RecompressErr::FileOpenFailed)?;
+    let metadata = input.metadata().map_err(RecompressErr::FileOpenFailed)?;
+    let mut input = BufReader::new(input);
+    let magic = input.fill_buf().map_err(RecompressErr::FileOpenFailed)?;
+    let input_format = Codec::detect(magic)
+        .ok_or_else(|| RecompressErr::UnknownCodec(input_path.to_path_buf()))?;
+    let output_path = recompressed_path(input_path, input_format, output_format);
+    if output_path != input_path && !options.overwrite && output_path.symlink_metadata().is_ok() {
+        return Err(RecompressErr::OutputExists(output_path));
+    }
+
+    let (temp_dir, temp_file_path) = create_temp_file(output_format.extension())
+        .map_err(RecompressErr::TempFileCreationFailed)?;
+    let temp_file = File::create(&temp_file_path).map_err(RecompressErr::CompressionFailed)?;
+    let mut encoder = CodecWriter::new(BufWriter::new(temp_file), output_format, level)
+        .map_err(RecompressErr::CompressionFailed)?;
+    let mut reader = decoder(input, input_format).map_err(RecompressErr::DecompressionFailed)?;
+    let mut hasher = options.verify.then(Sha256::new);
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(RecompressErr::DecompressionFailed(e)),
+        };
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&buffer[..read]);
+        }
+        encoder
+            .write_all(&buffer[..read])
+            .map_err(RecompressErr::CompressionFailed)?;
+    }
+    let temp_file = encoder
+        .finish()
+        .map_err(RecompressErr::CompressionFailed)?
+        .into_inner()
+        .map_err(|e| RecompressErr::CompressionFailed(e.into_error()))?;
+    temp_file
+        .set_permissions(metadata.permissions())
+        .map_err(RecompressErr::CompressionFailed)?;
+    if let Ok(modified) = metadata.modified() {
+        temp_file
+            .set_modified(modified)
+            .map_err(RecompressErr::CompressionFailed)?;
+    }
+    drop(temp_file);
+
+    if let Some(hasher) = hasher {
+        let written = File::open(&temp_file_path).map_err(RecompressErr::FileOpenFailed)?;
+        let mut check = decoder(BufReader::new(written), output_format)
+            .map_err(RecompressErr::DecompressionFailed)?;
+        let mut check_hasher = Sha256::new();
+        io::copy(&mut check, &mut check_hasher).map_err(RecompressErr::DecompressionFailed)?;
+        if check_hasher.finalize() != hasher.finalize() {
+            return Err(RecompressErr::VerificationFailed(input_path.to_path_buf()));
+        }
+    }
+
+    make_permanent(temp_dir, &temp_file_path, &output_path)
+        .map_err(RecompressErr::TempFileFinalizationFailed)?;
+    if output_path != input_path && !options.keep_original {
+        fs::remove_file(input_path).map_err(RecompressErr::RemoveFailed)?;
+    }
+    Ok(output_path)
+}
+
+/// Path of the recompressed file: the extension of the input codec swapped
+/// for that of the output codec (`backup.tgz` becoming `backup.tar.xz`), or
+/// the new extension appended when the input has another one.
+fn recompressed_path(input_path: &Path, input_format: Codec, output_format: Codec) -> PathBuf {
+    let extension = input_path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let input_extensions: &[&str] = match input_format {
+        Codec::Gzip => &["gz"],
+        Codec::Bzip2 => &["bz2", "bz"],
+        Codec::Xz => &["xz"],
+        Codec::Zlib => &["zz", "zlib"],
+        Codec::Zstd => &["zst"],
+    };
+    match extension.as_deref() {
+        Some(extension) if input_extensions.contains(&extension) => {
+            input_path.with_extension(output_format.extension())
+        }
+        Some(extension)
+            if extension
+                .strip_prefix('t')
+                .is_some_and(|rest| input_extensions.contains(&rest)) =>
+        {
+            input_path.with_extension(format!("tar.{}", output_format.extension()))
+        }
+        _ => {
+            let mut path = input_path.as_os_str().to_os_string();
+            path.push(format!(".{}", output_format.extension()));
+            PathBuf::from(path)
+        }
+    }
+}
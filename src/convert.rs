@@ -0,0 +1,354 @@
+use crate::codec::Codec;
+use crate::crypt::EncryptionKey;
+use crate::entry::{is_zip, ArchiveEntry, EntryKind};
+use crate::owner::Owner;
+use crate::tar::{read_tar_entries, TarConvertWriter, TarErr, TarOptions};
+use crate::transform::{PathTransform, TransformErr};
+use crate::utils::contained_path;
+use crate::zip::{
+    entry_name, read_zip_entries, ZipConvertWriter, ZipNameEncoding, ZipOptions, ZipPassword,
+    ZippingErr,
+};
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+use tar::EntryType;
+
+#[derive(Debug)]
+pub enum ConvertErr {
+    UnknownFormat(PathBuf),
+    InputOpenFailed(io::Error),
+    UnsafeEntryPath(String),
+    PathTransformFailed(TransformErr),
+    Tar(TarErr),
+    Zip(ZippingErr),
+}
+
+impl fmt::Display for ConvertErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConvertErr::UnknownFormat(path) => {
+                write!(
+                    f,
+                    "Cannot tell the archive format from the name {}",
+                    path.display()
+                )
+            }
+            ConvertErr::InputOpenFailed(e) => {
+                write!(f, "Failed to open the archive to convert: {}", e)
+            }
+            ConvertErr::UnsafeEntryPath(name) => {
+                write!(f, "Entry path escapes the archive root: {}", name)
+            }
+            ConvertErr::PathTransformFailed(e) => {
+                write!(f, "Failed to rewrite an entry path: {}", e)
+            }
+            ConvertErr::Tar(e) => write!(f, "Tar conversion failed: {}", e),
+            ConvertErr::Zip(e) => write!(f, "Zip conversion failed: {}", e),
+        }
+    }
+}
+
+impl Error for ConvertErr {}
+
+impl From<TarErr> for ConvertErr {
+    fn from(error: TarErr) -> Self {
+        ConvertErr::Tar(error)
+    }
+}
+
+impl From<ZippingErr> for ConvertErr {
+    fn from(error: ZippingErr) -> Self {
+        ConvertErr::Zip(error)
+    }
+}
+
+/// Container and compression of an archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarBz2,
+    TarXz,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    /// Format named by the extension of `path`: `.zip`, `.tar`, `.tar.gz` or
+    /// `.tgz`, `.tar.bz2` or `.tbz2`, `.tar.xz` or `.txz`, `.tar.zst` or `.tzst`.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let name = path.as_ref().file_name()?.to_string_lossy().to_lowercase();
+        [
+            (".zip", ArchiveFormat::Zip),
+            (".tar", ArchiveFormat::Tar),
+            (".tar.gz", ArchiveFormat::TarGz),
+            (".tgz", ArchiveFormat::TarGz),
+            (".tar.bz2", ArchiveFormat::TarBz2),
+            (".tbz2", ArchiveFormat::TarBz2),
+            (".tar.xz", ArchiveFormat::TarXz),
+            (".txz", ArchiveFormat::TarXz),
+            (".tar.zst", ArchiveFormat::TarZst),
+            (".tzst", ArchiveFormat::TarZst),
+        ]
+        .into_iter()
+        .find_map(|(extension, format)| name.ends_with(extension).then_some(format))
+    }
+
+    /// Compression of a tar format; `None` for plain tar and zip.
+    fn codec(self) -> Option<Codec> {
+        match self {
+            ArchiveFormat::TarGz => Some(Codec::Gzip),
+            ArchiveFormat::TarBz2 => Some(Codec::Bzip2),
+            ArchiveFormat::TarXz => Some(Codec::Xz),
+            ArchiveFormat::TarZst => Some(Codec::Zstd),
+            ArchiveFormat::Tar | ArchiveFormat::Zip => None,
+        }
+    }
+}
+
+/// Settings for `convert_archive`.
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// Output format; taken from the extension of the output path when unset.
+    pub format: Option<ArchiveFormat>,
+    /// Settings for zip output: compression method and level, permissions,
+    /// encryption, comment, volume size and the entry name transform.
+    pub zip: ZipOptions,
+    /// Settings for tar output: header format, gzip header, owner mapping,
+    /// encryption, volume size and the entry name transform.
+    pub tar: TarOptions,
+    /// Passwords of encrypted entries of zip input.
+    pub password: ZipPassword,
+    /// Fallback encoding of zip input entry names without the UTF-8 flag.
+    pub name_encoding: ZipNameEncoding,
+    /// Key for tar input encrypted with age.
+    pub decryption: Option<EncryptionKey>,
+}
+
+/// Something of the input that the output does not hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversionLoss {
+    /// A device or FIFO entry, which zip cannot store, left out.
+    SkippedEntry {
+        name: String,
+        kind: &'static str,
+    },
+    /// A hard link stored as a copy of its target.
+    HardLinkCopied {
+        name: String,
+        target: String,
+    },
+    /// A hard link left out because the transform dropped its target.
+    HardLinkDropped {
+        name: String,
+        target: String,
+    },
+    /// A sparse file stored with its holes filled in.
+    SparseFilled(String),
+    /// The owner of an entry; zip has no owner fields, and reproducible tar
+    /// output replaces it with root.
+    Owner(String),
+    /// The sub-second part of a modification time, which zip and
+    /// reproducible tar output do not keep.
+    MtimePrecision(String),
+    ExtendedAttributes(String),
+    /// The comment of an entry; tar has no comments.
+    EntryComment(String),
+    /// Extra fields of an entry besides those this crate writes itself.
+    ExtraFields(String),
+    ArchiveComment,
+    /// An encrypted zip entry written without encryption.
+    EntryEncryption(String),
+    /// An encrypted archive converted into an unencrypted one.
+    ArchiveEncryption,
+}
+
+/// Outcome of `convert_archive`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConversionReport {
+    /// Number of entries written to the output.
+    pub entries: usize,
+    pub losses: Vec<ConversionLoss>,
+}
+
+/// Copies every entry of the archive at `input_path` into a new archive at
+/// `output_path` without extracting anything to disk. The input is read as
+/// zip when its name ends in `.zip` or it starts with a zip signature, and
+/// as a (compressed, encrypted or split) tar archive otherwise.
+///
+/// Names, permissions, modification times and links are kept where both
+/// formats can hold them; the report lists everything else that was left
+/// behind, such as owners and extended attributes when converting to zip or
+/// comments when converting to tar.
+pub fn convert_archive<P: AsRef<Path>>(
+    input_path: P,
+    output_path: P,
+    options: &ConvertOptions,
+) -> Result<ConversionReport, ConvertErr> {
+    let (input_path, output_path) = (input_path.as_ref(), output_path.as_ref());
+    let format = match options.format {
+        Some(format) => format,
+        None => ArchiveFormat::from_path(output_path)
+            .ok_or_else(|| ConvertErr::UnknownFormat(output_path.to_path_buf()))?,
+    };
+    let mut output = match format {
+        ArchiveFormat::Zip => ConvertOutput::Zip(Box::new(ZipConvertWriter::new(&options.zip)?)),
+        format => ConvertOutput::Tar(Box::new(TarConvertWriter::new(
+            format.codec(),
+            &options.tar,
+        )?)),
+    };
+    let mut report = ConversionReport::default();
+    let add = |entry, data: &mut dyn Read| output.add(entry, data, &mut report);
+
+    let (comment, encrypted) = match is_zip(input_path).map_err(ConvertErr::InputOpenFailed)? {
+        true => (
+            read_zip_entries(input_path, &options.password, options.name_encoding, add)?,
+            false,
+        ),
+        false => (
+            None,
+            read_tar_entries(input_path, options.decryption.as_ref(), add)?,
+        ),
+    };
+    let comment = comment.filter(|comment| !comment.is_empty());
+    let output_encrypted = match &output {
+        ConvertOutput::Zip(_) => !matches!(options.zip.password, ZipPassword::None),
+        ConvertOutput::Tar(_) => options.tar.encryption.is_some(),
+    };
+    if encrypted && !output_encrypted {
+        report.losses.push(ConversionLoss::ArchiveEncryption);
+    }
+    match output {
+        ConvertOutput::Zip(writer) => writer.finish(output_path, comment.as_deref())?,
+        ConvertOutput::Tar(writer) => {
+            if comment.is_some() {
+                report.losses.push(ConversionLoss::ArchiveComment);
+            }
+            writer.finish(output_path)?;
+        }
+    }
+    Ok(report)
+}
+
+enum ConvertOutput<'a> {
+    Zip(Box<ZipConvertWriter<'a>>),
+    Tar(Box<TarConvertWriter<'a>>),
+}
+
+impl ConvertOutput<'_> {
+    /// Renames `entry` with the transform of the output options, records
+    /// what the output cannot hold and writes it.
+    fn add(
+        &mut self,
+        mut entry: ArchiveEntry,
+        data: &mut dyn Read,
+        report: &mut ConversionReport,
+    ) -> Result<(), ConvertErr> {
+        let transform = match self {
+            ConvertOutput::Zip(writer) => &writer.options.transform,
+            ConvertOutput::Tar(writer) => &writer.options.transform,
+        };
+        let Some(name) = output_name(&entry.name, transform)? else {
+            return Ok(());
+        };
+        entry.name = name;
+        if let EntryKind::HardLink(target) = &entry.kind {
+            let Some(output_target) = output_name(target, transform)? else {
+                report.losses.push(ConversionLoss::HardLinkDropped {
+                    name: entry.name,
+                    target: target.clone(),
+                });
+                return Ok(());
+            };
+            entry.kind = EntryKind::HardLink(output_target);
+        }
+
+        let name = &entry.name;
+        let mut losses = Vec::new();
+        if entry.sparse {
+            losses.push(ConversionLoss::SparseFilled(name.clone()));
+        }
+        let has_owner = entry
+            .owner
+            .as_ref()
+            .is_some_and(|owner| *owner != Owner::default());
+        let rounded = match self {
+            ConvertOutput::Zip(_) => true,
+            ConvertOutput::Tar(writer) => writer.options.reproducible.is_some(),
+        };
+        if rounded && entry.mtime_nanos != 0 {
+            losses.push(ConversionLoss::MtimePrecision(name.clone()));
+        }
+        match self {
+            ConvertOutput::Zip(writer) => {
+                match &entry.kind {
+                    EntryKind::Special { entry_type, .. } => {
+                        let kind = match entry_type {
+                            EntryType::Char => "character device",
+                            EntryType::Block => "block device",
+                            _ => "FIFO",
+                        };
+                        report.losses.push(ConversionLoss::SkippedEntry {
+                            name: name.clone(),
+                            kind,
+                        });
+                        return Ok(());
+                    }
+                    EntryKind::HardLink(target) => losses.push(ConversionLoss::HardLinkCopied {
+                        name: name.clone(),
+                        target: target.clone(),
+                    }),
+                    _ => {}
+                }
+                if has_owner {
+                    losses.push(ConversionLoss::Owner(name.clone()));
+                }
+                if !entry.xattrs.is_empty() {
+                    losses.push(ConversionLoss::ExtendedAttributes(name.clone()));
+                }
+                if entry.encrypted && writer.options.password.for_entry(name).is_none() {
+                    losses.push(ConversionLoss::EntryEncryption(name.clone()));
+                }
+                writer.add(&entry, data)?;
+            }
+            ConvertOutput::Tar(writer) => {
+                if has_owner && writer.options.reproducible.is_some() {
+                    losses.push(ConversionLoss::Owner(name.clone()));
+                }
+                if !entry.comment.is_empty() {
+                    losses.push(ConversionLoss::EntryComment(name.clone()));
+                }
+                if !entry.extra_fields.is_empty() {
+                    losses.push(ConversionLoss::ExtraFields(name.clone()));
+                }
+                if entry.encrypted && writer.options.encryption.is_none() {
+                    losses.push(ConversionLoss::EntryEncryption(name.clone()));
+                }
+                writer.add(&entry, data)?;
+            }
+        }
+        report.entries += 1;
+        report.losses.extend(losses);
+        Ok(())
+    }
+}
+
+/// Output name of an input entry, or `None` when the transform drops it or
+/// it names the archive root (like the `./` entry of many tar archives).
+fn output_name(name: &str, transform: &PathTransform) -> Result<Option<String>, ConvertErr> {
+    let unsafe_path = || ConvertErr::UnsafeEntryPath(name.to_string());
+    contained_path(Path::new(""), Path::new(name)).ok_or_else(unsafe_path)?;
+    let Some(path) = transform
+        .apply(Path::new(name))
+        .map_err(ConvertErr::PathTransformFailed)?
+    else {
+        return Ok(None);
+    };
+    let relative = contained_path(Path::new(""), &path).ok_or_else(unsafe_path)?;
+    Ok(entry_name(&relative))
+}
@@ -0,0 +1,321 @@
+use crate::utils::{create_temp_file, make_permanent, UtilsErr};
+use age::secrecy::SecretString;
+use age::stream::{StreamReader, StreamWriter};
+use age::{DecryptError, Decryptor, EncryptError, Encryptor, IdentityFile};
+use std::{
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// First line of every age file.
+pub(crate) const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+
+#[derive(Debug)]
+pub enum CryptErr {
+    TempFileCreationFailed(UtilsErr),
+    FileOpenFailed(io::Error),
+    WriteFailed(io::Error),
+    KeyFileFailed(PathBuf, io::Error),
+    InvalidRecipient(String),
+    EncryptionSetupFailed(EncryptError),
+    EncryptionFailed(io::Error),
+    /// The key only holds public recipients, which cannot decrypt.
+    NoIdentity,
+    /// The input is encrypted but no key was given.
+    KeyRequired,
+    /// Wrong passphrase, or none of the secret keys matches.
+    WrongKey,
+    DecryptionFailed(DecryptError),
+    /// The stream ended after a complete chunk, before its final chunk.
+    Truncated,
+    /// A chunk failed authentication; a stream cut off in the middle of a
+    /// chunk is reported this way too.
+    CorruptData(io::Error),
+    TempFileFinalizationFailed(UtilsErr),
+}
+
+impl fmt::Display for CryptErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CryptErr::TempFileCreationFailed(e) => {
+                write!(f, "Temporary file creation failed: {}", e)
+            }
+            CryptErr::FileOpenFailed(e) => write!(f, "Failed to open a file: {}", e),
+            CryptErr::WriteFailed(e) => write!(f, "Failed to write the output file: {}", e),
+            CryptErr::KeyFileFailed(path, e) => {
+                write!(f, "Failed to read key file {}: {}", path.display(), e)
+            }
+            CryptErr::InvalidRecipient(recipient) => {
+                write!(f, "Invalid age recipient: {}", recipient)
+            }
+            CryptErr::EncryptionSetupFailed(e) => write!(f, "Failed to set up encryption: {}", e),
+            CryptErr::EncryptionFailed(e) => write!(f, "Encryption failed: {}", e),
+            CryptErr::NoIdentity => write!(f, "The key has no secret key to decrypt with"),
+            CryptErr::KeyRequired => write!(f, "The input is encrypted and no key was given"),
+            CryptErr::WrongKey => write!(f, "Wrong passphrase or key"),
+            CryptErr::DecryptionFailed(e) => write!(f, "Decryption failed: {}", e),
+            CryptErr::Truncated => write!(f, "The encrypted stream is truncated"),
+            CryptErr::CorruptData(e) => {
+                write!(f, "The encrypted stream is corrupt or truncated: {}", e)
+            }
+            CryptErr::TempFileFinalizationFailed(e) => {
+                write!(f, "Failed to finalize the temporary file: {}", e)
+            }
+        }
+    }
+}
+
+impl Error for CryptErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CryptErr::FileOpenFailed(e)
+            | CryptErr::WriteFailed(e)
+            | CryptErr::KeyFileFailed(_, e)
+            | CryptErr::EncryptionFailed(e)
+            | CryptErr::CorruptData(e) => Some(e),
+            CryptErr::EncryptionSetupFailed(e) => Some(e),
+            CryptErr::DecryptionFailed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl CryptErr {
+    /// Classifies an error returned while reading a `DecryptReader`.
+    pub fn from_read_error(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::UnexpectedEof => CryptErr::Truncated,
+            _ => CryptErr::CorruptData(error),
+        }
+    }
+}
+
+/// Key of the age encryption layer (<https://age-encryption.org/v1>), so
+/// the output can also be decrypted with the `age` command line tool.
+#[derive(Clone)]
+pub enum EncryptionKey {
+    /// A passphrase, stretched with scrypt.
+    Passphrase(String),
+    /// An age identity file as written by `age-keygen`: encrypts to the
+    /// public keys of its secret keys and decrypts with them.
+    KeyFile(PathBuf),
+    /// age public keys (`age1...`); can only encrypt.
+    Recipients(Vec<String>),
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncryptionKey::Passphrase(_) => write!(f, "Passphrase(..)"),
+            EncryptionKey::KeyFile(path) => f.debug_tuple("KeyFile").field(path).finish(),
+            EncryptionKey::Recipients(recipients) => {
+                f.debug_tuple("Recipients").field(recipients).finish()
+            }
+        }
+    }
+}
+
+impl EncryptionKey {
+    fn identity_file(path: &Path) -> Result<IdentityFile<age::NoCallbacks>, CryptErr> {
+        let name = path.to_string_lossy().into_owned();
+        IdentityFile::from_file(name).map_err(|e| CryptErr::KeyFileFailed(path.to_path_buf(), e))
+    }
+
+    fn encryptor(&self) -> Result<Encryptor, CryptErr> {
+        let recipients: Vec<Box<dyn age::Recipient + Send>> = match self {
+            EncryptionKey::Passphrase(passphrase) => {
+                return Ok(Encryptor::with_user_passphrase(SecretString::from(
+                    passphrase.clone(),
+                )))
+            }
+            EncryptionKey::KeyFile(path) => Self::identity_file(path)?
+                .to_recipients()
+                .map_err(CryptErr::EncryptionSetupFailed)?,
+            EncryptionKey::Recipients(recipients) => recipients
+                .iter()
+                .map(|recipient| {
+                    recipient
+                        .parse::<age::x25519::Recipient>()
+                        .map(|recipient| Box::new(recipient) as _)
+                        .map_err(|_| CryptErr::InvalidRecipient(recipient.clone()))
+                })
+                .collect::<Result<_, _>>()?,
+        };
+        Encryptor::with_recipients(recipients.iter().map(|recipient| recipient.as_ref() as _))
+            .map_err(CryptErr::EncryptionSetupFailed)
+    }
+
+    fn decrypt<R: Read>(&self, input: R) -> Result<StreamReader<R>, CryptErr> {
+        let decryptor = Decryptor::new(input).map_err(decrypt_error)?;
+        let identities: Vec<Box<dyn age::Identity>> = match self {
+            EncryptionKey::Passphrase(passphrase) => {
+                if !decryptor.is_scrypt() {
+                    return Err(CryptErr::WrongKey);
+                }
+                vec![Box::new(age::scrypt::Identity::new(SecretString::from(
+                    passphrase.clone(),
+                )))]
+            }
+            EncryptionKey::KeyFile(path) => Self::identity_file(path)?
+                .into_identities()
+                .map_err(CryptErr::DecryptionFailed)?,
+            EncryptionKey::Recipients(_) => return Err(CryptErr::NoIdentity),
+        };
+        decryptor
+            .decrypt(identities.iter().map(|identity| identity.as_ref()))
+            .map_err(decrypt_error)
+    }
+}
+
+fn decrypt_error(error: DecryptError) -> CryptErr {
+    match error {
+        DecryptError::DecryptionFailed | DecryptError::NoMatchingKeys => CryptErr::WrongKey,
+        error => CryptErr::DecryptionFailed(error),
+    }
+}
+
+/// Encrypts everything written to it in 64 KiB authenticated chunks. Wrap it
+/// in a compressor to encrypt compressed output; `finish` must be called
+/// (after finishing the compressor), otherwise the output reads as truncated.
+pub struct EncryptWriter<W: Write> {
+    inner: StreamWriter<W>,
+}
+
+impl<W: Write> EncryptWriter<W> {
+    pub fn new(output: W, key: &EncryptionKey) -> Result<Self, CryptErr> {
+        let inner = key
+            .encryptor()?
+            .wrap_output(output)
+            .map_err(CryptErr::EncryptionFailed)?;
+        Ok(EncryptWriter { inner })
+    }
+
+    /// Writes the final chunk and returns the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        self.inner.finish()
+    }
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts an age stream while it is read. Reads fail once a chunk does
+/// not authenticate or the input ends before the final chunk; see
+/// `CryptErr::from_read_error`.
+pub struct DecryptReader<R: Read> {
+    inner: StreamReader<R>,
+}
+
+impl<R: Read> DecryptReader<R> {
+    pub fn new(input: R, key: &EncryptionKey) -> Result<Self, CryptErr> {
+        Ok(DecryptReader {
+            inner: key.decrypt(input)?,
+        })
+    }
+}
+
+impl<R: Read> Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+/// Output that is encrypted when a key is given and written as is otherwise.
+pub(crate) enum EncryptionLayer<W: Write> {
+    Plain(W),
+    Encrypted(EncryptWriter<W>),
+}
+
+impl<W: Write> EncryptionLayer<W> {
+    pub(crate) fn new(output: W, key: Option<&EncryptionKey>) -> Result<Self, CryptErr> {
+        Ok(match key {
+            Some(key) => EncryptionLayer::Encrypted(EncryptWriter::new(output, key)?),
+            None => EncryptionLayer::Plain(output),
+        })
+    }
+
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            EncryptionLayer::Plain(output) => Ok(output),
+            EncryptionLayer::Encrypted(writer) => writer.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for EncryptionLayer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            EncryptionLayer::Plain(output) => output.write(buf),
+            EncryptionLayer::Encrypted(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            EncryptionLayer::Plain(output) => output.flush(),
+            EncryptionLayer::Encrypted(writer) => writer.flush(),
+        }
+    }
+}
+
+/// Encrypts `input_path` (typically an already compressed file) into
+/// `output_path`.
+pub fn encrypt_file<P: AsRef<Path>>(
+    input_path: P,
+    output_path: P,
+    key: &EncryptionKey,
+) -> Result<(), CryptErr> {
+    let (temp_dir, temp_file_path) =
+        create_temp_file("age").map_err(CryptErr::TempFileCreationFailed)?;
+    let temp_file = File::create(&temp_file_path).map_err(CryptErr::FileOpenFailed)?;
+    let mut writer = EncryptWriter::new(temp_file, key)?;
+
+    let mut input_file = File::open(input_path).map_err(CryptErr::FileOpenFailed)?;
+    io::copy(&mut input_file, &mut writer).map_err(CryptErr::EncryptionFailed)?;
+    writer.finish().map_err(CryptErr::EncryptionFailed)?;
+
+    make_permanent(temp_dir, &temp_file_path, output_path.as_ref())
+        .map_err(CryptErr::TempFileFinalizationFailed)?;
+    Ok(())
+}
+
+/// Decrypts `input_path` into `output_path`. Nothing is written unless the
+/// whole input authenticates.
+pub fn decrypt_file<P: AsRef<Path>>(
+    input_path: P,
+    output_path: P,
+    key: &EncryptionKey,
+) -> Result<(), CryptErr> {
+    let input_file = File::open(input_path).map_err(CryptErr::FileOpenFailed)?;
+    let mut reader = DecryptReader::new(BufReader::new(input_file), key)?;
+
+    let (temp_dir, temp_file_path) =
+        create_temp_file("out").map_err(CryptErr::TempFileCreationFailed)?;
+    let mut temp_file = File::create(&temp_file_path).map_err(CryptErr::FileOpenFailed)?;
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(CryptErr::from_read_error(e)),
+        };
+        temp_file
+            .write_all(&buffer[..read])
+            .map_err(CryptErr::WriteFailed)?;
+    }
+
+    make_permanent(temp_dir, &temp_file_path, output_path.as_ref())
+        .map_err(CryptErr::TempFileFinalizationFailed)?;
+    Ok(())
+}
@@ -0,0 +1,62 @@
+use crate::owner::Owner;
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+use tar::EntryType;
+
+/// An entry read from a tar or zip archive, in terms both formats share,
+/// for conversion and manifest checks.
+pub(crate) struct ArchiveEntry {
+    /// Relative path with `/` separators and no trailing slash.
+    pub(crate) name: String,
+    pub(crate) kind: EntryKind,
+    pub(crate) size: u64,
+    /// Permission bits.
+    pub(crate) mode: u32,
+    pub(crate) mtime: u64,
+    pub(crate) mtime_nanos: u32,
+    pub(crate) owner: Option<Owner>,
+    /// Extended attributes by name, without the `SCHILY.xattr.` prefix.
+    pub(crate) xattrs: Vec<(String, Vec<u8>)>,
+    pub(crate) comment: String,
+    /// Zip extra fields as header id and data.
+    pub(crate) extra_fields: Vec<(u16, Vec<u8>)>,
+    pub(crate) encrypted: bool,
+    /// Read from a sparse entry, with the holes filled in.
+    pub(crate) sparse: bool,
+}
+
+pub(crate) enum EntryKind {
+    File,
+    Directory,
+    Symlink(String),
+    /// A hard link to the entry with this name.
+    HardLink(String),
+    /// A character or block device or a FIFO.
+    Special {
+        entry_type: EntryType,
+        device: (u32, u32),
+    },
+}
+
+/// Whether the input is a zip archive, by name or by its first signature:
+/// a local header, an empty archive's end record or a split archive marker.
+pub(crate) fn is_zip(path: &Path) -> io::Result<bool> {
+    let named_zip = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().to_lowercase().ends_with(".zip"));
+    if named_zip {
+        return Ok(true);
+    }
+    let mut magic = Vec::with_capacity(4);
+    match File::open(path) {
+        Ok(file) => file.take(4).read_to_end(&mut magic)?,
+        // Possibly the volumes of a split tar archive.
+        Err(_) => return Ok(false),
+    };
+    Ok([b"PK\x03\x04", b"PK\x05\x06", b"PK\x07\x08"]
+        .iter()
+        .any(|signature| magic == *signature))
+}
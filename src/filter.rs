@@ -0,0 +1,170 @@
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::WalkBuilder;
+use std::{
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum FilterErr {
+    InvalidPattern(ignore::Error),
+    WalkFailed(ignore::Error),
+}
+
+impl fmt::Display for FilterErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterErr::InvalidPattern(e) => write!(f, "Invalid glob pattern: {}", e),
+            FilterErr::WalkFailed(e) => write!(f, "Failed to walk the input files: {}", e),
+        }
+    }
+}
+
+impl Error for FilterErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FilterErr::InvalidPattern(e) => Some(e),
+            FilterErr::WalkFailed(e) => Some(e),
+        }
+    }
+}
+
+/// Selects which files end up in an archive when directories are passed to
+/// the builders. Patterns use `.gitignore` syntax and are matched relative to
+/// each input directory, so `target/` only excludes directories named `target`.
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    /// When non-empty, only files matching at least one pattern are kept.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Honour `.gitignore` and `.ignore` files found while walking.
+    pub use_ignore_files: bool,
+    /// Maximum depth below each input; `Some(1)` keeps only a directory's direct children.
+    pub max_depth: Option<usize>,
+    /// Descend into directories reached through symbolic links.
+    pub follow_links: bool,
+}
+
+impl FileFilter {
+    pub fn include<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.include.push(pattern.into());
+        self
+    }
+
+    pub fn exclude<S: Into<String>>(mut self, pattern: S) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    pub fn use_ignore_files(mut self, enabled: bool) -> Self {
+        self.use_ignore_files = enabled;
+        self
+    }
+
+    pub fn max_depth(mut self, depth: Option<usize>) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    pub fn follow_links(mut self, enabled: bool) -> Self {
+        self.follow_links = enabled;
+        self
+    }
+
+    fn overrides(&self, root: &Path) -> Result<(Override, Override), FilterErr> {
+        let mut include = OverrideBuilder::new(root);
+        for pattern in &self.include {
+            include.add(pattern).map_err(FilterErr::InvalidPattern)?;
+        }
+        let mut exclude = OverrideBuilder::new(root);
+        for pattern in &self.exclude {
+            exclude
+                .add(&format!("!{}", pattern))
+                .map_err(FilterErr::InvalidPattern)?;
+        }
+        Ok((
+            include.build().map_err(FilterErr::InvalidPattern)?,
+            exclude.build().map_err(FilterErr::InvalidPattern)?,
+        ))
+    }
+}
+
+/// A file selected for archiving.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedFile {
+    /// Location on disk, as reached from the input path.
+    pub path: PathBuf,
+    /// Path relative to the input's parent directory, starting with the
+    /// input's own file name.
+    pub relative: PathBuf,
+}
+
+/// Expands `files` into the list of files the archive builders would store,
+/// without writing anything. Directories are walked recursively in file-name
+/// order and replaced by the files they contain.
+pub fn resolve_files<P: AsRef<Path>>(
+    files: &[P],
+    filter: &FileFilter,
+) -> Result<Vec<ResolvedFile>, FilterErr> {
+    let mut resolved = Vec::new();
+    for input in files {
+        let input = input.as_ref();
+        let input_name = input.file_name().map(PathBuf::from).unwrap_or_default();
+
+        if !input.is_dir() {
+            let root = input.parent().unwrap_or_else(|| Path::new(""));
+            let (include, exclude) = filter.overrides(root)?;
+            if is_selected(input, &include, &exclude) {
+                resolved.push(ResolvedFile {
+                    path: input.to_path_buf(),
+                    relative: input_name,
+                });
+            }
+            continue;
+        }
+
+        let (include, exclude) = filter.overrides(input)?;
+        let walker = WalkBuilder::new(input)
+            .standard_filters(false)
+            .git_ignore(filter.use_ignore_files)
+            .ignore(filter.use_ignore_files)
+            .parents(filter.use_ignore_files)
+            .require_git(false)
+            .max_depth(filter.max_depth)
+            .follow_links(filter.follow_links)
+            .overrides(exclude.clone())
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
+
+        for entry in walker {
+            let entry = entry.map_err(FilterErr::WalkFailed)?;
+            if entry
+                .file_type()
+                .is_some_and(|file_type| file_type.is_dir())
+            {
+                continue;
+            }
+            if !is_selected(entry.path(), &include, &exclude) {
+                continue;
+            }
+            let relative = entry
+                .path()
+                .strip_prefix(input)
+                .map(|rest| input_name.join(rest))
+                .unwrap_or_else(|_| entry.path().to_path_buf());
+            resolved.push(ResolvedFile {
+                path: entry.into_path(),
+                relative,
+            });
+        }
+    }
+    Ok(resolved)
+}
+
+fn is_selected(path: &Path, include: &Override, exclude: &Override) -> bool {
+    if exclude.matched(path, false).is_ignore() {
+        return false;
+    }
+    include.is_empty() || include.matched(path, false).is_whitelist()
+}
@@ -1,14 +1,16 @@
+use crate::crypt::{CryptErr, EncryptionKey, EncryptionLayer};
 use crate::utils::{create_temp_file, make_permanent, UtilsErr};
-use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
-use flate2::Compression;
+use flate2::bufread::{GzDecoder, MultiGzDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::{Compress, Compression, Crc, FlushCompress, GzBuilder, GzHeader, Status};
 use std::{
     error::Error,
-    fmt::{self, Display},
-    fs::{self, File},
-    io::{self, Write},
-    path::Path,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
 };
-use tempfile::{tempdir, TempDir};
 
 #[derive(Debug)]
 pub enum GzipErr {
@@ -16,7 +18,10 @@ pub enum GzipErr {
     InvalidOutputFileName,
     FileOpenFailed(io::Error),
     CompressionFailed(io::Error),
+    DecompressionFailed(io::Error),
+    InvalidHeaderField(&'static str),
     TempFileFinalizationFailed(UtilsErr),
+    EncryptionFailed(CryptErr),
 }
 
 impl fmt::Display for GzipErr {
@@ -30,9 +35,14 @@ impl fmt::Display for GzipErr {
                 write!(f, "Failed to open a file for gzip compression: {}", e)
             }
             GzipErr::CompressionFailed(e) => write!(f, "Gzip compression failed: {}", e),
+            GzipErr::DecompressionFailed(e) => write!(f, "Gzip decompression failed: {}", e),
+            GzipErr::InvalidHeaderField(field) => {
+                write!(f, "Invalid gzip header field: {}", field)
+            }
             GzipErr::TempFileFinalizationFailed(e) => {
                 write!(f, "Failed to finalize the temporary gzip file: {}", e)
             }
+            GzipErr::EncryptionFailed(e) => write!(f, "Encryption failed: {}", e),
         }
     }
 }
@@ -43,25 +53,418 @@ impl Error for GzipErr {
             GzipErr::TempFileCreationFailed(e) => Some(e),
             GzipErr::FileOpenFailed(e) => Some(e),
             GzipErr::CompressionFailed(e) => Some(e),
+            GzipErr::DecompressionFailed(e) => Some(e),
             GzipErr::TempFileFinalizationFailed(e) => Some(e),
+            GzipErr::EncryptionFailed(e) => Some(e),
             _ => None,
         }
     }
 }
 
+/// Optional gzip header fields (RFC 1952). Fields left as `None` are omitted
+/// from the header, which is what `create_gzip_archive` writes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GzipOptions {
+    pub filename: Option<Vec<u8>>,
+    pub mtime: Option<u32>,
+    pub comment: Option<Vec<u8>>,
+    pub operating_system: Option<u8>,
+    pub extra: Option<Vec<u8>>,
+    pub rsyncable: bool,
+}
+
+impl GzipOptions {
+    /// Header carrying the file name and modification time of `path`, like `gzip -N`.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, GzipErr> {
+        let path = path.as_ref();
+        let metadata = path.metadata().map_err(GzipErr::FileOpenFailed)?;
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .and_then(|duration| u32::try_from(duration.as_secs()).ok());
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned().into_bytes());
+
+        Ok(GzipOptions {
+            filename,
+            mtime,
+            ..GzipOptions::default()
+        })
+    }
+
+    pub fn filename<T: Into<Vec<u8>>>(mut self, filename: T) -> Self {
+        self.filename = Some(filename.into());
+        self
+    }
+
+    pub fn mtime(mut self, mtime: u32) -> Self {
+        self.mtime = Some(mtime);
+        self
+    }
+
+    pub fn comment<T: Into<Vec<u8>>>(mut self, comment: T) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    pub fn operating_system(mut self, os: u8) -> Self {
+        self.operating_system = Some(os);
+        self
+    }
+
+    pub fn extra<T: Into<Vec<u8>>>(mut self, extra: T) -> Self {
+        self.extra = Some(extra.into());
+        self
+    }
+
+    /// Resets the compressor at content-defined boundaries, like `gzip --rsyncable`.
+    pub fn rsyncable(mut self, rsyncable: bool) -> Self {
+        self.rsyncable = rsyncable;
+        self
+    }
+
+    fn validate(&self) -> Result<(), GzipErr> {
+        if matches!(&self.filename, Some(name) if name.contains(&0)) {
+            return Err(GzipErr::InvalidHeaderField("file name contains a NUL byte"));
+        }
+        if matches!(&self.comment, Some(comment) if comment.contains(&0)) {
+            return Err(GzipErr::InvalidHeaderField("comment contains a NUL byte"));
+        }
+        if matches!(&self.extra, Some(extra) if extra.len() > u16::MAX as usize) {
+            return Err(GzipErr::InvalidHeaderField(
+                "extra field exceeds 65535 bytes",
+            ));
+        }
+        Ok(())
+    }
+
+    fn builder(&self) -> Result<GzBuilder, GzipErr> {
+        self.validate()?;
+        let mut builder = GzBuilder::new();
+        if let Some(filename) = &self.filename {
+            builder = builder.filename(filename.clone());
+        }
+        if let Some(mtime) = self.mtime {
+            builder = builder.mtime(mtime);
+        }
+        if let Some(comment) = &self.comment {
+            builder = builder.comment(comment.clone());
+        }
+        if let Some(os) = self.operating_system {
+            builder = builder.operating_system(os);
+        }
+        if let Some(extra) = &self.extra {
+            builder = builder.extra(extra.clone());
+        }
+        Ok(builder)
+    }
+
+    /// Header bytes matching what `builder` writes, for the rsyncable encoder.
+    fn header(&self, level: Compression) -> Result<Vec<u8>, GzipErr> {
+        self.validate()?;
+        let mut flags = 0u8;
+        let mut header = Vec::with_capacity(10);
+        header.extend_from_slice(&[0x1f, 0x8b, 8, 0]);
+        header.extend_from_slice(&self.mtime.unwrap_or(0).to_le_bytes());
+        header.push(match level.level() {
+            9 => 2,
+            1 => 4,
+            _ => 0,
+        });
+        header.push(self.operating_system.unwrap_or(255));
+        if let Some(extra) = &self.extra {
+            flags |= FEXTRA;
+            header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+            header.extend_from_slice(extra);
+        }
+        if let Some(filename) = &self.filename {
+            flags |= FNAME;
+            header.extend_from_slice(filename);
+            header.push(0);
+        }
+        if let Some(comment) = &self.comment {
+            flags |= FCOMMENT;
+            header.extend_from_slice(comment);
+            header.push(0);
+        }
+        header[3] = flags;
+        Ok(header)
+    }
+}
+
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+
+/// Window length of the rolling byte sum used to find rsyncable boundaries.
+const RSYNC_WINDOW: usize = 4096;
+const OUTPUT_CHUNK: usize = 32 * 1024;
+
+/// Gzip encoder writing the header described by [`GzipOptions`]: flate2's
+/// `GzEncoder`, or the rsyncable encoder below when `rsyncable` is set.
+pub struct GzipWriter<W: Write> {
+    encoder: GzipEncoder<W>,
+}
+
+enum GzipEncoder<W: Write> {
+    Standard(GzEncoder<W>),
+    Rsyncable(RsyncableEncoder<W>),
+}
+
+impl<W: Write> GzipWriter<W> {
+    pub fn new(inner: W, options: &GzipOptions) -> Result<Self, GzipErr> {
+        Self::with_level(inner, options, Compression::best())
+    }
+
+    pub fn with_level(
+        inner: W,
+        options: &GzipOptions,
+        level: Compression,
+    ) -> Result<Self, GzipErr> {
+        let encoder = match options.rsyncable {
+            true => GzipEncoder::Rsyncable(RsyncableEncoder::new(inner, options, level)?),
+            false => GzipEncoder::Standard(options.builder()?.write(inner, level)),
+        };
+        Ok(GzipWriter { encoder })
+    }
+
+    pub fn get_ref(&self) -> &W {
+        match &self.encoder {
+            GzipEncoder::Standard(encoder) => encoder.get_ref(),
+            GzipEncoder::Rsyncable(encoder) => encoder.get_ref(),
+        }
+    }
+
+    /// Writes the final deflate block and the gzip trailer, returning the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self.encoder {
+            GzipEncoder::Standard(encoder) => encoder.finish(),
+            GzipEncoder::Rsyncable(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.encoder {
+            GzipEncoder::Standard(encoder) => encoder.write(buf),
+            GzipEncoder::Rsyncable(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.encoder {
+            GzipEncoder::Standard(encoder) => encoder.flush(),
+            GzipEncoder::Rsyncable(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Gzip encoder for rsyncable mode: the deflate state is fully flushed
+/// whenever the rolling sum of the last 4 KiB of input is a multiple of the
+/// window size, so an edit only changes the compressed bytes of the chunks
+/// around it.
+struct RsyncableEncoder<W: Write> {
+    inner: Option<W>,
+    compress: Compress,
+    crc: Crc,
+    output: Vec<u8>,
+    rsync: RollingSum,
+}
+
+impl<W: Write> RsyncableEncoder<W> {
+    fn new(mut inner: W, options: &GzipOptions, level: Compression) -> Result<Self, GzipErr> {
+        let header = options.header(level)?;
+        inner
+            .write_all(&header)
+            .map_err(GzipErr::CompressionFailed)?;
+        Ok(RsyncableEncoder {
+            inner: Some(inner),
+            compress: Compress::new(level, false),
+            crc: Crc::new(),
+            output: Vec::with_capacity(OUTPUT_CHUNK),
+            rsync: RollingSum::new(),
+        })
+    }
+
+    fn get_ref(&self) -> &W {
+        self.inner.as_ref().expect("gzip writer already finished")
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().expect("gzip writer already finished"))
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        if self.inner.is_none() {
+            return Ok(());
+        }
+        self.deflate(&[], FlushCompress::Finish)?;
+        let mut trailer = [0u8; 8];
+        trailer[..4].copy_from_slice(&self.crc.sum().to_le_bytes());
+        trailer[4..].copy_from_slice(&self.crc.amount().to_le_bytes());
+        let inner = self.inner.as_mut().expect("gzip writer already finished");
+        inner.write_all(&trailer)?;
+        inner.flush()
+    }
+
+    fn deflate(&mut self, mut input: &[u8], flush: FlushCompress) -> io::Result<()> {
+        let inner = self.inner.as_mut().expect("gzip writer already finished");
+        loop {
+            self.output.clear();
+            let before = self.compress.total_in();
+            let status = self
+                .compress
+                .compress_vec(input, &mut self.output, flush)
+                .map_err(io::Error::other)?;
+            input = &input[(self.compress.total_in() - before) as usize..];
+            inner.write_all(&self.output)?;
+
+            if status == Status::StreamEnd {
+                return Ok(());
+            }
+            let drained = self.output.len() < self.output.capacity();
+            if input.is_empty() && drained && !matches!(flush, FlushCompress::Finish) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for RsyncableEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.crc.update(buf);
+        let mut rest = buf;
+        while let Some(boundary) = self.rsync.next_boundary(rest) {
+            self.deflate(&rest[..boundary], FlushCompress::Full)?;
+            rest = &rest[boundary..];
+        }
+        self.deflate(rest, FlushCompress::None)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflate(&[], FlushCompress::Sync)?;
+        self.inner
+            .as_mut()
+            .expect("gzip writer already finished")
+            .flush()
+    }
+}
+
+impl<W: Write> Drop for RsyncableEncoder<W> {
+    fn drop(&mut self) {
+        let _ = self.try_finish();
+    }
+}
+
+struct RollingSum {
+    window: Box<[u8; RSYNC_WINDOW]>,
+    position: usize,
+    sum: u32,
+    since_boundary: usize,
+}
+
+impl RollingSum {
+    fn new() -> Self {
+        RollingSum {
+            window: Box::new([0; RSYNC_WINDOW]),
+            position: 0,
+            sum: 0,
+            since_boundary: 0,
+        }
+    }
+
+    /// Feeds bytes until a chunk boundary is found and returns its offset
+    /// (exclusive), or `None` once `data` is exhausted without one.
+    fn next_boundary(&mut self, data: &[u8]) -> Option<usize> {
+        for (index, &byte) in data.iter().enumerate() {
+            let slot = &mut self.window[self.position];
+            self.sum = self.sum - u32::from(*slot) + u32::from(byte);
+            *slot = byte;
+            self.position = (self.position + 1) % RSYNC_WINDOW;
+            self.since_boundary += 1;
+
+            if self.since_boundary >= RSYNC_WINDOW && self.sum.is_multiple_of(RSYNC_WINDOW as u32) {
+                self.since_boundary = 0;
+                return Some(index + 1);
+            }
+        }
+        None
+    }
+}
+
+/// Header fields read back from one gzip member.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GzipHeaderInfo {
+    pub filename: Option<Vec<u8>>,
+    pub mtime: u32,
+    pub comment: Option<Vec<u8>>,
+    pub operating_system: u8,
+    pub extra: Option<Vec<u8>>,
+}
+
+impl From<&GzHeader> for GzipHeaderInfo {
+    fn from(header: &GzHeader) -> Self {
+        GzipHeaderInfo {
+            filename: header.filename().map(<[u8]>::to_vec),
+            mtime: header.mtime(),
+            comment: header.comment().map(<[u8]>::to_vec),
+            operating_system: header.operating_system(),
+            extra: header.extra().map(<[u8]>::to_vec),
+        }
+    }
+}
+
 pub fn create_gzip_archive<P: AsRef<Path>>(file_path: P, output_path: P) -> Result<(), GzipErr> {
+    create_gzip_archive_with_options(file_path, output_path, &GzipOptions::default())
+}
+
+pub fn create_gzip_archive_with_options<P: AsRef<Path>>(
+    file_path: P,
+    output_path: P,
+    options: &GzipOptions,
+) -> Result<(), GzipErr> {
+    write_gzip_archive(file_path.as_ref(), output_path.as_ref(), options, None)
+}
+
+/// Like `create_gzip_archive`, with the compressed output encrypted with age.
+pub fn create_gzip_archive_encrypted<P: AsRef<Path>>(
+    file_path: P,
+    output_path: P,
+    key: &EncryptionKey,
+) -> Result<(), GzipErr> {
+    write_gzip_archive(
+        file_path.as_ref(),
+        output_path.as_ref(),
+        &GzipOptions::default(),
+        Some(key),
+    )
+}
+
+fn write_gzip_archive(
+    file_path: &Path,
+    output_path: &Path,
+    options: &GzipOptions,
+    key: Option<&EncryptionKey>,
+) -> Result<(), GzipErr> {
     let (temp_dir, temp_file_path) =
         create_temp_file("gz").map_err(GzipErr::TempFileCreationFailed)?;
     let temp_file = File::create(&temp_file_path).map_err(GzipErr::FileOpenFailed)?;
+    let output = EncryptionLayer::new(temp_file, key).map_err(GzipErr::EncryptionFailed)?;
 
-    let mut encoder = GzEncoder::new(temp_file, Compression::best());
-    let mut file = File::open(&file_path).map_err(GzipErr::FileOpenFailed)?;
+    let mut encoder = GzipWriter::new(output, options)?;
+    let mut file = File::open(file_path).map_err(GzipErr::FileOpenFailed)?;
     io::copy(&mut file, &mut encoder).map_err(GzipErr::CompressionFailed)?;
     encoder
         .finish()
-        .map_err(|e| GzipErr::CompressionFailed(io::Error::new(io::ErrorKind::Other, e)))?;
+        .and_then(EncryptionLayer::finish)
+        .map_err(GzipErr::CompressionFailed)?;
 
-    make_permanent(temp_dir, &temp_file_path, output_path.as_ref())
+    make_permanent(temp_dir, &temp_file_path, output_path)
         .map_err(GzipErr::TempFileFinalizationFailed)?;
     Ok(())
 }
@@ -79,9 +482,7 @@ pub fn encode_file_deflate<P: AsRef<Path>>(file_path: P, output_path: P) -> Resu
     let mut encoder = ZlibEncoder::new(temp_file, Compression::best());
     let mut file = File::open(&file_path).map_err(GzipErr::FileOpenFailed)?;
     io::copy(&mut file, &mut encoder).map_err(GzipErr::CompressionFailed)?;
-    encoder
-        .finish()
-        .map_err(|e| GzipErr::CompressionFailed(io::Error::new(io::ErrorKind::Other, e)))?;
+    encoder.finish().map_err(GzipErr::CompressionFailed)?;
 
     make_permanent(temp_dir, &temp_file_path, output_path.as_ref())
         .map_err(GzipErr::TempFileFinalizationFailed)?;
@@ -101,11 +502,182 @@ pub fn encode_file_zlib<P: AsRef<Path>>(file_path: P, output_path: P) -> Result<
     let mut encoder = ZlibEncoder::new(temp_file, Compression::best());
     let mut file = File::open(&file_path).map_err(GzipErr::FileOpenFailed)?;
     io::copy(&mut file, &mut encoder).map_err(GzipErr::CompressionFailed)?;
-    encoder
-        .finish()
-        .map_err(|e| GzipErr::CompressionFailed(io::Error::new(io::ErrorKind::Other, e)))?;
+    encoder.finish().map_err(GzipErr::CompressionFailed)?;
 
     make_permanent(temp_dir, &temp_file_path, output_path.as_ref())
         .map_err(GzipErr::TempFileFinalizationFailed)?;
     Ok(())
 }
+
+/// Location and header of one member inside a (possibly concatenated) gzip file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GzipMember {
+    pub offset: u64,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub header: GzipHeaderInfo,
+}
+
+/// Decompresses every member of a gzip file into `output_path` and returns
+/// the header of each member in order.
+pub fn decompress_gzip_archive<P: AsRef<Path>>(
+    file_path: P,
+    output_path: P,
+) -> Result<Vec<GzipHeaderInfo>, GzipErr> {
+    let (temp_dir, temp_file_path) =
+        create_temp_file("out").map_err(GzipErr::TempFileCreationFailed)?;
+    let mut temp_file = File::create(&temp_file_path).map_err(GzipErr::FileOpenFailed)?;
+    let file = File::open(&file_path).map_err(GzipErr::FileOpenFailed)?;
+
+    let members = decode_gzip_members(BufReader::new(file), &mut temp_file)
+        .map_err(GzipErr::DecompressionFailed)?;
+    temp_file.flush().map_err(GzipErr::DecompressionFailed)?;
+
+    make_permanent(temp_dir, &temp_file_path, output_path.as_ref())
+        .map_err(GzipErr::TempFileFinalizationFailed)?;
+    Ok(members.into_iter().map(|member| member.header).collect())
+}
+
+/// Reads the header of every member of a gzip file without keeping the data.
+pub fn read_gzip_headers<P: AsRef<Path>>(file_path: P) -> Result<Vec<GzipHeaderInfo>, GzipErr> {
+    Ok(list_gzip_members(file_path)?
+        .into_iter()
+        .map(|member| member.header)
+        .collect())
+}
+
+/// Opens a gzip file as a reader that transparently decodes all of its members.
+pub fn open_gzip_reader<P: AsRef<Path>>(
+    file_path: P,
+) -> Result<MultiGzDecoder<BufReader<File>>, GzipErr> {
+    let file = File::open(&file_path).map_err(GzipErr::FileOpenFailed)?;
+    Ok(MultiGzDecoder::new(BufReader::new(file)))
+}
+
+/// Enumerates the members of a gzip file with their byte offsets and sizes.
+pub fn list_gzip_members<P: AsRef<Path>>(file_path: P) -> Result<Vec<GzipMember>, GzipErr> {
+    let file = File::open(&file_path).map_err(GzipErr::FileOpenFailed)?;
+    decode_gzip_members(BufReader::new(file), &mut io::sink()).map_err(GzipErr::DecompressionFailed)
+}
+
+/// Compresses `file_path` into a new member and appends it to the gzip file at
+/// `archive_path`, leaving the existing members untouched.
+pub fn append_gzip_member<P: AsRef<Path>>(
+    archive_path: P,
+    file_path: P,
+    options: &GzipOptions,
+) -> Result<(), GzipErr> {
+    let (_temp_dir, temp_file_path) =
+        create_temp_file("gz").map_err(GzipErr::TempFileCreationFailed)?;
+    let temp_file = File::create(&temp_file_path).map_err(GzipErr::FileOpenFailed)?;
+
+    let mut encoder = GzipWriter::new(temp_file, options)?;
+    let mut file = File::open(&file_path).map_err(GzipErr::FileOpenFailed)?;
+    io::copy(&mut file, &mut encoder).map_err(GzipErr::CompressionFailed)?;
+    encoder.finish().map_err(GzipErr::CompressionFailed)?;
+
+    let mut member = File::open(&temp_file_path).map_err(GzipErr::FileOpenFailed)?;
+    let mut archive = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&archive_path)
+        .map_err(GzipErr::FileOpenFailed)?;
+    io::copy(&mut member, &mut archive).map_err(GzipErr::CompressionFailed)?;
+    archive.sync_all().map_err(GzipErr::CompressionFailed)?;
+    Ok(())
+}
+
+/// Splits a multi-member gzip file into one standalone `.gz` file per member
+/// inside `output_dir`, copying the compressed bytes as they are.
+pub fn split_gzip_members<P: AsRef<Path>>(
+    file_path: P,
+    output_dir: P,
+) -> Result<Vec<PathBuf>, GzipErr> {
+    let members = list_gzip_members(&file_path)?;
+    let stem = file_path
+        .as_ref()
+        .file_stem()
+        .ok_or(GzipErr::InvalidOutputFileName)?
+        .to_string_lossy()
+        .into_owned();
+
+    let mut file = File::open(&file_path).map_err(GzipErr::FileOpenFailed)?;
+    let mut outputs = Vec::with_capacity(members.len());
+    for (index, member) in members.iter().enumerate() {
+        let (temp_dir, temp_file_path) =
+            create_temp_file("gz").map_err(GzipErr::TempFileCreationFailed)?;
+        let mut temp_file = File::create(&temp_file_path).map_err(GzipErr::FileOpenFailed)?;
+        file.seek(SeekFrom::Start(member.offset))
+            .map_err(GzipErr::FileOpenFailed)?;
+        io::copy(
+            &mut (&mut file).take(member.compressed_size),
+            &mut temp_file,
+        )
+        .map_err(GzipErr::CompressionFailed)?;
+
+        let output_path = output_dir
+            .as_ref()
+            .join(format!("{}.{:03}.gz", stem, index + 1));
+        make_permanent(temp_dir, &temp_file_path, &output_path)
+            .map_err(GzipErr::TempFileFinalizationFailed)?;
+        outputs.push(output_path);
+    }
+    Ok(outputs)
+}
+
+fn decode_gzip_members<R: BufRead, W: Write>(
+    reader: R,
+    output: &mut W,
+) -> io::Result<Vec<GzipMember>> {
+    let mut reader = CountingReader::new(reader);
+    let mut members = Vec::new();
+    while !reader.fill_buf()?.is_empty() {
+        let offset = reader.position;
+        let mut decoder = GzDecoder::new(reader);
+        let uncompressed_size = io::copy(&mut decoder, output)?;
+        let header = decoder.header().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "missing gzip member header")
+        })?;
+        let header = GzipHeaderInfo::from(header);
+        reader = decoder.into_inner();
+        members.push(GzipMember {
+            offset,
+            compressed_size: reader.position - offset,
+            uncompressed_size,
+            header,
+        });
+    }
+    Ok(members)
+}
+
+/// Tracks how many bytes of the underlying reader have been consumed, so
+/// member boundaries are known exactly even through buffering.
+struct CountingReader<R> {
+    inner: R,
+    position: u64,
+}
+
+impl<R> CountingReader<R> {
+    fn new(inner: R) -> Self {
+        CountingReader { inner, position: 0 }
+    }
+}
+
+impl<R: BufRead> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position += amt as u64;
+        self.inner.consume(amt);
+    }
+}
@@ -1,32 +1,69 @@
 mod bzip;
+mod codec;
+mod convert;
+mod crypt;
+mod entry;
+mod filter;
 mod gzip;
+mod manifest;
+mod owner;
+mod split;
 mod tar;
+mod transform;
 mod utils;
 mod xz;
 mod zip;
 
-pub use crate::{bzip::*, gzip::*, tar::*, utils::*, xz::*, zip::*};
+pub use crate::{
+    bzip::*, codec::*, convert::*, crypt::*, filter::*, gzip::*, manifest::*, owner::*, split::*,
+    tar::*, transform::*, utils::*, xz::*, zip::*,
+};
 
 #[cfg(test)]
 mod tests {
-    use super::*;
     use crate::{
-        bzip::create_file_bzip2,
-        gzip::{create_gzip_archive, encode_file_deflate, encode_file_zlib},
+        bzip::{create_file_bzip2, create_file_bzip2_encrypted},
+        codec::{recompress, recompress_with_options, Codec, RecompressErr, RecompressOptions},
+        convert::{convert_archive, ConversionLoss, ConvertErr, ConvertOptions},
+        crypt::{decrypt_file, encrypt_file, CryptErr, EncryptionKey},
+        filter::{resolve_files, FileFilter},
+        gzip::{
+            append_gzip_member, create_gzip_archive, create_gzip_archive_encrypted,
+            create_gzip_archive_with_options, decompress_gzip_archive, encode_file_deflate,
+            encode_file_zlib, list_gzip_members, open_gzip_reader, read_gzip_headers,
+            split_gzip_members, GzipOptions,
+        },
+        manifest::{
+            sidecar_path, verify_manifest, ChecksumAlgorithm, ManifestErr, ManifestOptions,
+        },
+        owner::{Owner, OwnerMapping},
+        split::{split_file, volume_path, VolumeReader},
         tar::{
-            create_tar_archive, create_tar_bz2_archive, create_tar_gz_archive,
-            create_tar_xz_archive,
+            append_to_tar_archive, append_to_tar_archive_with_options, create_tar_archive,
+            create_tar_archive_with_options, create_tar_bz2_archive, create_tar_gz_archive,
+            create_tar_gz_archive_with_options, create_tar_xz_archive,
+            create_tar_xz_archive_with_options, extract_tar_archive, ExtractOptions, SymlinkPolicy,
+            TarAppendOptions, TarErr, TarFormat, TarOptions, XattrFilter,
+        },
+        transform::PathTransform,
+        utils::{prepare_directory_with_files, Reproducible, UtilsErr},
+        xz::{create_file_xz, create_file_xz_encrypted},
+        zip::{
+            append_to_zip_archive, create_zip_archive, create_zip_archive_with_options,
+            delete_zip_entries, extract_zip_archive, join_split_zip, read_zip_info,
+            read_zip_info_with_encoding, replace_zip_entry, write_zip_archive, MethodSelector,
+            ZipCipher, ZipEntryInfo, ZipEntryMetadata, ZipExtraField, ZipExtractOptions,
+            ZipInconsistency, ZipMethod, ZipNameEncoding, ZipOptions, ZipPassword, ZipStreamReader,
+            ZipStreamWriter, ZippingErr,
         },
-        utils::{prepare_directory_with_files, UtilsErr},
-        xz::create_file_xz,
-        zip::create_zip_archive,
     };
-    use clap::{arg, Command};
     use std::{
         error::Error,
-        fs::File,
-        io::Write,
+        fs::{self, File},
+        io::{Read, Seek, SeekFrom, Write},
         path::{Path, PathBuf},
+        process::Command,
+        time::{Duration, UNIX_EPOCH},
     };
 
     fn create_example_files() -> Result<(), Box<dyn Error>> {
@@ -42,6 +79,32 @@ mod tests {
         Ok(())
     }
 
+    fn sample_text(len: usize) -> Vec<u8> {
+        let words = [
+            "alpha", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel",
+        ];
+        let mut state = 0x2545_f491_u32;
+        let mut text = Vec::with_capacity(len + 8);
+        while text.len() < len {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            text.extend_from_slice(words[(state >> 16) as usize % words.len()].as_bytes());
+            text.push(if state.is_multiple_of(7) { b'\n' } else { b' ' });
+        }
+        text.truncate(len);
+        text
+    }
+
+    fn changed_bytes(before: &[u8], after: &[u8]) -> usize {
+        let prefix = before.iter().zip(after).take_while(|(a, b)| a == b).count();
+        let suffix = before[prefix..]
+            .iter()
+            .rev()
+            .zip(after[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        before.len().max(after.len()) - prefix - suffix
+    }
+
     #[test]
     fn tar_works() -> Result<(), Box<dyn Error>> {
         // Create example files if they don't exist
@@ -142,12 +205,27 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn zip_works() -> Result<(), Box<dyn Error>> {
+        create_example_files()?;
+
+        let input_files: Vec<PathBuf> = ["file1.txt", "file2.txt"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+
+        let output = PathBuf::from("output.zip");
+        create_zip_archive(&input_files, output)?;
+
+        Ok(())
+    }
+
     #[test]
     fn encode_file_gz() -> Result<(), Box<dyn Error>> {
         create_example_files()?;
         let file_to_compress_gzip = "file1.txt";
         let gzip_output_path = "file1.gz";
-        match gzip::create_gzip_archive(file_to_compress_gzip, gzip_output_path) {
+        match create_gzip_archive(file_to_compress_gzip, gzip_output_path) {
             Ok(()) => println!("GZIP archive created successfully."),
             Err(e) => eprintln!("GZIP archive creation failed: {}", e),
         }
@@ -160,7 +238,7 @@ mod tests {
         create_example_files()?;
         let file_to_compress_gzip = "file1.txt";
         let gzip_output_path = "file1.bz2";
-        match gzip::create_gzip_archive(file_to_compress_gzip, gzip_output_path) {
+        match create_file_bzip2(file_to_compress_gzip, gzip_output_path) {
             Ok(()) => println!("BZIP2 archive created successfully."),
             Err(e) => eprintln!("BZIP2 archive creation failed: {}", e),
         }
@@ -173,7 +251,7 @@ mod tests {
         create_example_files()?;
         let file_to_compress_gzip = "file1.txt";
         let gzip_output_path = "file1.xz";
-        match gzip::create_gzip_archive(file_to_compress_gzip, gzip_output_path) {
+        match create_file_xz(file_to_compress_gzip, gzip_output_path) {
             Ok(()) => println!("XZ2 archive created successfully."),
             Err(e) => eprintln!("XZ2 archive creation failed: {}", e),
         }
@@ -200,4 +278,2199 @@ mod tests {
         // Verification logic or just Ok(()) if nothing to verify
         Ok(())
     }
+
+    #[test]
+    fn gzip_header_fields_round_trip() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let input = temp_dir.path().join("notes.txt");
+        fs::write(&input, b"first member")?;
+        let second_input = temp_dir.path().join("more.txt");
+        fs::write(&second_input, b" and the second")?;
+
+        let options = GzipOptions::default()
+            .filename("notes.txt")
+            .mtime(1_700_000_000)
+            .comment("built by zippylib")
+            .operating_system(3)
+            .extra(b"AB\x02\x00hi".to_vec());
+        let first = temp_dir.path().join("first.gz");
+        create_gzip_archive_with_options(&input, &first, &options)?;
+        let second = temp_dir.path().join("second.gz");
+        create_gzip_archive(&second_input, &second)?;
+        // Without rsyncable mode the output is plain flate2 gzip.
+        let mut expected = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        expected.write_all(b" and the second")?;
+        assert_eq!(fs::read(&second)?, expected.finish()?);
+
+        let combined = temp_dir.path().join("combined.gz");
+        let mut bytes = fs::read(&first)?;
+        bytes.extend(fs::read(&second)?);
+        fs::write(&combined, bytes)?;
+
+        let headers = read_gzip_headers(&combined)?;
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers[0].filename.as_deref(), Some(&b"notes.txt"[..]));
+        assert_eq!(headers[0].mtime, 1_700_000_000);
+        assert_eq!(
+            headers[0].comment.as_deref(),
+            Some(&b"built by zippylib"[..])
+        );
+        assert_eq!(headers[0].operating_system, 3);
+        assert_eq!(headers[0].extra.as_deref(), Some(&b"AB\x02\x00hi"[..]));
+        assert_eq!(headers[1].filename, None);
+        assert_eq!(headers[1].mtime, 0);
+
+        let output = temp_dir.path().join("combined.txt");
+        decompress_gzip_archive(&combined, &output)?;
+        assert_eq!(fs::read(&output)?, b"first member and the second");
+
+        assert!(create_gzip_archive_with_options(
+            &input,
+            &first,
+            &GzipOptions::default().filename(b"bad\0name".to_vec())
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn gzip_members_append_and_split() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let log = temp_dir.path().join("app.log");
+        let archive = temp_dir.path().join("app.log.gz");
+
+        fs::write(&log, b"monday\n")?;
+        create_gzip_archive(&log, &archive)?;
+        let first_member = fs::read(&archive)?;
+        fs::write(&log, b"tuesday\n")?;
+        append_gzip_member(
+            &archive,
+            &log,
+            &GzipOptions::default().filename("tuesday.log"),
+        )?;
+        fs::write(&log, b"wednesday\n")?;
+        append_gzip_member(&archive, &log, &GzipOptions::default())?;
+        assert!(fs::read(&archive)?.starts_with(&first_member));
+
+        let mut content = String::new();
+        open_gzip_reader(&archive)?.read_to_string(&mut content)?;
+        assert_eq!(content, "monday\ntuesday\nwednesday\n");
+
+        let members = list_gzip_members(&archive)?;
+        assert_eq!(members.len(), 3);
+        assert_eq!(members[0].offset, 0);
+        assert_eq!(members[0].compressed_size, first_member.len() as u64);
+        assert_eq!(members[1].offset, members[0].compressed_size);
+        assert_eq!(members[1].uncompressed_size, 8);
+        assert_eq!(
+            members[1].header.filename.as_deref(),
+            Some(&b"tuesday.log"[..])
+        );
+        let total: u64 = members.iter().map(|member| member.compressed_size).sum();
+        assert_eq!(total, fs::metadata(&archive)?.len());
+
+        let split_dir = temp_dir.path().join("split");
+        fs::create_dir(&split_dir)?;
+        let parts = split_gzip_members(&archive, &split_dir)?;
+        assert_eq!(parts.len(), 3);
+        assert_eq!(fs::read(&parts[0])?, first_member);
+        let restored = temp_dir.path().join("wednesday.log");
+        decompress_gzip_archive(&parts[2], &restored)?;
+        assert_eq!(fs::read(&restored)?, b"wednesday\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn tar_entries_of_absolute_paths_drop_the_root() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let input = temp_dir.path().join("absolute.txt");
+        fs::write(&input, b"stored without the root")?;
+        let archive_path = temp_dir.path().join("absolute.tar");
+        create_tar_archive(&[&input], &archive_path)?;
+
+        let mut archive = ::tar::Archive::new(File::open(&archive_path)?);
+        let entry = archive.entries()?.next().ok_or("empty archive")??;
+        let relative: PathBuf = input.components().skip(1).collect();
+        assert_eq!(entry.path()?, relative);
+
+        Ok(())
+    }
+
+    #[test]
+    fn same_named_files_keep_their_directories_or_clash() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let readmes =
+            [temp_dir.path().join("a"), temp_dir.path().join("b")].map(|dir| dir.join("readme"));
+        for readme in &readmes {
+            fs::create_dir_all(readme.parent().ok_or("no parent")?)?;
+            fs::write(readme, b"read me")?;
+        }
+
+        // Tar keeps the directories the files were given with.
+        let tar_path = temp_dir.path().join("readmes.tar");
+        create_tar_archive(&[&readmes[0], &readmes[1]], &tar_path)?;
+        let mut archive = ::tar::Archive::new(File::open(&tar_path)?);
+        let names = archive
+            .entries()?
+            .map(|entry| Ok(entry?.path()?.into_owned()))
+            .collect::<Result<Vec<_>, std::io::Error>>()?;
+        let expected: Vec<PathBuf> = readmes
+            .iter()
+            .map(|readme| readme.components().skip(1).collect())
+            .collect();
+        assert_eq!(names, expected);
+        assert!(matches!(
+            create_tar_archive(&[&readmes[0], &readmes[0]], &tar_path),
+            Err(TarErr::DuplicateEntry(name)) if name == expected[0]
+        ));
+
+        // Zip names files by their file name, so both would be `readme`.
+        let zip_path = temp_dir.path().join("readmes.zip");
+        assert!(matches!(
+            create_zip_archive(&[&readmes[0], &readmes[1]], &zip_path),
+            Err(ZippingErr::DuplicateEntry(name)) if name == "readme"
+        ));
+        assert!(!zip_path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn rsyncable_tar_gz_localizes_changes() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let input = temp_dir.path().join("data.txt");
+        let mut content = sample_text(512 * 1024);
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+
+        let build = |content: &[u8], rsyncable: bool| -> Result<Vec<u8>, Box<dyn Error>> {
+            fs::write(&input, content)?;
+            File::options()
+                .write(true)
+                .open(&input)?
+                .set_modified(mtime)?;
+            let output = temp_dir.path().join("data.tar.gz");
+            let options = TarOptions {
+                gzip: GzipOptions::default().rsyncable(rsyncable),
+                ..TarOptions::default()
+            };
+            create_tar_gz_archive_with_options(&[&input], &output, &options)?;
+            Ok(fs::read(&output)?)
+        };
+
+        let plain_before = build(&content, false)?;
+        let rsync_before = build(&content, true)?;
+        content[300 * 1024] ^= 0x20;
+        let plain_after = build(&content, false)?;
+        let rsync_after = build(&content, true)?;
+
+        // The 8-byte trailer carries the CRC of the whole input, so it always differs.
+        let body = |bytes: &[u8]| bytes[..bytes.len() - 8].to_vec();
+        let plain_changed = changed_bytes(&body(&plain_before), &body(&plain_after));
+        let rsync_changed = changed_bytes(&body(&rsync_before), &body(&rsync_after));
+        assert!(
+            rsync_changed < 16 * 1024,
+            "rsyncable changed {} bytes",
+            rsync_changed
+        );
+        assert!(plain_changed > 4 * rsync_changed);
+
+        let unpacked = temp_dir.path().join("unpacked.tar");
+        decompress_gzip_archive(temp_dir.path().join("data.tar.gz"), unpacked.clone())?;
+        let mut archive = ::tar::Archive::new(File::open(&unpacked)?);
+        let mut entry = archive.entries()?.next().ok_or("empty archive")??;
+        let mut restored = Vec::new();
+        entry.read_to_end(&mut restored)?;
+        assert_eq!(restored, content);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reproducible_archives_are_identical() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let a = temp_dir.path().join("a.txt");
+        let b = temp_dir.path().join("b.sh");
+        fs::write(&a, b"alpha")?;
+        fs::write(&b, b"#!/bin/sh\necho bravo\n")?;
+        let reproducible = Reproducible::new(1_500_000_000);
+
+        let build = |files: &[&PathBuf], mtime: u64, run: &str| -> Result<_, Box<dyn Error>> {
+            for file in files {
+                File::options()
+                    .write(true)
+                    .open(file)?
+                    .set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+            }
+            let tar_options = TarOptions {
+                gzip: GzipOptions::default().mtime(1_600_000_000),
+                reproducible: Some(reproducible),
+                ..TarOptions::default()
+            };
+            let zip_options = ZipOptions {
+                reproducible: Some(reproducible),
+                ..ZipOptions::default()
+            };
+            let out = |ext: &str| temp_dir.path().join(format!("{}.{}", run, ext));
+            create_tar_archive_with_options(files, &out("tar"), &tar_options)?;
+            create_tar_gz_archive_with_options(files, &out("tar.gz"), &tar_options)?;
+            create_tar_xz_archive_with_options(files, &out("tar.xz"), &tar_options)?;
+            create_zip_archive_with_options(files, &out("zip"), &zip_options)?;
+            Ok(["tar", "tar.gz", "tar.xz", "zip"]
+                .iter()
+                .map(|ext| fs::read(out(ext)))
+                .collect::<Result<Vec<_>, _>>()?)
+        };
+
+        let first = build(&[&a, &b], 1_700_000_000, "first")?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&a, fs::Permissions::from_mode(0o600))?;
+        }
+        let second = build(&[&b, &a], 1_800_000_000, "second")?;
+        assert_eq!(first, second);
+
+        let mut archive = ::tar::Archive::new(&first[0][..]);
+        let headers = archive
+            .entries()?
+            .map(|entry| {
+                let header = entry?.header().clone();
+                Ok((header.path()?.into_owned(), header.mtime()?, header.uid()?))
+            })
+            .collect::<Result<Vec<_>, std::io::Error>>()?;
+        assert!(headers[0].0.ends_with("a.txt"));
+        assert!(headers[1].0.ends_with("b.sh"));
+        assert!(headers
+            .iter()
+            .all(|(_, mtime, uid)| *mtime == 1_500_000_000 && *uid == 0));
+        assert_eq!(
+            read_gzip_headers(temp_dir.path().join("first.tar.gz"))?[0].mtime,
+            1_500_000_000
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn filters_select_files_from_directories() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let project = temp_dir.path().join("project");
+        for dir in ["src/nested/deeper", "target/debug", ".git"] {
+            fs::create_dir_all(project.join(dir))?;
+        }
+        for file in [
+            "Cargo.toml",
+            "notes.tmp",
+            "secret.txt",
+            "src/lib.rs",
+            "src/nested/mod.rs",
+            "src/nested/deeper/deep.rs",
+            "target/debug/app",
+            ".git/HEAD",
+        ] {
+            fs::write(project.join(file), file)?;
+        }
+        fs::write(project.join(".gitignore"), "secret.txt\n")?;
+
+        let filter = FileFilter::default()
+            .exclude("target/")
+            .exclude("*.tmp")
+            .exclude(".git")
+            .use_ignore_files(true);
+        let names = |filter: &FileFilter| -> Result<Vec<String>, Box<dyn Error>> {
+            Ok(resolve_files(&[&project], filter)?
+                .into_iter()
+                .map(|file| file.relative.to_string_lossy().replace('\\', "/"))
+                .collect())
+        };
+        assert_eq!(
+            names(&filter)?,
+            [
+                "project/.gitignore",
+                "project/Cargo.toml",
+                "project/src/lib.rs",
+                "project/src/nested/deeper/deep.rs",
+                "project/src/nested/mod.rs",
+            ]
+        );
+        assert_eq!(
+            names(&filter.clone().include("*.rs").max_depth(Some(3)))?,
+            ["project/src/lib.rs", "project/src/nested/mod.rs"]
+        );
+
+        let zip_path = temp_dir.path().join("project.zip");
+        let zip_options = ZipOptions {
+            filter: filter.clone(),
+            ..ZipOptions::default()
+        };
+        create_zip_archive_with_options(&[&project], &zip_path, &zip_options)?;
+        let zip = ::zip::ZipArchive::new(File::open(&zip_path)?)?;
+        let mut zip_names: Vec<_> = zip.file_names().collect();
+        zip_names.sort();
+        assert_eq!(zip_names, names(&filter)?);
+
+        let tar_path = temp_dir.path().join("project.tar");
+        let tar_options = TarOptions {
+            filter: filter.clone(),
+            ..TarOptions::default()
+        };
+        create_tar_archive_with_options(&[&project], &tar_path, &tar_options)?;
+        let mut archive = ::tar::Archive::new(File::open(&tar_path)?);
+        let mut tar_names = archive
+            .entries()?
+            .map(|entry| Ok(entry?.path()?.to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>, std::io::Error>>()?;
+        tar_names.sort();
+        assert_eq!(tar_names, names(&filter)?);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tar_symlink_and_hardlink_policies() -> Result<(), Box<dyn Error>> {
+        use std::os::unix::fs::{symlink, MetadataExt};
+
+        let temp_dir = tempfile::tempdir()?;
+        let tree = temp_dir.path().join("tree");
+        fs::create_dir(&tree)?;
+        fs::write(tree.join("a.txt"), b"shared content")?;
+        fs::hard_link(tree.join("a.txt"), tree.join("b.txt"))?;
+        symlink("a.txt", tree.join("link.txt"))?;
+
+        let archive_path = temp_dir.path().join("tree.tar.gz");
+        let options = TarOptions {
+            symlinks: SymlinkPolicy::Preserve,
+            detect_hardlinks: true,
+            ..TarOptions::default()
+        };
+        create_tar_gz_archive_with_options(&[&tree], &archive_path, &options)?;
+
+        let rejecting = TarOptions {
+            symlinks: SymlinkPolicy::Reject,
+            ..TarOptions::default()
+        };
+        assert!(matches!(
+            create_tar_archive_with_options(&[&tree], &temp_dir.path().join("x.tar"), &rejecting),
+            Err(TarErr::SymlinkRejected(_))
+        ));
+
+        let preserved = temp_dir.path().join("preserved");
+        let extract = ExtractOptions {
+            symlinks: SymlinkPolicy::Preserve,
+            ..ExtractOptions::default()
+        };
+        extract_tar_archive(&archive_path, &preserved, &extract)?;
+        let root = preserved.join("tree");
+        assert_eq!(fs::read_link(root.join("link.txt"))?, Path::new("a.txt"));
+        assert_eq!(fs::read(root.join("link.txt"))?, b"shared content");
+        assert_eq!(
+            fs::metadata(root.join("a.txt"))?.ino(),
+            fs::metadata(root.join("b.txt"))?.ino()
+        );
+
+        let followed = temp_dir.path().join("followed");
+        extract_tar_archive(&archive_path, &followed, &ExtractOptions::default())?;
+        let root = followed.join("tree");
+        assert!(!fs::symlink_metadata(root.join("link.txt"))?
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read(root.join("link.txt"))?, b"shared content");
+
+        for (entry_type, target) in [
+            (::tar::EntryType::Symlink, "../../outside"),
+            (::tar::EntryType::Symlink, "/etc/passwd"),
+            (::tar::EntryType::Link, "../outside"),
+        ] {
+            let evil_path = temp_dir.path().join("evil.tar");
+            let mut builder = ::tar::Builder::new(File::create(&evil_path)?);
+            let mut header = ::tar::Header::new_gnu();
+            header.set_entry_type(entry_type);
+            header.set_size(0);
+            header.set_mode(0o777);
+            builder.append_link(&mut header, "dir/escape", target)?;
+            builder.finish()?;
+            drop(builder);
+
+            let result = extract_tar_archive(&evil_path, &temp_dir.path().join("evil"), &extract);
+            assert!(matches!(result, Err(TarErr::UnsafeLinkTarget { .. })));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tar_links_resolve_against_extracted_links() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let link = |builder: &mut ::tar::Builder<File>, kind, path: &str, target: &str| {
+            let mut header = ::tar::Header::new_gnu();
+            header.set_entry_type(kind);
+            header.set_size(0);
+            header.set_mode(0o777);
+            builder.append_link(&mut header, path, target)
+        };
+        let file = |builder: &mut ::tar::Builder<File>, path: &str, data: &[u8]| {
+            let mut header = ::tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, path, data)
+        };
+
+        // `a/up` is a legitimate link to the root, but `a/up/esc` climbs out of it.
+        let chain_path = temp_dir.path().join("chain.tar");
+        let mut builder = ::tar::Builder::new(File::create(&chain_path)?);
+        file(&mut builder, "a/keep.txt", b"kept")?;
+        link(&mut builder, ::tar::EntryType::Symlink, "a/up", "..")?;
+        link(
+            &mut builder,
+            ::tar::EntryType::Symlink,
+            "a/up/esc",
+            "../secret",
+        )?;
+        link(&mut builder, ::tar::EntryType::Link, "h", "a/up/esc")?;
+        builder.finish()?;
+        drop(builder);
+
+        let destination = temp_dir.path().join("chain");
+        let preserve = ExtractOptions {
+            symlinks: SymlinkPolicy::Preserve,
+            ..ExtractOptions::default()
+        };
+        let result = extract_tar_archive(&chain_path, &destination, &preserve);
+        assert!(matches!(
+            result,
+            Err(TarErr::UnsafeEntryPath(_) | TarErr::UnsafeLinkTarget { .. })
+        ));
+        assert!(fs::symlink_metadata(destination.join("esc")).is_err());
+        assert!(fs::symlink_metadata(destination.join("h")).is_err());
+
+        // A followed link may come before its target and may point at a directory.
+        let follow_path = temp_dir.path().join("follow.tar");
+        let mut builder = ::tar::Builder::new(File::create(&follow_path)?);
+        link(&mut builder, ::tar::EntryType::Symlink, "alias", "data")?;
+        file(&mut builder, "data/nested/file.txt", b"followed")?;
+        builder.finish()?;
+        drop(builder);
+
+        let destination = temp_dir.path().join("follow");
+        extract_tar_archive(&follow_path, &destination, &ExtractOptions::default())?;
+        let copied = destination.join("alias");
+        assert!(fs::symlink_metadata(&copied)?.is_dir());
+        assert_eq!(fs::read(copied.join("nested/file.txt"))?, b"followed");
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn zip_links_resolve_against_extracted_links() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let options = zip::write::SimpleFileOptions::default();
+        let archive = |name: &str, entries: &[(&str, Option<&str>)]| -> zip::result::ZipResult<_> {
+            let path = temp_dir.path().join(name);
+            let mut zip = zip::ZipWriter::new(File::create(&path)?);
+            for (name, link) in entries {
+                match link {
+                    Some(target) => zip.add_symlink(*name, *target, options)?,
+                    None => {
+                        zip.start_file(*name, options)?;
+                        zip.write_all(b"data")?;
+                    }
+                }
+            }
+            zip.finish()?;
+            Ok(path)
+        };
+
+        // `a/up` stays inside, but a link below it or a link resolved
+        // through it later would not.
+        let chain = archive(
+            "chain.zip",
+            &[
+                ("a/keep.txt", None),
+                ("a/up", Some("..")),
+                ("a/up/esc", Some("../secret")),
+            ],
+        )?;
+        let late = archive(
+            "late.zip",
+            &[
+                ("l", Some("c/../x")),
+                ("a/keep.txt", None),
+                ("a/up", Some("..")),
+                ("c", Some("a/up")),
+            ],
+        )?;
+        for (archive, name) in [(chain, "chain"), (late, "late")] {
+            let destination = temp_dir.path().join(name);
+            let result = extract_zip_archive(&archive, &destination, &ZipExtractOptions::default());
+            assert!(matches!(result, Err(ZippingErr::UnsafeEntryPath(_))));
+            assert!(fs::symlink_metadata(temp_dir.path().join("secret")).is_err());
+            assert!(fs::symlink_metadata(temp_dir.path().join("x")).is_err());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn tar_xattrs_round_trip() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let input = temp_dir.path().join("labelled.txt");
+        fs::write(&input, b"with attributes")?;
+        if xattr::set(&input, "user.origin", b"build-42").is_err() {
+            // The filesystem backing the temp dir does not support user xattrs.
+            return Ok(());
+        }
+        xattr::set(&input, "user.skip.me", b"ignored")?;
+
+        let archive_path = temp_dir.path().join("labelled.tar");
+        let options = TarOptions {
+            xattrs: Some(XattrFilter::default().include("user.").exclude("user.skip")),
+            ..TarOptions::default()
+        };
+        create_tar_archive_with_options(&[&input], &archive_path, &options)?;
+
+        let mut archive = ::tar::Archive::new(File::open(&archive_path)?);
+        let mut entry = archive.entries()?.next().ok_or("empty archive")??;
+        let keys = entry
+            .pax_extensions()?
+            .ok_or("missing pax header")?
+            .map(|extension| Ok(extension?.key().unwrap_or_default().to_string()))
+            .collect::<Result<Vec<_>, std::io::Error>>()?;
+        assert_eq!(keys, ["SCHILY.xattr.user.origin"]);
+
+        let destination = temp_dir.path().join("out");
+        let extract = ExtractOptions {
+            xattrs: Some(XattrFilter::all()),
+            ..ExtractOptions::default()
+        };
+        extract_tar_archive(&archive_path, &destination, &extract)?;
+        let restored = destination.join(input.strip_prefix("/")?);
+        assert_eq!(
+            xattr::get(&restored, "user.origin")?,
+            Some(b"build-42".to_vec())
+        );
+        assert_eq!(xattr::get(&restored, "user.skip.me")?, None);
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn sparse_files_round_trip() -> Result<(), Box<dyn Error>> {
+        use std::os::unix::fs::MetadataExt;
+
+        const SIZE: u64 = 64 * 1024 * 1024;
+        let temp_dir = tempfile::tempdir()?;
+        let image = temp_dir.path().join("disk.img");
+        let mut file = File::create(&image)?;
+        file.set_len(SIZE)?;
+        file.write_all(b"boot sector")?;
+        file.seek(SeekFrom::Start(SIZE / 2))?;
+        file.write_all(b"middle of the disk")?;
+        drop(file);
+        let holes_reported = fs::metadata(&image)?.blocks() * 512 < SIZE;
+
+        let archive_path = temp_dir.path().join("disk.tar");
+        let options = TarOptions {
+            sparse: true,
+            manifest: Some(ManifestOptions::default()),
+            ..TarOptions::default()
+        };
+        create_tar_archive_with_options(&[&image], &archive_path, &options)?;
+        if holes_reported {
+            assert!(fs::metadata(&archive_path)?.len() < 64 * 1024);
+        }
+        // The manifest hashes the file with its holes.
+        let report = verify_manifest(&archive_path)?;
+        assert_eq!(report.verified, 1);
+        assert!(report.is_valid());
+
+        let destination = temp_dir.path().join("out");
+        extract_tar_archive(&archive_path, &destination, &ExtractOptions::default())?;
+        let restored = destination.join(image.strip_prefix("/")?);
+        let metadata = fs::metadata(&restored)?;
+        assert_eq!(metadata.len(), SIZE);
+        if holes_reported {
+            assert!(metadata.blocks() * 512 < SIZE / 4);
+        }
+
+        let mut restored = File::open(&restored)?;
+        let mut start = [0u8; 11];
+        restored.read_exact(&mut start)?;
+        assert_eq!(&start, b"boot sector");
+        restored.seek(SeekFrom::Start(SIZE / 2 - 4))?;
+        let mut middle = [0u8; 22];
+        restored.read_exact(&mut middle)?;
+        assert_eq!(&middle, b"\0\0\0\0middle of the disk");
+
+        // A symlink already at the target is replaced, not written through.
+        let victim = temp_dir.path().join("victim.txt");
+        fs::write(&victim, b"keep me")?;
+        let linked = temp_dir.path().join("linked");
+        let planted = linked.join(image.strip_prefix("/")?);
+        fs::create_dir_all(planted.parent().ok_or("no parent")?)?;
+        std::os::unix::fs::symlink(&victim, &planted)?;
+        extract_tar_archive(&archive_path, &linked, &ExtractOptions::default())?;
+        assert_eq!(fs::read(&victim)?, b"keep me");
+        let metadata = fs::symlink_metadata(&planted)?;
+        assert!(metadata.is_file());
+        assert_eq!(metadata.len(), SIZE);
+
+        Ok(())
+    }
+
+    #[test]
+    fn tar_formats_handle_long_paths_and_precise_mtimes() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let top = temp_dir.path().join("d".repeat(120));
+        let deep = top.join("e".repeat(120));
+        fs::create_dir_all(&deep)?;
+        let file_path = deep.join("precise.txt");
+        let mut file = File::create(&file_path)?;
+        file.write_all(b"nanoseconds")?;
+        file.set_modified(UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789))?;
+        drop(file);
+
+        let build = |format: TarFormat, name: &str| -> Result<PathBuf, TarErr> {
+            let archive_path = temp_dir.path().join(name);
+            let options = TarOptions {
+                format,
+                ..TarOptions::default()
+            };
+            create_tar_archive_with_options(&[&top], &archive_path, &options)?;
+            Ok(archive_path)
+        };
+
+        assert!(matches!(
+            build(TarFormat::Ustar, "ustar.tar"),
+            Err(TarErr::UnrepresentableEntry { .. })
+        ));
+
+        let gnu = build(TarFormat::Gnu, "gnu.tar")?;
+        let mut archive = tar::Archive::new(File::open(&gnu)?);
+        let mut entries = archive.entries()?.raw(true);
+        let long_name = entries.next().ok_or("empty archive")??;
+        assert!(long_name.header().entry_type().is_gnu_longname());
+
+        let pax = build(TarFormat::Pax, "pax.tar")?;
+        let mut archive = tar::Archive::new(File::open(&pax)?);
+        let mut entries = archive.entries()?;
+        let mut entry = entries.next().ok_or("empty archive")??;
+        assert!(entry.header().as_ustar().is_some());
+        assert_eq!(
+            entry.path()?,
+            Path::new(&"d".repeat(120))
+                .join("e".repeat(120))
+                .join("precise.txt")
+        );
+        let records = entry.pax_extensions()?.ok_or("missing PAX header")?;
+        let mtime = records
+            .filter_map(Result::ok)
+            .find(|record| record.key() == Ok("mtime"))
+            .ok_or("missing mtime record")?;
+        assert_eq!(mtime.value_bytes(), b"1700000000.123456789");
+        let mut contents = String::new();
+        entry.read_to_string(&mut contents)?;
+        assert_eq!(contents, "nanoseconds");
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tar_owner_mapping() -> Result<(), Box<dyn Error>> {
+        use std::os::unix::fs::MetadataExt;
+
+        let temp_dir = tempfile::tempdir()?;
+        let input = temp_dir.path().join("artifact");
+        fs::create_dir(&input)?;
+        fs::write(input.join("app.bin"), b"binary")?;
+
+        let owners = |archive_path: &Path| -> Result<Vec<Owner>, Box<dyn Error>> {
+            let mut archive = tar::Archive::new(File::open(archive_path)?);
+            let mut owners = Vec::new();
+            for entry in archive.entries()? {
+                let entry = entry?;
+                let header = entry.header();
+                let name = |name: Option<&str>| name.filter(|n| !n.is_empty()).map(String::from);
+                owners.push(Owner {
+                    uid: header.uid()?,
+                    gid: header.gid()?,
+                    uname: name(header.username()?),
+                    gname: name(header.groupname()?),
+                });
+            }
+            Ok(owners)
+        };
+
+        let rooted = temp_dir.path().join("rooted.tar");
+        let options = TarOptions {
+            owner: OwnerMapping::Fixed(Owner::root()),
+            ..TarOptions::default()
+        };
+        create_tar_archive_with_options(&[&input], &rooted, &options)?;
+        assert_eq!(owners(&rooted)?, vec![Owner::root()]);
+
+        let (uid, gid) = {
+            let metadata = fs::metadata(input.join("app.bin"))?;
+            (u64::from(metadata.uid()), u64::from(metadata.gid()))
+        };
+        let shifted = temp_dir.path().join("shifted.tar");
+        let options = TarOptions {
+            owner: OwnerMapping::map(|owner| Owner::new(owner.uid + 100_000, owner.gid + 100_000)),
+            numeric_owner: true,
+            ..TarOptions::default()
+        };
+        create_tar_archive_with_options(&[&input], &shifted, &options)?;
+        assert_eq!(
+            owners(&shifted)?,
+            vec![Owner::new(uid + 100_000, gid + 100_000)]
+        );
+
+        if unsafe { libc::geteuid() } == 0 {
+            let destination = temp_dir.path().join("out");
+            let extract = ExtractOptions {
+                restore_owner: true,
+                owner: OwnerMapping::map(|owner| Owner::new(owner.uid - 100_000 + 7, owner.gid)),
+                numeric_owner: true,
+                ..ExtractOptions::default()
+            };
+            extract_tar_archive(&shifted, &destination, &extract)?;
+            let restored = destination.join("artifact").join("app.bin");
+            let metadata = fs::metadata(restored)?;
+            assert_eq!(u64::from(metadata.uid()), uid + 7);
+            assert_eq!(u64::from(metadata.gid()), gid + 100_000);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn path_transforms_rewrite_entry_names() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let out = temp_dir.path().join("build").join("out");
+        fs::create_dir_all(out.join("bin"))?;
+        fs::write(out.join("bin").join("app"), b"app")?;
+        fs::write(out.join("README"), b"readme")?;
+
+        let transform = PathTransform::default()
+            .replace("^out/", "")?
+            .add_prefix("myapp-1.2.3");
+        let tar_path = temp_dir.path().join("release.tar");
+        let options = TarOptions {
+            transform,
+            ..TarOptions::default()
+        };
+        create_tar_archive_with_options(&[&out], &tar_path, &options)?;
+        let mut names = Vec::new();
+        for entry in tar::Archive::new(File::open(&tar_path)?).entries()? {
+            names.push(entry?.path()?.into_owned());
+        }
+        assert_eq!(
+            names,
+            vec![
+                PathBuf::from("myapp-1.2.3/README"),
+                PathBuf::from("myapp-1.2.3/bin/app")
+            ]
+        );
+
+        let zip_path = temp_dir.path().join("release.zip");
+        let options = ZipOptions {
+            transform: PathTransform::default()
+                .strip_components(1)
+                .add_prefix("myapp-1.2.3"),
+            ..ZipOptions::default()
+        };
+        create_zip_archive_with_options(&[&out], &zip_path, &options)?;
+        let zip = zip::ZipArchive::new(File::open(&zip_path)?)?;
+        let mut names: Vec<_> = zip.file_names().collect();
+        names.sort();
+        assert_eq!(names, vec!["myapp-1.2.3/README", "myapp-1.2.3/bin/app"]);
+
+        let destination = temp_dir.path().join("unpacked");
+        let extract = ExtractOptions {
+            transform: PathTransform::default().strip_components(1),
+            ..ExtractOptions::default()
+        };
+        extract_tar_archive(&tar_path, &destination, &extract)?;
+        assert_eq!(fs::read(destination.join("bin").join("app"))?, b"app");
+
+        let escaping = ZipOptions {
+            transform: PathTransform::default().replace("^", "../")?,
+            ..ZipOptions::default()
+        };
+        assert!(matches!(
+            create_zip_archive_with_options(&[&out], &zip_path, &escaping),
+            Err(ZippingErr::PathTransformFailed(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn zip_entries_can_be_appended_replaced_and_deleted() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = |name: &str| temp_dir.path().join(name);
+        fs::write(path("notes.txt"), sample_text(20_000))?;
+        fs::write(path("build.log"), b"old log")?;
+        fs::write(path("debug.log"), b"debug")?;
+        fs::write(path("extra.txt"), b"extra")?;
+        let archive = path("bundle.zip");
+        create_zip_archive(&[path("notes.txt"), path("build.log")], archive.clone())?;
+
+        let raw_notes = |archive: &Path| -> Result<Vec<u8>, Box<dyn Error>> {
+            let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+            let mut raw = Vec::new();
+            for index in 0..zip.len() {
+                let mut entry = zip.by_index_raw(index)?;
+                if entry.name() == "notes.txt" {
+                    entry.read_to_end(&mut raw)?;
+                }
+            }
+            Ok(raw)
+        };
+        let notes_before = raw_notes(&archive)?;
+
+        let options = ZipOptions::default();
+        append_to_zip_archive(
+            archive.clone(),
+            &[path("debug.log"), path("extra.txt")],
+            &options,
+        )?;
+        assert!(matches!(
+            append_to_zip_archive(archive.clone(), &[path("extra.txt")], &options),
+            Err(ZippingErr::DuplicateEntry(name)) if name == "extra.txt"
+        ));
+        fs::write(path("twice.txt"), b"twice")?;
+        assert!(matches!(
+            append_to_zip_archive(
+                archive.clone(),
+                &[path("twice.txt"), path("twice.txt")],
+                &options
+            ),
+            Err(ZippingErr::DuplicateEntry(name)) if name == "twice.txt"
+        ));
+        // Settings for new archives are refused rather than ignored.
+        let with_manifest = ZipOptions {
+            manifest: Some(ManifestOptions::default()),
+            ..ZipOptions::default()
+        };
+        assert!(matches!(
+            append_to_zip_archive(archive.clone(), &[path("twice.txt")], &with_manifest),
+            Err(ZippingErr::UnsupportedEditOption("manifest"))
+        ));
+        let split = ZipOptions {
+            volume_size: Some(1 << 20),
+            ..ZipOptions::default()
+        };
+        assert!(matches!(
+            replace_zip_entry(archive.clone(), "build.log", path("build.log"), &split),
+            Err(ZippingErr::UnsupportedEditOption("volume_size"))
+        ));
+
+        fs::write(path("build.log"), b"new log")?;
+        replace_zip_entry(archive.clone(), "build.log", path("build.log"), &options)?;
+        let mut zip = zip::ZipArchive::new(File::open(&archive)?)?;
+        let mut log = String::new();
+        zip.by_name("build.log")?.read_to_string(&mut log)?;
+        assert_eq!(log, "new log");
+        assert_eq!(zip.by_index(1)?.name(), "build.log");
+        drop(zip);
+        assert!(matches!(
+            replace_zip_entry(archive.clone(), "missing.txt", path("build.log"), &options),
+            Err(ZippingErr::EntryNotFound(_))
+        ));
+
+        let deleted = delete_zip_entries(&archive, &["*.log"])?;
+        assert_eq!(deleted, vec!["build.log", "debug.log"]);
+
+        let mut zip = zip::ZipArchive::new(File::open(&archive)?)?;
+        let names: Vec<_> = zip.file_names().map(String::from).collect();
+        assert_eq!(names.len(), 2);
+        assert!(names.contains(&"notes.txt".to_string()));
+        assert!(names.contains(&"extra.txt".to_string()));
+        let mut extra = String::new();
+        zip.by_name("extra.txt")?.read_to_string(&mut extra)?;
+        assert_eq!(extra, "extra");
+        drop(zip);
+        assert_eq!(raw_notes(&archive)?, notes_before);
+
+        fs::write(path("build.log"), b"newest log")?;
+        append_to_zip_archive(archive.clone(), &[path("build.log")], &options)?;
+        let mut zip = zip::ZipArchive::new(File::open(&archive)?)?;
+        let mut log = String::new();
+        zip.by_name("build.log")?.read_to_string(&mut log)?;
+        assert_eq!(log, "newest log");
+
+        Ok(())
+    }
+
+    #[test]
+    fn tar_archives_can_be_appended_in_place() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let logs = temp_dir.path().join("logs");
+        fs::create_dir(&logs)?;
+        for day in ["mon", "tue", "wed"] {
+            fs::write(logs.join(format!("{}.log", day)), sample_text(3_000))?;
+        }
+        let flat = TarOptions {
+            transform: PathTransform::default().replace("^.*/", "")?,
+            ..TarOptions::default()
+        };
+        let archive = temp_dir.path().join("logs.tar");
+        create_tar_archive_with_options(&[logs.join("mon.log")], archive.clone(), &flat)?;
+        let original = fs::read(&archive)?;
+
+        let append = TarAppendOptions {
+            tar: flat.clone(),
+            unique_names: true,
+        };
+        append_to_tar_archive_with_options(&archive, &[&logs.join("tue.log")], &append)?;
+        assert!(matches!(
+            append_to_tar_archive_with_options(&archive, &[&logs.join("mon.log")], &append),
+            Err(TarErr::DuplicateEntry(name)) if name == Path::new("mon.log")
+        ));
+        append_to_tar_archive(archive.clone(), &[logs.join("wed.log")])?;
+
+        let appended = fs::read(&archive)?;
+        let first_end = original.len() - 1024;
+        assert_eq!(appended[..first_end], original[..first_end]);
+        assert!(appended.ends_with(&[0; 1024]));
+
+        let mut archive_reader = tar::Archive::new(File::open(&archive)?);
+        let mut names = Vec::new();
+        for entry in archive_reader.entries()? {
+            let mut entry = entry?;
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            assert_eq!(contents.len(), 3_000);
+            names.push(entry.path()?.into_owned());
+        }
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[0], Path::new("mon.log"));
+        assert_eq!(names[1], Path::new("tue.log"));
+        assert!(names[2].ends_with("logs/wed.log"));
+
+        let compressed = temp_dir.path().join("logs.tar.gz");
+        create_tar_gz_archive(&[logs.join("mon.log")], compressed.clone())?;
+        assert!(matches!(
+            append_to_tar_archive(compressed, &[logs.join("tue.log")]),
+            Err(TarErr::NotAppendable { .. })
+        ));
+
+        // Settings for a whole new output file are refused rather than ignored.
+        let whole_file = [
+            TarOptions {
+                encryption: Some(EncryptionKey::KeyFile(temp_dir.path().join("key.txt"))),
+                ..flat.clone()
+            },
+            TarOptions {
+                manifest: Some(ManifestOptions::default()),
+                ..flat.clone()
+            },
+        ];
+        for (tar, option) in whole_file.into_iter().zip(["encryption", "manifest"]) {
+            let append = TarAppendOptions {
+                tar,
+                unique_names: false,
+            };
+            assert!(matches!(
+                append_to_tar_archive_with_options(&archive, &[&logs.join("tue.log")], &append),
+                Err(TarErr::UnsupportedAppendOption(name)) if name == option
+            ));
+        }
+        assert_eq!(fs::read(&archive)?, appended);
+
+        Ok(())
+    }
+
+    #[test]
+    fn zip_compression_method_per_entry() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let path = |name: &str| temp_dir.path().join(name);
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let noise: Vec<u8> = (0..100_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        let text = sample_text(50_000);
+        fs::write(path("photo.jpg"), &text)?;
+        fs::write(path("random.bin"), &noise)?;
+        for name in ["notes.txt", "a.zst", "b.bz2x", "c.xz2", "d.data"] {
+            fs::write(path(name), &text)?;
+        }
+        let inputs: Vec<PathBuf> = [
+            "photo.jpg",
+            "random.bin",
+            "notes.txt",
+            "a.zst",
+            "b.bz2x",
+            "c.xz2",
+            "d.data",
+        ]
+        .iter()
+        .map(|name| path(name))
+        .collect();
+
+        let options = ZipOptions {
+            method: MethodSelector::precompressed(ZipMethod::Deflated)
+                .extension("txt", ZipMethod::Lzma)
+                .extension("bz2x", ZipMethod::Bzip2)
+                .extension("xz2", ZipMethod::Xz)
+                .extension("ZST", ZipMethod::Deflated),
+            store_below: Some(0.05),
+            ..ZipOptions::default()
+        };
+        let archive = path("mixed.zip");
+        create_zip_archive_with_options(&inputs, archive.clone(), &options)?;
+
+        let mut zip = zip::ZipArchive::new(File::open(&archive)?)?;
+        let expected = [
+            ("photo.jpg", zip::CompressionMethod::Stored, &text[..]),
+            ("random.bin", zip::CompressionMethod::Stored, &noise[..]),
+            ("notes.txt", zip::CompressionMethod::Lzma, &text[..]),
+            ("a.zst", zip::CompressionMethod::Deflated, &text[..]),
+            ("b.bz2x", zip::CompressionMethod::Bzip2, &text[..]),
+            ("c.xz2", zip::CompressionMethod::Xz, &text[..]),
+            ("d.data", zip::CompressionMethod::Deflated, &text[..]),
+        ];
+        for (name, method, contents) in expected {
+            let mut entry = zip.by_name(name)?;
+            assert_eq!(entry.compression(), method, "{}", name);
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            assert_eq!(data, contents, "{}", name);
+        }
+
+        let zstd = ZipOptions {
+            method: MethodSelector::callback(|_, name| match name.ends_with(".txt") {
+                true => ZipMethod::Zstd,
+                false => ZipMethod::Stored,
+            }),
+            level: Some(19),
+            ..ZipOptions::default()
+        };
+        create_zip_archive_with_options(&inputs[..3], archive.clone(), &zstd)?;
+        let mut zip = zip::ZipArchive::new(File::open(&archive)?)?;
+        let mut entry = zip.by_name("notes.txt")?;
+        assert_eq!(entry.compression(), zip::CompressionMethod::Zstd);
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        assert_eq!(data, text);
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn zip_entries_keep_permissions_and_timestamps() -> Result<(), Box<dyn Error>> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempfile::tempdir()?;
+        let script = temp_dir.path().join("run.sh");
+        let notes = temp_dir.path().join("notes.txt");
+        fs::write(&script, b"#!/bin/sh\necho hi\n")?;
+        fs::write(&notes, sample_text(2_000))?;
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o750))?;
+        fs::set_permissions(&notes, fs::Permissions::from_mode(0o640))?;
+        for file in [&script, &notes] {
+            File::options()
+                .write(true)
+                .open(file)?
+                .set_modified(UNIX_EPOCH + Duration::from_secs(1_600_000_000))?;
+        }
+
+        let check =
+            |options: &ZipOptions, expected: [(&str, u32); 2]| -> Result<(), Box<dyn Error>> {
+                let archive = temp_dir.path().join("stamped.zip");
+                create_zip_archive_with_options(&[&script, &notes], &archive, options)?;
+                let mut zip = zip::ZipArchive::new(File::open(&archive)?)?;
+                for (name, mode) in expected {
+                    let entry = zip.by_name(name)?;
+                    assert_eq!(
+                        entry.unix_mode().map(|mode| mode & 0o7777),
+                        Some(mode),
+                        "{}",
+                        name
+                    );
+                    let modified = entry.last_modified().ok_or("missing timestamp")?;
+                    assert_eq!(
+                        (
+                            modified.year(),
+                            modified.month(),
+                            modified.day(),
+                            modified.hour()
+                        ),
+                        (2020, 9, 13, 12)
+                    );
+                    let extended = entry.extra_data_fields().find_map(|field| match field {
+                        zip::ExtraField::ExtendedTimestamp(timestamp) => timestamp.mod_time(),
+                        _ => None,
+                    });
+                    assert_eq!(extended, Some(1_600_000_000), "{}", name);
+                }
+                Ok(())
+            };
+
+        check(
+            &ZipOptions::default(),
+            [("run.sh", 0o750), ("notes.txt", 0o640)],
+        )?;
+        check(
+            &ZipOptions {
+                method: MethodSelector::Fixed(ZipMethod::Lzma),
+                ..ZipOptions::default()
+            },
+            [("run.sh", 0o750), ("notes.txt", 0o640)],
+        )?;
+        check(
+            &ZipOptions {
+                permissions: Some(0o644),
+                ..ZipOptions::default()
+            },
+            [("run.sh", 0o644), ("notes.txt", 0o644)],
+        )?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn zip_entries_can_be_encrypted() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let secret = temp_dir.path().join("secret.csv");
+        let readme = temp_dir.path().join("README.txt");
+        fs::write(&secret, sample_text(5_000))?;
+        fs::write(&readme, b"Exported data, password protected\n")?;
+
+        let archive = temp_dir.path().join("export.zip");
+        let options = ZipOptions {
+            password: ZipPassword::matching("hunter2", &["*.csv"])?,
+            ..ZipOptions::default()
+        };
+        create_zip_archive_with_options(&[&secret, &readme], &archive, &options)?;
+
+        let mut zip = zip::ZipArchive::new(File::open(&archive)?)?;
+        assert!(!zip.by_name("README.txt")?.encrypted());
+        let entry = zip.by_index_raw(zip.index_for_name("secret.csv").ok_or("missing entry")?)?;
+        assert!(entry.encrypted());
+        // AE-2: no CRC of the plaintext, vendor version 2 in the 0x9901 field.
+        assert_eq!(entry.crc32(), 0);
+        let extra = entry.extra_data().ok_or("missing extra field")?;
+        let aes = extra
+            .windows(6)
+            .position(|window| window[..4] == [0x01, 0x99, 7, 0])
+            .ok_or("missing AES field")?;
+        assert_eq!(extra[aes + 4..aes + 6], [2, 0]);
+        drop(entry);
+
+        let extract = |password: ZipPassword, name: &str| {
+            let options = ZipExtractOptions {
+                password,
+                ..ZipExtractOptions::default()
+            };
+            extract_zip_archive(archive.clone(), temp_dir.path().join(name), &options)
+        };
+        extract(ZipPassword::callback(|_| Some("hunter2".to_string())), "ok")?;
+        assert_eq!(
+            fs::read(temp_dir.path().join("ok/secret.csv"))?,
+            fs::read(&secret)?
+        );
+        assert!(matches!(
+            extract(ZipPassword::Fixed("wrong".to_string()), "wrong"),
+            Err(ZippingErr::WrongPassword(name)) if name == "secret.csv"
+        ));
+        assert!(matches!(
+            extract(ZipPassword::None, "none"),
+            Err(ZippingErr::PasswordRequired(_))
+        ));
+
+        // Flip a byte of the encrypted data: the authentication code no longer matches.
+        let data_start = zip
+            .by_name_decrypt("secret.csv", b"hunter2")?
+            .data_start()
+            .ok_or("missing data")?;
+        let mut bytes = fs::read(&archive)?;
+        bytes[data_start as usize + 40] ^= 0xff;
+        fs::write(&archive, bytes)?;
+        assert!(matches!(
+            extract(ZipPassword::Fixed("hunter2".to_string()), "corrupt"),
+            Err(ZippingErr::CorruptEntry(..))
+        ));
+        assert!(!temp_dir.path().join("corrupt/secret.csv").exists());
+
+        // Legacy ZipCrypto only on request; it stays readable after edits.
+        let legacy = temp_dir.path().join("legacy.zip");
+        let options = ZipOptions {
+            password: ZipPassword::Fixed("hunter2".to_string()),
+            cipher: ZipCipher::ZipCrypto,
+            ..ZipOptions::default()
+        };
+        create_zip_archive_with_options(&[&secret, &readme], &legacy, &options)?;
+        delete_zip_entries(&legacy, &["README.txt"])?;
+        let options = ZipExtractOptions {
+            password: ZipPassword::Fixed("hunter2".to_string()),
+            ..ZipExtractOptions::default()
+        };
+        extract_zip_archive(legacy, temp_dir.path().join("legacy"), &options)?;
+        assert_eq!(
+            fs::read(temp_dir.path().join("legacy/secret.csv"))?,
+            fs::read(&secret)?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn archives_can_be_encrypted_with_age() -> Result<(), Box<dyn Error>> {
+        use age::secrecy::ExposeSecret;
+
+        let temp_dir = tempfile::tempdir()?;
+        let input = temp_dir.path().join("data.txt");
+        fs::write(&input, sample_text(200_000))?;
+
+        let identity = age::x25519::Identity::generate();
+        let key_file = temp_dir.path().join("key.txt");
+        fs::write(&key_file, identity.to_string().expose_secret())?;
+        let key = EncryptionKey::KeyFile(key_file);
+
+        let archive = temp_dir.path().join("backup.tar.xz.age");
+        let options = TarOptions {
+            encryption: Some(EncryptionKey::Recipients(vec![identity
+                .to_public()
+                .to_string()])),
+            ..TarOptions::default()
+        };
+        create_tar_xz_archive_with_options(&[&input], &archive, &options)?;
+        assert!(fs::read(&archive)?.starts_with(b"age-encryption.org/v1"));
+
+        let extract = ExtractOptions {
+            decryption: Some(key.clone()),
+            ..ExtractOptions::default()
+        };
+        let destination = temp_dir.path().join("restored");
+        extract_tar_archive(&archive, &destination, &extract)?;
+        assert_eq!(
+            fs::read(destination.join(input.strip_prefix("/")?))?,
+            fs::read(&input)?
+        );
+
+        assert!(matches!(
+            extract_tar_archive(&archive, &destination, &ExtractOptions::default()),
+            Err(TarErr::EncryptionFailed(CryptErr::KeyRequired))
+        ));
+        let other_key = temp_dir.path().join("other.txt");
+        let other = age::x25519::Identity::generate().to_string();
+        fs::write(&other_key, other.expose_secret())?;
+        let extract = ExtractOptions {
+            decryption: Some(EncryptionKey::KeyFile(other_key)),
+            ..ExtractOptions::default()
+        };
+        assert!(matches!(
+            extract_tar_archive(&archive, &destination, &extract),
+            Err(TarErr::EncryptionFailed(CryptErr::WrongKey))
+        ));
+
+        // Single compressed files; damaged input never leaves a partial output.
+        let encrypted = temp_dir.path().join("data.txt.xz.age");
+        create_file_xz_encrypted(&input, &encrypted, &key)?;
+        let decrypted = temp_dir.path().join("data.txt.xz");
+        decrypt_file(&encrypted, &decrypted, &key)?;
+        let plain = temp_dir.path().join("plain.xz");
+        create_file_xz(&input, &plain)?;
+        assert_eq!(fs::read(&decrypted)?, fs::read(&plain)?);
+        create_gzip_archive_encrypted(&input, &encrypted, &key)?;
+        decrypt_file(&encrypted, &decrypted, &key)?;
+        create_gzip_archive(&input, &plain)?;
+        assert_eq!(fs::read(&decrypted)?, fs::read(&plain)?);
+        create_file_bzip2_encrypted(&input, &encrypted, &key)?;
+        decrypt_file(&encrypted, &decrypted, &key)?;
+        create_file_bzip2(&input, &plain)?;
+        assert_eq!(fs::read(&decrypted)?, fs::read(&plain)?);
+        create_file_xz_encrypted(&input, &encrypted, &key)?;
+
+        let truncated = temp_dir.path().join("truncated.xz");
+        let bytes = fs::read(&encrypted)?;
+        fs::write(&encrypted, &bytes[..bytes.len() - 100])?;
+        assert!(matches!(
+            decrypt_file(&encrypted, &truncated, &key),
+            Err(CryptErr::CorruptData(_))
+        ));
+        assert!(!truncated.exists());
+
+        // Cut right after a full 64 KiB chunk: the missing final chunk is detected.
+        encrypt_file(&input, &encrypted, &key)?;
+        let bytes = fs::read(&encrypted)?;
+        let last_chunk = 200_000 % 65_536 + 16;
+        fs::write(&encrypted, &bytes[..bytes.len() - last_chunk])?;
+        assert!(matches!(
+            decrypt_file(&encrypted, &truncated, &key),
+            Err(CryptErr::Truncated)
+        ));
+        assert!(!truncated.exists());
+
+        let passphrase = EncryptionKey::Passphrase("correct horse".to_string());
+        encrypt_file(&plain, &encrypted, &passphrase)?;
+        assert!(matches!(
+            decrypt_file(&encrypted, &truncated, &key),
+            Err(CryptErr::WrongKey)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn zip_comments_and_extra_fields() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let app = temp_dir.path().join("app.bin");
+        let notes = temp_dir.path().join("notes.txt");
+        fs::write(&app, sample_text(4_000))?;
+        fs::write(&notes, b"release notes\n")?;
+
+        let build = ZipExtraField {
+            id: 0xCAFE,
+            data: b"commit=4af56b3".to_vec(),
+        };
+        let archive = temp_dir.path().join("release.zip");
+        let options = ZipOptions {
+            comment: Some("build 1042".to_string()),
+            method: MethodSelector::Fixed(ZipMethod::Lzma),
+            entry_metadata: ZipEntryMetadata::callback({
+                let build = build.clone();
+                move |_, name| {
+                    (name == "app.bin").then(|| ZipEntryInfo {
+                        comment: "signed binary".to_string(),
+                        extra_fields: vec![build.clone()],
+                    })
+                }
+            }),
+            ..ZipOptions::default()
+        };
+        create_zip_archive_with_options(&[&app], &archive, &options)?;
+        let options = ZipOptions {
+            entry_metadata: ZipEntryMetadata::Named(
+                [(
+                    "notes.txt".to_string(),
+                    ZipEntryInfo {
+                        comment: "généré".to_string(),
+                        extra_fields: Vec::new(),
+                    },
+                )]
+                .into(),
+            ),
+            ..ZipOptions::default()
+        };
+        append_to_zip_archive(&archive, &[&notes], &options)?;
+
+        // Existing entries keep their comment and extra fields through edits.
+        let info = read_zip_info(&archive)?;
+        assert_eq!(info.comment, "build 1042");
+        let (name, app_info) = &info.entries[0];
+        assert_eq!(name, "app.bin");
+        assert_eq!(app_info.comment, "signed binary");
+        assert!(app_info.extra_fields.contains(&build));
+        assert!(app_info.extra_fields.iter().any(|field| field.id == 0x5455));
+        assert_eq!(info.entries[1].1.comment, "généré");
+
+        let mut zip = zip::ZipArchive::new(File::open(&archive)?)?;
+        let mut contents = Vec::new();
+        zip.by_name("app.bin")?.read_to_end(&mut contents)?;
+        assert_eq!(contents, fs::read(&app)?);
+
+        let too_long = ZipOptions {
+            comment: Some("x".repeat(65_536)),
+            ..ZipOptions::default()
+        };
+        assert!(matches!(
+            create_zip_archive_with_options(&[&notes], &archive, &too_long),
+            Err(ZippingErr::FieldTooLong(_))
+        ));
+        let oversized = ZipExtraField {
+            id: 0xCAFE,
+            data: vec![0; 65_530],
+        };
+        let too_long = ZipOptions {
+            entry_metadata: ZipEntryMetadata::callback(move |_, _| {
+                Some(ZipEntryInfo {
+                    comment: String::new(),
+                    extra_fields: vec![oversized.clone()],
+                })
+            }),
+            ..ZipOptions::default()
+        };
+        assert!(matches!(
+            create_zip_archive_with_options(&[&notes], &archive, &too_long),
+            Err(ZippingErr::FieldTooLong(_))
+        ));
+        let reserved = ZipOptions {
+            entry_metadata: ZipEntryMetadata::callback(|_, _| {
+                Some(ZipEntryInfo {
+                    comment: String::new(),
+                    extra_fields: vec![ZipExtraField {
+                        id: 0x5455,
+                        data: vec![1, 0, 0, 0, 0],
+                    }],
+                })
+            }),
+            ..ZipOptions::default()
+        };
+        assert!(matches!(
+            create_zip_archive_with_options(&[&notes], &archive, &reserved),
+            Err(ZippingErr::ReservedExtraField(0x5455))
+        ));
+        assert_eq!(read_zip_info(&archive)?, info);
+
+        Ok(())
+    }
+
+    #[test]
+    fn zip_legacy_filename_encodings() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+
+        // What a Japanese Windows zipper writes: a Shift-JIS name ("日本.txt")
+        // without the UTF-8 flag, and a placeholder name with an Info-ZIP
+        // Unicode Path field carrying the real one.
+        let placeholder = b"????.txt";
+        let mut crc = flate2::Crc::new();
+        crc.update(placeholder);
+        let mut unicode_path = vec![0x75, 0x70, 0, 0, 1];
+        unicode_path.extend_from_slice(&crc.sum().to_le_bytes());
+        unicode_path.extend_from_slice("Ünïcødé.txt".as_bytes());
+        unicode_path[2] = (unicode_path.len() - 4) as u8;
+        let entries: [(&[u8], &[u8], &[u8]); 2] = [
+            (b"\x93\xfa\x96\x7b.txt", b"", b"nihon"),
+            (placeholder, &unicode_path, b"unicode"),
+        ];
+        let (mut bytes, mut central) = (Vec::new(), Vec::new());
+        for (name, extra, data) in entries {
+            let mut crc = flate2::Crc::new();
+            crc.update(data);
+            let mut common = vec![20, 0, 0, 0, 0, 0, 0, 0, 0x21, 0];
+            common.extend_from_slice(&crc.sum().to_le_bytes());
+            common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            common.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+            central.extend_from_slice(&[0x50, 0x4b, 1, 2, 20, 0]);
+            central.extend_from_slice(&common);
+            central.extend_from_slice(&[0; 10]);
+            central.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+            central.extend_from_slice(name);
+            central.extend_from_slice(extra);
+            bytes.extend_from_slice(&[0x50, 0x4b, 3, 4]);
+            bytes.extend_from_slice(&common);
+            bytes.extend_from_slice(name);
+            bytes.extend_from_slice(extra);
+            bytes.extend_from_slice(data);
+        }
+        let central_offset = bytes.len() as u32;
+        bytes.extend_from_slice(&central);
+        bytes.extend_from_slice(&[0x50, 0x4b, 5, 6, 0, 0, 0, 0, 2, 0, 2, 0]);
+        bytes.extend_from_slice(&(central.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&central_offset.to_le_bytes());
+        bytes.extend_from_slice(&[0, 0]);
+        let archive = temp_dir.path().join("partner.zip");
+        fs::write(&archive, bytes)?;
+
+        let names = |info: crate::zip::ZipInfo| -> Vec<String> {
+            info.entries.into_iter().map(|(name, _)| name).collect()
+        };
+        assert_eq!(names(read_zip_info(&archive)?), ["ô·û{.txt", "Ünïcødé.txt"]);
+        let shift_jis = ZipNameEncoding::from_label("shift_jis").ok_or("unknown encoding")?;
+        let options = ZipExtractOptions {
+            name_encoding: shift_jis,
+            ..ZipExtractOptions::default()
+        };
+        extract_zip_archive(&archive, &temp_dir.path().join("out"), &options)?;
+        assert_eq!(fs::read(temp_dir.path().join("out/日本.txt"))?, b"nihon");
+        assert_eq!(
+            fs::read(temp_dir.path().join("out/Ünïcødé.txt"))?,
+            b"unicode"
+        );
+
+        // Editing keeps the legacy name as it is and stores the Unicode Path
+        // name as UTF-8; new non-ASCII names always get the UTF-8 flag.
+        let cafe = temp_dir.path().join("café.txt");
+        fs::write(&cafe, b"cafe")?;
+        append_to_zip_archive(&archive, &[&cafe], &ZipOptions::default())?;
+        let info = read_zip_info_with_encoding(&archive, shift_jis)?;
+        assert!(info
+            .entries
+            .iter()
+            .all(|(_, entry)| entry.extra_fields.iter().all(|field| field.id != 0x7075)));
+        assert_eq!(names(info), ["日本.txt", "Ünïcødé.txt", "café.txt"]);
+        let bytes = fs::read(&archive)?;
+        let mut zip = zip::ZipArchive::new(File::open(&archive)?)?;
+        for (index, utf8) in [(0, false), (1, true), (2, true)] {
+            let entry = zip.by_index_raw(index)?;
+            let at = entry.header_start() as usize;
+            assert_eq!(bytes[at + 7] & 0x08 != 0, utf8);
+        }
+        let mut contents = String::new();
+        zip.by_name("Ünïcødé.txt")?.read_to_string(&mut contents)?;
+        assert_eq!(contents, "unicode");
+
+        Ok(())
+    }
+
+    #[test]
+    fn zip_archives_stream_to_non_seekable_writers() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let report = temp_dir.path().join("report.csv");
+        let notes = temp_dir.path().join("notes.txt");
+        fs::write(&report, sample_text(50_000))?;
+        fs::write(&notes, b"streamed\n")?;
+
+        // `Vec<u8>` implements `Write` but not `Seek`, like a socket or a pipe.
+        let options = ZipOptions {
+            method: MethodSelector::by_extension(ZipMethod::Deflated)
+                .extension("txt", ZipMethod::Lzma),
+            password: ZipPassword::matching("hunter2", &["*.csv"])?,
+            comment: Some("streamed".to_string()),
+            ..ZipOptions::default()
+        };
+        let mut writer = ZipStreamWriter::new(Vec::new(), &options);
+        writer.add_files(&[&report, &notes])?;
+        writer.add_reader("generated/hello.txt", &mut "hello".as_bytes(), Some(5))?;
+        writer.add_reader("generated/unsized.bin", &mut [7u8; 300].as_slice(), None)?;
+        assert!(matches!(
+            writer.add_reader("notes.txt", &mut "again".as_bytes(), Some(5)),
+            Err(ZippingErr::DuplicateEntry(_))
+        ));
+        let bytes = writer.finish()?;
+
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(&bytes))?;
+        assert_eq!(zip.comment(), b"streamed");
+        let mut contents = Vec::new();
+        zip.by_name_decrypt("report.csv", b"hunter2")?
+            .read_to_end(&mut contents)?;
+        assert_eq!(contents, fs::read(&report)?);
+        let mut contents = String::new();
+        zip.by_name("generated/hello.txt")?
+            .read_to_string(&mut contents)?;
+        assert_eq!(contents, "hello");
+        let mut contents = Vec::new();
+        zip.by_name("generated/unsized.bin")?
+            .read_to_end(&mut contents)?;
+        assert_eq!(contents, [7u8; 300]);
+        // Sizes and CRCs follow the data, flagged by bit 3 of each header.
+        let index = zip.index_for_name("report.csv").ok_or("missing entry")?;
+        let header_start = zip.by_index_raw(index)?.header_start() as usize;
+        assert_ne!(bytes[header_start + 6] & 0x08, 0);
+
+        let streamed = write_zip_archive(&[&notes], Vec::new(), &ZipOptions::default())?;
+        let mut zip = zip::ZipArchive::new(std::io::Cursor::new(streamed))?;
+        let mut contents = Vec::new();
+        zip.by_name("notes.txt")?.read_to_end(&mut contents)?;
+        assert_eq!(contents, b"streamed\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn streamed_lzma_entries_open_in_other_readers() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let archive_path = temp_dir.path().join("streamed.zip");
+        let text = sample_text(20_000);
+
+        let options = ZipOptions {
+            method: MethodSelector::by_extension(ZipMethod::Lzma),
+            ..ZipOptions::default()
+        };
+        let mut writer = ZipStreamWriter::new(Vec::new(), &options);
+        writer.add_reader("sized.txt", &mut text.as_slice(), Some(text.len() as u64))?;
+        writer.add_reader("unsized.txt", &mut text.as_slice(), None)?;
+        let bytes = writer.finish()?;
+        // Version needed to extract: 6.3, the first with LZMA.
+        assert_eq!(bytes[4..6], 63u16.to_le_bytes());
+        fs::write(&archive_path, &bytes)?;
+
+        // Python's `zipfile` implements LZMA independently of this crate
+        // and checks the CRC and sizes of every entry.
+        let script = "import sys, zipfile\n\
+            z = zipfile.ZipFile(sys.argv[1])\n\
+            assert z.testzip() is None\n\
+            sys.stdout.buffer.write(b''.join(z.read(n) for n in z.namelist()))";
+        let output = match Command::new("python3")
+            .args(["-c", script])
+            .arg(&archive_path)
+            .output()
+        {
+            Ok(output) => output,
+            // No reader installed.
+            Err(_) => return Ok(()),
+        };
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(output.stdout, [text.as_slice(), text.as_slice()].concat());
+
+        Ok(())
+    }
+
+    #[test]
+    fn zip_archives_stream_from_non_seekable_readers() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let report = temp_dir.path().join("report.csv");
+        let notes = temp_dir.path().join("notes.txt");
+        fs::write(&report, sample_text(50_000))?;
+        fs::write(&notes, b"streamed\n")?;
+
+        // Every entry of a streamed archive ends at a data descriptor.
+        let options = ZipOptions {
+            method: MethodSelector::by_extension(ZipMethod::Deflated)
+                .extension("txt", ZipMethod::Lzma)
+                .extension("bin", ZipMethod::Stored),
+            password: ZipPassword::matching("hunter2", &["*.csv"])?,
+            ..ZipOptions::default()
+        };
+        let mut writer = ZipStreamWriter::new(Vec::new(), &options);
+        writer.add_files(&[&report, &notes])?;
+        writer.add_reader("generated/hello.log", &mut "hello".as_bytes(), Some(5))?;
+        writer.add_reader("generated/sevens.bin", &mut [7u8; 300].as_slice(), None)?;
+        let bytes = writer.finish()?;
+
+        // `&[u8]` implements `Read` but not `Seek`, like stdin or a socket.
+        let mut reader = ZipStreamReader::new(bytes.as_slice());
+        let mut names = Vec::new();
+        while let Some(mut entry) = reader.next_entry()? {
+            names.push(entry.name().to_string());
+            let mut contents = Vec::new();
+            match entry.name() {
+                // Encrypted entries can only be skipped.
+                "report.csv" => assert!(entry.encrypted() && entry.read(&mut [0; 16]).is_err()),
+                "notes.txt" => {
+                    entry.read_to_end(&mut contents)?;
+                    assert_eq!(contents, b"streamed\n");
+                }
+                "generated/hello.log" => {
+                    entry.read_to_end(&mut contents)?;
+                    assert_eq!(contents, b"hello");
+                }
+                // Left half read: the next call skips the rest.
+                _ => assert_eq!(entry.read(&mut [0; 10])?, 10),
+            }
+        }
+        assert_eq!(
+            names,
+            [
+                "report.csv",
+                "notes.txt",
+                "generated/hello.log",
+                "generated/sevens.bin"
+            ]
+        );
+        assert!(reader.finish()?.is_empty());
+
+        // Entries with their sizes in the local header, read from a file.
+        let stored = temp_dir.path().join("methods.zip");
+        let options = ZipOptions {
+            method: MethodSelector::by_extension(ZipMethod::Bzip2)
+                .extension("txt", ZipMethod::Zstd)
+                .extension("log", ZipMethod::Xz),
+            ..ZipOptions::default()
+        };
+        let log = temp_dir.path().join("build.log");
+        fs::write(&log, sample_text(20_000))?;
+        create_zip_archive_with_options(&[&report, &notes, &log], &stored, &options)?;
+        let mut reader = ZipStreamReader::new(File::open(&stored)?);
+        while let Some(mut entry) = reader.next_entry()? {
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            assert_eq!(contents, fs::read(temp_dir.path().join(entry.name()))?);
+        }
+        assert!(reader.finish()?.is_empty());
+
+        // A central directory that disagrees with the local headers.
+        let mut tampered = bytes.clone();
+        let name = b"generated/hello.log";
+        let record = tampered
+            .windows(name.len())
+            .rposition(|w| w == name)
+            .ok_or("missing record")?
+            - 46;
+        tampered[record + 16] ^= 0xFF;
+        let mut reader = ZipStreamReader::new(tampered.as_slice());
+        while reader.next_entry()?.is_some() {}
+        assert_eq!(
+            reader.finish()?,
+            [ZipInconsistency::FieldMismatch {
+                name: "generated/hello.log".to_string(),
+                field: "CRC",
+            }]
+        );
+
+        // Corrupt data fails its CRC, and a cut stream has no central directory.
+        let mut corrupt = bytes.clone();
+        let data = corrupt
+            .windows(300)
+            .position(|w| w == [7u8; 300])
+            .ok_or("missing data")?;
+        corrupt[data] = 8;
+        let mut reader = ZipStreamReader::new(corrupt.as_slice());
+        while let Some(mut entry) = reader.next_entry()? {
+            if entry.name() == "generated/sevens.bin" {
+                let error = entry.read_to_end(&mut Vec::new()).unwrap_err();
+                assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+            }
+        }
+        let first = b"report.csv";
+        let central = bytes
+            .windows(first.len())
+            .rposition(|w| w == first)
+            .ok_or("missing record")?
+            - 46;
+        let mut reader = ZipStreamReader::new(&bytes[..central]);
+        while reader.next_entry()?.is_some() {}
+        assert_eq!(
+            reader.finish()?,
+            [ZipInconsistency::MissingCentralDirectory]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn archives_split_into_volumes() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("source");
+        fs::create_dir(&source)?;
+        fs::write(source.join("big.txt"), sample_text(200_000))?;
+        fs::write(source.join("small.txt"), b"small\n")?;
+
+        // Byte-split tar volumes are read back transparently.
+        let tar_path = temp_dir.path().join("backup.tar.gz");
+        let options = TarOptions {
+            volume_size: Some(10_000),
+            ..TarOptions::default()
+        };
+        create_tar_gz_archive_with_options(&[&source], &tar_path, &options)?;
+        assert!(!tar_path.exists());
+        assert_eq!(fs::metadata(volume_path(&tar_path, 1))?.len(), 10_000);
+        assert!(volume_path(&tar_path, 2).exists());
+        let destination = temp_dir.path().join("from-tar");
+        extract_tar_archive(&tar_path, &destination, &ExtractOptions::default())?;
+        let restored = destination.join("source").join("big.txt");
+        assert_eq!(fs::read(restored)?, sample_text(200_000));
+
+        // Rewriting the archive removes whatever an earlier one left behind.
+        let volumes = |path: &Path| (1..).take_while(|n| volume_path(path, *n).exists()).count();
+        let count = volumes(&tar_path);
+        let larger = TarOptions {
+            volume_size: Some(20_000),
+            ..TarOptions::default()
+        };
+        create_tar_gz_archive_with_options(&[&source], &tar_path, &larger)?;
+        assert!(volumes(&tar_path) < count);
+        create_tar_gz_archive_with_options(&[&source], &tar_path, &TarOptions::default())?;
+        assert_eq!(volumes(&tar_path), 0);
+        create_tar_gz_archive_with_options(&[&source], &tar_path, &options)?;
+        assert!(!tar_path.exists());
+        extract_tar_archive(&tar_path, &destination, &ExtractOptions::default())?;
+
+        // Any file can be split; the volumes join back into the original.
+        let xz_path = temp_dir.path().join("big.txt.xz");
+        create_file_xz(source.join("big.txt"), xz_path.clone())?;
+        let parts = split_file(&xz_path, 4096)?;
+        assert_eq!(parts[0], volume_path(&xz_path, 1));
+        let mut joined = Vec::new();
+        VolumeReader::open(&parts[0])?.read_to_end(&mut joined)?;
+        assert_eq!(joined, fs::read(&xz_path)?);
+        let mut reader = VolumeReader::open(&xz_path)?;
+        reader.seek(SeekFrom::Start(5000))?;
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail)?;
+        assert_eq!(tail, joined[5000..]);
+
+        // Split zips use the standard .z01, .z02, ..., .zip volumes.
+        let zip_path = temp_dir.path().join("backup.zip");
+        let options = ZipOptions {
+            method: MethodSelector::Fixed(ZipMethod::Stored),
+            volume_size: Some(64 * 1024),
+            comment: Some("split".to_string()),
+            ..ZipOptions::default()
+        };
+        assert!(matches!(
+            create_zip_archive_with_options(
+                &[&source],
+                &zip_path,
+                &ZipOptions {
+                    volume_size: Some(1000),
+                    ..ZipOptions::default()
+                }
+            ),
+            Err(ZippingErr::InvalidVolumeSize(1000))
+        ));
+        create_zip_archive_with_options(&[&source], &zip_path, &options)?;
+        let first = fs::read(temp_dir.path().join("backup.z01"))?;
+        assert_eq!(first[..4], *b"PK\x07\x08");
+        assert!(first.len() <= 64 * 1024);
+        assert!(temp_dir.path().join("backup.z03").exists());
+        assert_eq!(read_zip_info(&zip_path)?.comment, "split");
+        let destination = temp_dir.path().join("from-zip");
+        extract_zip_archive(&zip_path, &destination, &ZipExtractOptions::default())?;
+        assert_eq!(
+            fs::read(destination.join("source/big.txt"))?,
+            sample_text(200_000)
+        );
+        let single = temp_dir.path().join("single.zip");
+        join_split_zip(&zip_path, &single)?;
+        let mut zip = zip::ZipArchive::new(File::open(&single)?)?;
+        let mut contents = String::new();
+        zip.by_name("source/small.txt")?
+            .read_to_string(&mut contents)?;
+        assert_eq!(contents, "small\n");
+
+        // Writing an archive that fits in one volume removes the old volumes.
+        create_zip_archive_with_options(&[source.join("small.txt")], zip_path.clone(), &options)?;
+        assert!(!temp_dir.path().join("backup.z01").exists());
+        assert_eq!(read_zip_info(&zip_path)?.entries.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn archives_convert_between_formats() -> Result<(), Box<dyn Error>> {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let temp_dir = tempfile::tempdir()?;
+        let source = temp_dir.path().join("source");
+        fs::create_dir_all(source.join("bin"))?;
+        fs::write(source.join("notes.txt"), sample_text(50_000))?;
+        File::options()
+            .write(true)
+            .open(source.join("notes.txt"))?
+            .set_modified(UNIX_EPOCH + Duration::from_secs(1_600_000_000))?;
+        fs::write(source.join("bin/run.sh"), b"#!/bin/sh\n")?;
+        fs::set_permissions(source.join("bin/run.sh"), fs::Permissions::from_mode(0o750))?;
+        File::options()
+            .write(true)
+            .open(source.join("bin/run.sh"))?
+            .set_modified(UNIX_EPOCH + Duration::new(1_600_000_000, 500_000_000))?;
+        symlink("notes.txt", source.join("latest.txt"))?;
+        fs::hard_link(source.join("notes.txt"), source.join("copy.txt"))?;
+
+        // tar.gz to zip: links and modes are kept, owners, sub-second times
+        // and hard links reported.
+        let tar_path = temp_dir.path().join("drop.tar.gz");
+        let tar_options = TarOptions {
+            format: TarFormat::Pax,
+            symlinks: SymlinkPolicy::Preserve,
+            detect_hardlinks: true,
+            owner: OwnerMapping::Fixed(Owner::new(1000, 1000).names("dev", "dev")),
+            ..TarOptions::default()
+        };
+        create_tar_gz_archive_with_options(&[&source], &tar_path, &tar_options)?;
+        let zip_path = temp_dir.path().join("drop.zip");
+        let options = ConvertOptions {
+            zip: ZipOptions {
+                comment: Some("vendor drop".to_string()),
+                entry_metadata: ZipEntryMetadata::Named(
+                    [(
+                        "source/bin/run.sh".to_string(),
+                        ZipEntryInfo {
+                            comment: "entry point".to_string(),
+                            extra_fields: Vec::new(),
+                        },
+                    )]
+                    .into(),
+                ),
+                ..ZipOptions::default()
+            },
+            ..ConvertOptions::default()
+        };
+        let report = convert_archive(&tar_path, &zip_path, &options)?;
+        assert_eq!(report.entries, 4);
+        assert!(report
+            .losses
+            .contains(&ConversionLoss::Owner("source/bin/run.sh".to_string())));
+        assert!(report.losses.contains(&ConversionLoss::MtimePrecision(
+            "source/bin/run.sh".to_string()
+        )));
+        let linked = report
+            .losses
+            .iter()
+            .find_map(|loss| match loss {
+                ConversionLoss::HardLinkCopied { target, .. } => Some(target.clone()),
+                _ => None,
+            })
+            .ok_or("the hard link was not reported")?;
+        let mut zip = zip::ZipArchive::new(File::open(&zip_path)?)?;
+        assert_eq!(zip.by_name("source/bin/run.sh")?.comment(), "entry point");
+        assert!(zip.by_name("source/latest.txt")?.is_symlink());
+        assert_eq!(
+            zip.by_name("source/bin/run.sh")?
+                .unix_mode()
+                .map(|mode| mode & 0o777),
+            Some(0o750)
+        );
+        let mut copy = Vec::new();
+        zip.by_name("source/copy.txt")?.read_to_end(&mut copy)?;
+        assert_eq!(copy, sample_text(50_000));
+
+        // zip to tar.zst: comments have nowhere to go.
+        let zst_path = temp_dir.path().join("drop.tar.zst");
+        let report = convert_archive(&zip_path, &zst_path, &ConvertOptions::default())?;
+        assert_eq!(
+            report.losses,
+            vec![
+                ConversionLoss::EntryComment("source/bin/run.sh".to_string()),
+                ConversionLoss::ArchiveComment
+            ]
+        );
+        assert_eq!(fs::read(&zst_path)?[..4], [0x28, 0xb5, 0x2f, 0xfd]);
+        let destination = temp_dir.path().join("restored");
+        let extract_options = ExtractOptions {
+            symlinks: SymlinkPolicy::Preserve,
+            ..ExtractOptions::default()
+        };
+        extract_tar_archive(&zst_path, &destination, &extract_options)?;
+        let restored = destination.join("source");
+        assert_eq!(
+            fs::read_link(restored.join("latest.txt"))?,
+            Path::new("notes.txt")
+        );
+        assert_eq!(
+            fs::metadata(restored.join("bin/run.sh"))?
+                .permissions()
+                .mode()
+                & 0o777,
+            0o750
+        );
+        let notes = fs::metadata(restored.join("notes.txt"))?;
+        assert_eq!(
+            notes.modified()?,
+            UNIX_EPOCH + Duration::from_secs(1_600_000_000)
+        );
+        assert_eq!(fs::read(restored.join("copy.txt"))?, sample_text(50_000));
+
+        // A hard link whose target the transform drops is reported, not lost silently.
+        let options = ConvertOptions {
+            tar: TarOptions {
+                transform: PathTransform::default()
+                    .replace(&format!("^{}$", regex::escape(&linked)), "")?,
+                ..TarOptions::default()
+            },
+            ..ConvertOptions::default()
+        };
+        let report = convert_archive(&tar_path, &temp_dir.path().join("subset.tar"), &options)?;
+        assert!(report.losses.iter().any(|loss| matches!(
+            loss,
+            ConversionLoss::HardLinkDropped { target, .. } if *target == linked
+        )));
+
+        assert!(matches!(
+            convert_archive(
+                &zst_path,
+                &temp_dir.path().join("drop.rar"),
+                &ConvertOptions::default()
+            ),
+            Err(ConvertErr::UnknownFormat(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn files_recompress_to_another_codec() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let text_path = temp_dir.path().join("events.log");
+        fs::write(&text_path, sample_text(200_000))?;
+        let gz_path = temp_dir.path().join("events.log.gz");
+        create_gzip_archive(&text_path, &gz_path)?;
+
+        // gzip to xz, checked before the original is removed.
+        let options = RecompressOptions {
+            verify: true,
+            ..RecompressOptions::default()
+        };
+        let xz_path = recompress_with_options(&gz_path, Codec::Xz, Some(6), &options)?;
+        assert_eq!(xz_path, temp_dir.path().join("events.log.xz"));
+        assert!(!gz_path.exists());
+        let mut restored = Vec::new();
+        xz2::read::XzDecoder::new(File::open(&xz_path)?).read_to_end(&mut restored)?;
+        assert_eq!(restored, sample_text(200_000));
+
+        // xz to zstd under a short tarball name, keeping the original.
+        let txz_path = temp_dir.path().join("events.txz");
+        fs::rename(&xz_path, &txz_path)?;
+        let options = RecompressOptions {
+            keep_original: true,
+            ..RecompressOptions::default()
+        };
+        let zst_path = recompress_with_options(&txz_path, Codec::Zstd, None, &options)?;
+        assert_eq!(zst_path, temp_dir.path().join("events.tar.zst"));
+        assert!(txz_path.exists());
+        assert_eq!(
+            zstd::decode_all(File::open(&zst_path)?)?,
+            sample_text(200_000)
+        );
+
+        // A second run finds events.tar.zst in place.
+        assert!(matches!(
+            recompress_with_options(&txz_path, Codec::Zstd, None, &options),
+            Err(RecompressErr::OutputExists(path)) if path == zst_path
+        ));
+        let options = RecompressOptions {
+            overwrite: true,
+            ..options
+        };
+        recompress_with_options(&txz_path, Codec::Zstd, Some(3), &options)?;
+        assert_eq!(
+            zstd::decode_all(File::open(&zst_path)?)?,
+            sample_text(200_000)
+        );
+
+        assert!(matches!(
+            recompress(&zst_path, Codec::Bzip2, Some(0)),
+            Err(RecompressErr::InvalidLevel {
+                codec: Codec::Bzip2,
+                level: 0
+            })
+        ));
+        assert!(matches!(
+            recompress(&text_path, Codec::Gzip, None),
+            Err(RecompressErr::UnknownCodec(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn manifests_record_and_verify_entry_checksums() -> Result<(), Box<dyn Error>> {
+        use sha2::{Digest, Sha256};
+
+        let temp_dir = tempfile::tempdir()?;
+        let release = temp_dir.path().join("release");
+        fs::create_dir_all(release.join("bin"))?;
+        fs::write(release.join("bin/tool"), sample_text(70_000))?;
+        fs::write(release.join("README"), b"release notes\n")?;
+        fs::hard_link(release.join("README"), release.join("README.txt"))?;
+
+        // tar.gz with a `sha256sum` manifest entry; the hard link counts as a copy.
+        let tar_path = temp_dir.path().join("release.tar.gz");
+        let tar_options = TarOptions {
+            detect_hardlinks: true,
+            reproducible: Some(Reproducible::new(1_700_000_000)),
+            manifest: Some(ManifestOptions::default()),
+            ..TarOptions::default()
+        };
+        create_tar_gz_archive_with_options(&[&release], &tar_path, &tar_options)?;
+        let report = verify_manifest(&tar_path)?;
+        assert!(report.is_valid());
+        assert_eq!(
+            (report.algorithm, report.verified),
+            (ChecksumAlgorithm::Sha256, 3)
+        );
+        let destination = temp_dir.path().join("unpacked");
+        extract_tar_archive(&tar_path, &destination, &ExtractOptions::default())?;
+        let listed = fs::read_to_string(destination.join("MANIFEST.sha256"))?;
+        let digest = format!("{:x}", Sha256::digest(sample_text(70_000)));
+        assert!(listed.contains(&format!("{}  release/bin/tool\n", digest)));
+
+        // A file at the root may not shadow the manifest entry.
+        let own_manifest = temp_dir.path().join("MANIFEST.sha256");
+        fs::write(&own_manifest, b"not generated\n")?;
+        let flat_options = TarOptions {
+            transform: PathTransform::default().replace("^.*/", "")?,
+            ..tar_options.clone()
+        };
+        assert!(matches!(
+            create_tar_gz_archive_with_options(&[&own_manifest], &tar_path, &flat_options),
+            Err(TarErr::DuplicateEntry(name)) if name == Path::new("MANIFEST.sha256")
+        ));
+        let own_manifest_zip = temp_dir.path().join("own.zip");
+        let own_manifest_options = ZipOptions {
+            manifest: Some(ManifestOptions::default()),
+            ..ZipOptions::default()
+        };
+        assert!(matches!(
+            create_zip_archive_with_options(&[&own_manifest], &own_manifest_zip, &own_manifest_options),
+            Err(ZippingErr::DuplicateEntry(name)) if name == "MANIFEST.sha256"
+        ));
+
+        // zip with only a BLAKE3 sidecar, then tampered with.
+        let zip_path = temp_dir.path().join("release.zip");
+        let zip_options = ZipOptions {
+            manifest: Some(ManifestOptions {
+                algorithm: ChecksumAlgorithm::Blake3,
+                entry: false,
+                sidecar: true,
+            }),
+            ..ZipOptions::default()
+        };
+        create_zip_archive_with_options(&[&release], &zip_path, &zip_options)?;
+        let report = verify_manifest(&zip_path)?;
+        assert!(report.is_valid());
+        assert_eq!(
+            (report.algorithm, report.verified),
+            (ChecksumAlgorithm::Blake3, 3)
+        );
+
+        let sidecar = fs::read_to_string(sidecar_path(&zip_path))?;
+        let tool_digest = blake3::hash(&sample_text(70_000)).to_hex().to_string();
+        fs::write(
+            sidecar_path(&zip_path),
+            sidecar.replace(&tool_digest, &"0".repeat(64)),
+        )?;
+        fs::write(temp_dir.path().join("extra.txt"), b"added later")?;
+        append_to_zip_archive(
+            zip_path.clone(),
+            &[temp_dir.path().join("extra.txt")],
+            &ZipOptions::default(),
+        )?;
+        let report = verify_manifest(&zip_path)?;
+        assert!(!report.is_valid());
+        assert_eq!(report.mismatched, vec!["release/bin/tool".to_string()]);
+        assert_eq!(report.unlisted, vec!["extra.txt".to_string()]);
+
+        fs::remove_file(sidecar_path(&zip_path))?;
+        assert!(matches!(
+            verify_manifest(&zip_path),
+            Err(ManifestErr::ManifestNotFound(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn zip_entries_kept_by_edits_are_copied_verbatim() -> Result<(), Box<dyn Error>> {
+        let temp_dir = tempfile::tempdir()?;
+        let notes = temp_dir.path().join("notes.txt");
+        let extra = temp_dir.path().join("extra.txt");
+        fs::write(&notes, sample_text(2_000))?;
+        fs::write(&extra, b"more notes\n")?;
+
+        let archive = temp_dir.path().join("kept.zip");
+        create_zip_archive_with_options(&[&notes], &archive, &ZipOptions::default())?;
+        // Local header and data, and the central directory extra fields.
+        let stored = |archive: &Path| -> Result<(Vec<u8>, Vec<u8>), Box<dyn Error>> {
+            let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+            let entry = zip.by_name("notes.txt")?;
+            let start = entry.header_start();
+            let end = entry.data_start().unwrap_or_default() + entry.compressed_size();
+            let extra_fields = entry.extra_data().unwrap_or_default().to_vec();
+            let mut bytes = vec![0; (end - start) as usize];
+            let mut file = File::open(archive)?;
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(&mut bytes)?;
+            Ok((bytes, extra_fields))
+        };
+        let before = stored(&archive)?;
+        append_to_zip_archive(&archive, &[&extra], &ZipOptions::default())?;
+        assert_eq!(stored(&archive)?, before);
+
+        Ok(())
+    }
 }
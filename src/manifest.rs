@@ -0,0 +1,517 @@
+use crate::crypt::EncryptionKey;
+use crate::entry::{is_zip, ArchiveEntry, EntryKind};
+use crate::tar::{read_tar_entries, TarErr};
+use crate::zip::{read_zip_entries, ZipNameEncoding, ZipPassword, ZippingErr};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt, fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum ManifestErr {
+    ArchiveOpenFailed(io::Error),
+    TarReadFailed(TarErr),
+    ZipReadFailed(ZippingErr),
+    ManifestNotFound(PathBuf),
+    SidecarReadFailed(PathBuf, io::Error),
+    InvalidManifest(String),
+}
+
+impl fmt::Display for ManifestErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManifestErr::ArchiveOpenFailed(e) => {
+                write!(f, "Failed to open the archive: {}", e)
+            }
+            ManifestErr::TarReadFailed(e) => write!(f, "Failed to read the tar archive: {}", e),
+            ManifestErr::ZipReadFailed(e) => write!(f, "Failed to read the zip archive: {}", e),
+            ManifestErr::ManifestNotFound(path) => write!(
+                f,
+                "{} has no manifest entry and no manifest next to it",
+                path.display()
+            ),
+            ManifestErr::SidecarReadFailed(path, e) => {
+                write!(f, "Failed to read the manifest {}: {}", path.display(), e)
+            }
+            ManifestErr::InvalidManifest(reason) => write!(f, "Invalid manifest: {}", reason),
+        }
+    }
+}
+
+impl Error for ManifestErr {}
+
+impl From<TarErr> for ManifestErr {
+    fn from(error: TarErr) -> Self {
+        ManifestErr::TarReadFailed(error)
+    }
+}
+
+impl From<ZippingErr> for ManifestErr {
+    fn from(error: ZippingErr) -> Self {
+        ManifestErr::ZipReadFailed(error)
+    }
+}
+
+/// Hash recorded for each entry of a manifest.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl ChecksumAlgorithm {
+    const ALL: [ChecksumAlgorithm; 2] = [ChecksumAlgorithm::Sha256, ChecksumAlgorithm::Blake3];
+
+    /// Name of the manifest entry at the archive root, in the format of
+    /// `sha256sum` or `b3sum`: `MANIFEST.sha256` or `MANIFEST.blake3`.
+    pub fn manifest_name(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "MANIFEST.sha256",
+            ChecksumAlgorithm::Blake3 => "MANIFEST.blake3",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    fn from_manifest_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|algorithm| algorithm.manifest_name() == name)
+    }
+}
+
+/// Checksum manifest written by the tar and zip builders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestOptions {
+    pub algorithm: ChecksumAlgorithm,
+    /// Adds the manifest as the last entry of the archive, named by
+    /// `ChecksumAlgorithm::manifest_name`.
+    pub entry: bool,
+    /// Writes the manifest as JSON next to the archive, at
+    /// `<output>.manifest.json`.
+    pub sidecar: bool,
+}
+
+impl Default for ManifestOptions {
+    fn default() -> Self {
+        ManifestOptions {
+            algorithm: ChecksumAlgorithm::Sha256,
+            entry: true,
+            sidecar: false,
+        }
+    }
+}
+
+/// Path of the JSON manifest written next to `archive_path`.
+pub fn sidecar_path<P: AsRef<Path>>(archive_path: P) -> PathBuf {
+    let mut path = archive_path.as_ref().as_os_str().to_os_string();
+    path.push(".manifest.json");
+    PathBuf::from(path)
+}
+
+/// Hash state of one entry, fed with its data as it is written or read.
+pub(crate) struct EntryHasher {
+    state: HashState,
+    size: u64,
+}
+
+enum HashState {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl EntryHasher {
+    fn new(algorithm: ChecksumAlgorithm) -> Self {
+        let state = match algorithm {
+            ChecksumAlgorithm::Sha256 => HashState::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Blake3 => HashState::Blake3(Box::default()),
+        };
+        EntryHasher { state, size: 0 }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match &mut self.state {
+            HashState::Sha256(hasher) => hasher.update(data),
+            HashState::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+        self.size += data.len() as u64;
+    }
+
+    /// Lowercase hex digest of the data seen.
+    fn finish(self) -> String {
+        match self.state {
+            HashState::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            HashState::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+impl Write for EntryHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Passes the data of an entry through to the archive writer while hashing it.
+pub(crate) struct HashingReader<'a, R> {
+    inner: R,
+    hasher: &'a mut EntryHasher,
+}
+
+impl<'a, R: Read> HashingReader<'a, R> {
+    pub(crate) fn new(inner: R, hasher: &'a mut EntryHasher) -> Self {
+        HashingReader { inner, hasher }
+    }
+}
+
+impl<R: Read> Read for HashingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ManifestEntry {
+    name: String,
+    /// Recorded only in sidecar manifests.
+    size: Option<u64>,
+    digest: String,
+}
+
+/// Manifest collected while an archive is written.
+pub(crate) struct ManifestBuilder {
+    options: ManifestOptions,
+    entries: Vec<ManifestEntry>,
+    /// Position of each name in `entries`, for hard links.
+    index: HashMap<String, usize>,
+}
+
+impl ManifestBuilder {
+    pub(crate) fn new(options: &ManifestOptions) -> Self {
+        ManifestBuilder {
+            options: options.clone(),
+            entries: Vec::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn hasher(&self) -> EntryHasher {
+        EntryHasher::new(self.options.algorithm)
+    }
+
+    /// Records the file `name` with the data `hasher` has seen.
+    pub(crate) fn add(&mut self, name: String, hasher: EntryHasher) {
+        let size = Some(hasher.size);
+        let digest = hasher.finish();
+        self.push(ManifestEntry { name, size, digest });
+    }
+
+    /// Records the hard link `name` with the checksum of its target.
+    pub(crate) fn add_link(&mut self, name: String, target: &str) {
+        if let Some(&position) = self.index.get(target) {
+            let target = &self.entries[position];
+            let (size, digest) = (target.size, target.digest.clone());
+            self.push(ManifestEntry { name, size, digest });
+        }
+    }
+
+    fn push(&mut self, entry: ManifestEntry) {
+        self.index.insert(entry.name.clone(), self.entries.len());
+        self.entries.push(entry);
+    }
+
+    /// Name and contents of the manifest entry, unless `options.entry` is off.
+    pub(crate) fn entry(&self) -> Option<(&'static str, Vec<u8>)> {
+        self.options.entry.then(|| {
+            let name = self.options.algorithm.manifest_name();
+            (name, manifest_text(&self.entries).into_bytes())
+        })
+    }
+
+    /// Writes the sidecar manifest of the archive at `archive_path`, if
+    /// `options.sidecar` is set.
+    pub(crate) fn write_sidecar(&self, archive_path: &Path) -> io::Result<()> {
+        if !self.options.sidecar {
+            return Ok(());
+        }
+        let entries: Vec<Value> = self
+            .entries
+            .iter()
+            .map(|entry| json!({ "name": entry.name, "size": entry.size, "digest": entry.digest }))
+            .collect();
+        let manifest = json!({ "algorithm": self.options.algorithm.label(), "entries": entries });
+        let mut json = serde_json::to_string_pretty(&manifest)?;
+        json.push('\n');
+        fs::write(sidecar_path(archive_path), json)
+    }
+}
+
+/// Lines of `sha256sum`/`b3sum` output. Names holding a backslash or a line
+/// break are escaped and their line starts with a backslash, as those tools do.
+fn manifest_text(entries: &[ManifestEntry]) -> String {
+    let mut text = String::new();
+    for entry in entries {
+        let escaped = entry
+            .name
+            .replace('\\', "\\\\")
+            .replace('\n', "\\n")
+            .replace('\r', "\\r");
+        if escaped != entry.name {
+            text.push('\\');
+        }
+        text.push_str(&format!("{}  {}\n", entry.digest, escaped));
+    }
+    text
+}
+
+fn parse_manifest_text(text: &str) -> Result<Vec<ManifestEntry>, ManifestErr> {
+    let mut entries = Vec::new();
+    for line in text.lines().filter(|line| !line.is_empty()) {
+        let (escaped, line) = match line.strip_prefix('\\') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let invalid = || ManifestErr::InvalidManifest(format!("malformed line {:?}", line));
+        let (digest, name) = line.split_once(' ').ok_or_else(invalid)?;
+        // Text (` `) or binary (`*`) mode marker.
+        let name = name
+            .strip_prefix(' ')
+            .or_else(|| name.strip_prefix('*'))
+            .ok_or_else(invalid)?;
+        let name = match escaped {
+            true => unescape(name).ok_or_else(invalid)?,
+            false => name.to_string(),
+        };
+        entries.push(ManifestEntry {
+            name,
+            size: None,
+            digest: digest.to_lowercase(),
+        });
+    }
+    Ok(entries)
+}
+
+fn unescape(name: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(match c {
+            '\\' => match chars.next()? {
+                '\\' => '\\',
+                'n' => '\n',
+                'r' => '\r',
+                _ => return None,
+            },
+            c => c,
+        });
+    }
+    Some(unescaped)
+}
+
+fn parse_sidecar(json: &str) -> Result<(ChecksumAlgorithm, Vec<ManifestEntry>), ManifestErr> {
+    let invalid = |reason: &str| ManifestErr::InvalidManifest(reason.to_string());
+    let manifest: Value =
+        serde_json::from_str(json).map_err(|e| ManifestErr::InvalidManifest(e.to_string()))?;
+    let label = manifest["algorithm"]
+        .as_str()
+        .ok_or_else(|| invalid("no algorithm"))?;
+    let algorithm = ChecksumAlgorithm::ALL
+        .into_iter()
+        .find(|algorithm| algorithm.label() == label)
+        .ok_or_else(|| ManifestErr::InvalidManifest(format!("unknown algorithm {}", label)))?;
+    let entries = manifest["entries"]
+        .as_array()
+        .ok_or_else(|| invalid("no entries"))?
+        .iter()
+        .map(|entry| {
+            Some(ManifestEntry {
+                name: entry["name"].as_str()?.to_string(),
+                size: Some(entry["size"].as_u64()?),
+                digest: entry["digest"].as_str()?.to_lowercase(),
+            })
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| invalid("entry without a name, size or digest"))?;
+    Ok((algorithm, entries))
+}
+
+/// Settings for `verify_manifest_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct VerifyManifestOptions {
+    /// JSON manifest to check against instead of the archive's manifest entry.
+    pub sidecar: Option<PathBuf>,
+    /// Passwords of encrypted zip entries.
+    pub password: ZipPassword,
+    /// Fallback encoding of zip entry names without the UTF-8 flag.
+    pub name_encoding: ZipNameEncoding,
+    /// Key for tar archives encrypted with age.
+    pub decryption: Option<EncryptionKey>,
+}
+
+/// Outcome of `verify_manifest`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestReport {
+    pub algorithm: ChecksumAlgorithm,
+    /// Number of files whose checksum matches the manifest.
+    pub verified: usize,
+    /// Files whose contents differ from the manifest.
+    pub mismatched: Vec<String>,
+    /// Files in the manifest that the archive lacks.
+    pub missing: Vec<String>,
+    /// Files of the archive that the manifest does not list.
+    pub unlisted: Vec<String>,
+}
+
+impl ManifestReport {
+    pub fn is_valid(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty() && self.unlisted.is_empty()
+    }
+}
+
+pub fn verify_manifest<P: AsRef<Path>>(archive_path: P) -> Result<ManifestReport, ManifestErr> {
+    verify_manifest_with_options(archive_path, &VerifyManifestOptions::default())
+}
+
+/// Hashes every file of a zip or tar archive and compares it with the
+/// manifest: the archive's manifest entry, or else `<archive>.manifest.json`.
+/// Hard links count as copies of their target; directories and other
+/// entries without data are not listed.
+pub fn verify_manifest_with_options<P: AsRef<Path>>(
+    archive_path: P,
+    options: &VerifyManifestOptions,
+) -> Result<ManifestReport, ManifestErr> {
+    let archive_path = archive_path.as_ref();
+    let read_sidecar = |path: &Path| {
+        let json = fs::read_to_string(path)
+            .map_err(|e| ManifestErr::SidecarReadFailed(path.to_path_buf(), e))?;
+        parse_sidecar(&json)
+    };
+    let manifest = options.sidecar.as_deref().map(read_sidecar).transpose()?;
+    // The manifest entry comes last, so without a sidecar both algorithms are computed.
+    let algorithms = match &manifest {
+        Some((algorithm, _)) => vec![*algorithm],
+        None => ChecksumAlgorithm::ALL.to_vec(),
+    };
+
+    let zip = is_zip(archive_path).map_err(ManifestErr::ArchiveOpenFailed)?;
+    let read_error = |name: &str, e: io::Error| match zip {
+        true => ManifestErr::ZipReadFailed(ZippingErr::CorruptEntry(name.to_string(), e)),
+        false => ManifestErr::TarReadFailed(TarErr::ExtractionFailed(e)),
+    };
+    let mut files: Vec<(String, u64, Vec<String>)> = Vec::new();
+    let mut index = HashMap::new();
+    let mut embedded = None;
+    let add = |entry: ArchiveEntry, data: &mut dyn Read| -> Result<(), ManifestErr> {
+        let (size, digests) = match &entry.kind {
+            EntryKind::File => {
+                if let Some(algorithm) = ChecksumAlgorithm::from_manifest_name(&entry.name) {
+                    let mut text = String::new();
+                    data.read_to_string(&mut text)
+                        .map_err(|e| read_error(&entry.name, e))?;
+                    embedded = Some((algorithm, text));
+                    return Ok(());
+                }
+                let mut hashers: Vec<_> = algorithms.iter().map(|&a| EntryHasher::new(a)).collect();
+                let mut buffer = vec![0u8; 64 * 1024];
+                loop {
+                    let read = data
+                        .read(&mut buffer)
+                        .map_err(|e| read_error(&entry.name, e))?;
+                    if read == 0 {
+                        break;
+                    }
+                    for hasher in &mut hashers {
+                        hasher.update(&buffer[..read]);
+                    }
+                }
+                let size = hashers.first().map_or(0, |hasher| hasher.size);
+                (size, hashers.into_iter().map(EntryHasher::finish).collect())
+            }
+            EntryKind::HardLink(target) => match index.get(target) {
+                Some(&position) => {
+                    let (_, size, digests): &(String, u64, Vec<String>) = &files[position];
+                    (*size, digests.clone())
+                }
+                None => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+        index.insert(entry.name.clone(), files.len());
+        files.push((entry.name, size, digests));
+        Ok(())
+    };
+    match zip {
+        true => read_zip_entries(archive_path, &options.password, options.name_encoding, add)
+            .map(|_| ()),
+        false => read_tar_entries(archive_path, options.decryption.as_ref(), add).map(|_| ()),
+    }?;
+
+    let (algorithm, listed) = match (manifest, embedded) {
+        (Some(manifest), _) => manifest,
+        (None, Some((algorithm, text))) => (algorithm, parse_manifest_text(&text)?),
+        (None, None) => {
+            let sidecar = sidecar_path(archive_path);
+            if !sidecar.exists() {
+                return Err(ManifestErr::ManifestNotFound(archive_path.to_path_buf()));
+            }
+            read_sidecar(&sidecar)?
+        }
+    };
+    let Some(column) = algorithms.iter().position(|&a| a == algorithm) else {
+        return Err(ManifestErr::InvalidManifest(format!(
+            "{} checksums were not computed",
+            algorithm.label()
+        )));
+    };
+
+    let mut report = ManifestReport {
+        algorithm,
+        verified: 0,
+        mismatched: Vec::new(),
+        missing: Vec::new(),
+        unlisted: Vec::new(),
+    };
+    let mut seen = HashSet::new();
+    for entry in &listed {
+        seen.insert(entry.name.as_str());
+        match index.get(&entry.name) {
+            None => report.missing.push(entry.name.clone()),
+            Some(&position) => {
+                let (_, size, digests) = &files[position];
+                if digests[column] == entry.digest
+                    && entry.size.is_none_or(|listed| listed == *size)
+                {
+                    report.verified += 1;
+                } else {
+                    report.mismatched.push(entry.name.clone());
+                }
+            }
+        }
+    }
+    report.unlisted = files
+        .iter()
+        .filter(|(name, ..)| !seen.contains(name.as_str()))
+        .map(|(name, ..)| name.clone())
+        .collect();
+    Ok(report)
+}
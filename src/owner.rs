@@ -0,0 +1,217 @@
+use std::{fmt, fs::Metadata, sync::Arc};
+
+/// Owner of an archive entry: numeric ids plus the optional user and group
+/// names stored next to them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Owner {
+    pub uid: u64,
+    pub gid: u64,
+    pub uname: Option<String>,
+    pub gname: Option<String>,
+}
+
+impl Owner {
+    pub fn new(uid: u64, gid: u64) -> Self {
+        Owner {
+            uid,
+            gid,
+            ..Owner::default()
+        }
+    }
+
+    /// `root:root` with ids 0.
+    pub fn root() -> Self {
+        Owner::new(0, 0).names("root", "root")
+    }
+
+    pub fn names<U: Into<String>, G: Into<String>>(mut self, uname: U, gname: G) -> Self {
+        self.uname = Some(uname.into());
+        self.gname = Some(gname.into());
+        self
+    }
+}
+
+/// Rewrites the owner of every entry when creating or extracting a tar archive.
+#[derive(Clone, Default)]
+pub enum OwnerMapping {
+    /// Use the owner found on disk (or in the archive) unchanged.
+    #[default]
+    Keep,
+    /// Give every entry this owner.
+    Fixed(Owner),
+    /// Compute the owner from the original one.
+    Map(Arc<dyn Fn(&Owner) -> Owner + Send + Sync>),
+}
+
+impl OwnerMapping {
+    pub fn map<F: Fn(&Owner) -> Owner + Send + Sync + 'static>(mapping: F) -> Self {
+        OwnerMapping::Map(Arc::new(mapping))
+    }
+
+    pub fn apply(&self, owner: Owner) -> Owner {
+        match self {
+            OwnerMapping::Keep => owner,
+            OwnerMapping::Fixed(fixed) => fixed.clone(),
+            OwnerMapping::Map(mapping) => mapping(&owner),
+        }
+    }
+}
+
+impl fmt::Debug for OwnerMapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OwnerMapping::Keep => write!(f, "Keep"),
+            OwnerMapping::Fixed(owner) => f.debug_tuple("Fixed").field(owner).finish(),
+            OwnerMapping::Map(_) => write!(f, "Map(..)"),
+        }
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn metadata_owner(metadata: &Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.uid().into(), metadata.gid().into())
+}
+
+#[cfg(not(unix))]
+pub(crate) fn metadata_owner(_metadata: &Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
+/// Runs a reentrant passwd/group lookup, growing the buffer on `ERANGE`.
+#[cfg(unix)]
+fn lookup<T, F>(mut call: F) -> Option<T>
+where
+    F: FnMut(&mut [libc::c_char]) -> Result<Option<T>, libc::c_int>,
+{
+    let mut buffer = vec![0 as libc::c_char; 1024];
+    loop {
+        match call(&mut buffer) {
+            Ok(found) => return found,
+            Err(libc::ERANGE) if buffer.len() < 1 << 20 => {
+                let len = buffer.len() * 2;
+                buffer.resize(len, 0);
+            }
+            Err(_) => return None,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn c_string(name: &str) -> Option<std::ffi::CString> {
+    std::ffi::CString::new(name).ok()
+}
+
+#[cfg(unix)]
+unsafe fn owned_name(name: *const libc::c_char) -> Option<String> {
+    std::ffi::CStr::from_ptr(name)
+        .to_str()
+        .ok()
+        .map(String::from)
+}
+
+/// Local user name for `uid`.
+#[cfg(unix)]
+pub(crate) fn user_name(uid: u64) -> Option<String> {
+    let uid = libc::uid_t::try_from(uid).ok()?;
+    lookup(|buffer| {
+        let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let code = unsafe {
+            libc::getpwuid_r(
+                uid,
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match code {
+            0 if result.is_null() => Ok(None),
+            0 => Ok(unsafe { owned_name(entry.pw_name) }),
+            code => Err(code),
+        }
+    })
+}
+
+/// Local group name for `gid`.
+#[cfg(unix)]
+pub(crate) fn group_name(gid: u64) -> Option<String> {
+    let gid = libc::gid_t::try_from(gid).ok()?;
+    lookup(|buffer| {
+        let mut entry: libc::group = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let code = unsafe {
+            libc::getgrgid_r(
+                gid,
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match code {
+            0 if result.is_null() => Ok(None),
+            0 => Ok(unsafe { owned_name(entry.gr_name) }),
+            code => Err(code),
+        }
+    })
+}
+
+/// Local uid of the user called `name`.
+#[cfg(unix)]
+pub(crate) fn user_id(name: &str) -> Option<u64> {
+    let name = c_string(name)?;
+    lookup(|buffer| {
+        let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let code = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match code {
+            0 if result.is_null() => Ok(None),
+            0 => Ok(Some(entry.pw_uid.into())),
+            code => Err(code),
+        }
+    })
+}
+
+/// Local gid of the group called `name`.
+#[cfg(unix)]
+pub(crate) fn group_id(name: &str) -> Option<u64> {
+    let name = c_string(name)?;
+    lookup(|buffer| {
+        let mut entry: libc::group = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let code = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match code {
+            0 if result.is_null() => Ok(None),
+            0 => Ok(Some(entry.gr_gid.into())),
+            code => Err(code),
+        }
+    })
+}
+
+#[cfg(not(unix))]
+pub(crate) fn user_name(_uid: u64) -> Option<String> {
+    None
+}
+
+#[cfg(not(unix))]
+pub(crate) fn group_name(_gid: u64) -> Option<String> {
+    None
+}
@@ -0,0 +1,318 @@
+use crate::utils::{create_temp_file, make_permanent, UtilsErr};
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum SplitErr {
+    TempFileCreationFailed(UtilsErr),
+    FileOpenFailed(io::Error),
+    WriteFailed(io::Error),
+    InvalidVolumeSize(u64),
+    TempFileFinalizationFailed(UtilsErr),
+}
+
+impl fmt::Display for SplitErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SplitErr::TempFileCreationFailed(e) => {
+                write!(f, "Temporary file creation failed: {}", e)
+            }
+            SplitErr::FileOpenFailed(e) => write!(f, "Failed to open the file to split: {}", e),
+            SplitErr::WriteFailed(e) => write!(f, "Failed to write a volume: {}", e),
+            SplitErr::InvalidVolumeSize(size) => write!(f, "Invalid volume size: {} bytes", size),
+            SplitErr::TempFileFinalizationFailed(e) => {
+                write!(f, "Failed to finalize a volume: {}", e)
+            }
+        }
+    }
+}
+
+impl Error for SplitErr {}
+
+/// Path of volume `number` (counting from 1) of a byte-split file:
+/// `backup.tar.xz.part001`, `backup.tar.xz.part002`, ...
+pub fn volume_path<P: AsRef<Path>>(path: P, number: usize) -> PathBuf {
+    let mut volume = path.as_ref().as_os_str().to_os_string();
+    volume.push(format!(".part{:03}", number));
+    PathBuf::from(volume)
+}
+
+/// Splits `input_path` into volumes of `volume_size` bytes (the last one may
+/// be shorter) named `<input_path>.part001`, `.part002`, ..., leaving the
+/// input in place. Joined in order, the volumes are the original file, so
+/// any compressed stream or archive can be split; see `VolumeReader`.
+pub fn split_file<P: AsRef<Path>>(
+    input_path: P,
+    volume_size: u64,
+) -> Result<Vec<PathBuf>, SplitErr> {
+    split_into_volumes(input_path.as_ref(), input_path.as_ref(), volume_size)
+}
+
+/// Writes `source` as the volumes `<output_path>.part001`, ... Each volume
+/// goes through a temporary file like any other output, and volumes left
+/// over from an earlier, longer split are removed.
+pub(crate) fn split_into_volumes(
+    source: &Path,
+    output_path: &Path,
+    volume_size: u64,
+) -> Result<Vec<PathBuf>, SplitErr> {
+    if volume_size == 0 {
+        return Err(SplitErr::InvalidVolumeSize(volume_size));
+    }
+    let (temp_dir, _) = create_temp_file("part").map_err(SplitErr::TempFileCreationFailed)?;
+    let mut input = File::open(source).map_err(SplitErr::FileOpenFailed)?;
+    let mut volumes = VolumeWriter::new(temp_dir.path(), volume_size);
+    io::copy(&mut input, &mut volumes).map_err(SplitErr::WriteFailed)?;
+    let temp_volumes = volumes.finish().map_err(SplitErr::WriteFailed)?;
+
+    let outputs: Vec<PathBuf> = (1..=temp_volumes.len())
+        .map(|number| volume_path(output_path, number))
+        .collect();
+    let (last, others) = temp_volumes
+        .split_last()
+        .ok_or_else(|| SplitErr::WriteFailed(io::ErrorKind::NotFound.into()))?;
+    for (temp_volume, output) in others.iter().zip(&outputs) {
+        fs::rename(temp_volume, output).map_err(SplitErr::WriteFailed)?;
+    }
+    make_permanent(temp_dir, last, &outputs[outputs.len() - 1])
+        .map_err(SplitErr::TempFileFinalizationFailed)?;
+    remove_volumes_after(output_path, outputs.len()).map_err(SplitErr::WriteFailed)?;
+    Ok(outputs)
+}
+
+/// Removes the volumes of `path` numbered above `count`, so that readers
+/// do not append them to a newer, shorter set.
+pub(crate) fn remove_volumes_after(path: &Path, count: usize) -> io::Result<()> {
+    for stale in (count + 1..)
+        .map(|number| volume_path(path, number))
+        .take_while(|volume| volume.exists())
+    {
+        fs::remove_file(stale)?;
+    }
+    Ok(())
+}
+
+/// Writes a stream as numbered volumes of at most `volume_size` bytes in
+/// `directory`, opening each volume when its first byte is written.
+pub(crate) struct VolumeWriter<'a> {
+    directory: &'a Path,
+    volume_size: u64,
+    volumes: Vec<PathBuf>,
+    current: Option<BufWriter<File>>,
+    /// Bytes written to the current volume.
+    written: u64,
+}
+
+impl<'a> VolumeWriter<'a> {
+    pub(crate) fn new(directory: &'a Path, volume_size: u64) -> Self {
+        VolumeWriter {
+            directory,
+            volume_size,
+            volumes: Vec::new(),
+            current: None,
+            written: 0,
+        }
+    }
+
+    /// Volume number (counting from 0) and offset within it of the next byte.
+    pub(crate) fn position(&self) -> (u32, u64) {
+        match self.current {
+            Some(_) if self.written < self.volume_size => {
+                (self.volumes.len() as u32 - 1, self.written)
+            }
+            _ => (self.volumes.len() as u32, 0),
+        }
+    }
+
+    /// Starts a new volume unless the next `len` bytes fit in the current one,
+    /// so that a record is never split across volumes.
+    pub(crate) fn keep_together(&mut self, len: u64) -> io::Result<()> {
+        if len > self.volume_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a record is larger than the volume size",
+            ));
+        }
+        if self.current.is_some() && self.written + len > self.volume_size {
+            self.close_volume()?;
+        }
+        Ok(())
+    }
+
+    /// Flushes the last volume and returns the paths of all volumes; there
+    /// is always at least one, if empty.
+    pub(crate) fn finish(mut self) -> io::Result<Vec<PathBuf>> {
+        if self.volumes.is_empty() {
+            self.open_volume()?;
+        }
+        self.close_volume()?;
+        Ok(self.volumes)
+    }
+
+    fn open_volume(&mut self) -> io::Result<()> {
+        let path = self
+            .directory
+            .join(format!("volume.{:03}", self.volumes.len() + 1));
+        self.current = Some(BufWriter::new(File::create(&path)?));
+        self.volumes.push(path);
+        self.written = 0;
+        Ok(())
+    }
+
+    fn close_volume(&mut self) -> io::Result<()> {
+        if let Some(mut volume) = self.current.take() {
+            volume.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for VolumeWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.current.is_some() && self.written == self.volume_size {
+            self.close_volume()?;
+        }
+        if self.current.is_none() {
+            self.open_volume()?;
+        }
+        let room = usize::try_from(self.volume_size - self.written).unwrap_or(usize::MAX);
+        let volume = self.current.as_mut().ok_or(io::ErrorKind::NotConnected)?;
+        let written = volume.write(&buf[..buf.len().min(room)])?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.current {
+            Some(volume) => volume.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reads a sequence of volumes as one file, seekable across volumes. Each
+/// volume is opened when it is first read.
+#[derive(Debug)]
+pub struct VolumeReader {
+    /// Each volume with the offset of its first byte in the joined file.
+    volumes: Vec<(PathBuf, u64)>,
+    len: u64,
+    position: u64,
+    /// The open volume, with the joined-file offset of its cursor.
+    current: Option<(usize, File, u64)>,
+}
+
+impl VolumeReader {
+    pub fn new(volumes: Vec<PathBuf>) -> io::Result<Self> {
+        let mut len = 0;
+        let mut starts = Vec::with_capacity(volumes.len());
+        for volume in volumes {
+            let volume_len = fs::metadata(&volume)?.len();
+            starts.push((volume, len));
+            len += volume_len;
+        }
+        Ok(VolumeReader {
+            volumes: starts,
+            len,
+            position: 0,
+            current: None,
+        })
+    }
+
+    /// Opens `path` itself if it exists, else the volumes `<path>.part001`,
+    /// `.part002`, ... Given the path of a `.part001` volume, the volumes
+    /// following it are read too.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let base = path
+            .to_str()
+            .and_then(|path| path.strip_suffix(".part001"))
+            .map(PathBuf::from);
+        let base = match base {
+            Some(base) => base,
+            None if path.exists() || !volume_path(path, 1).exists() => {
+                return VolumeReader::new(vec![path.to_path_buf()])
+            }
+            None => path.to_path_buf(),
+        };
+        let volumes = (1..)
+            .map(|number| volume_path(&base, number))
+            .take_while(|volume| volume.exists())
+            .collect();
+        VolumeReader::new(volumes)
+    }
+
+    /// Total length of the volumes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Offset in the joined file of the first byte of each volume.
+    pub fn volume_starts(&self) -> Vec<u64> {
+        self.volumes.iter().map(|(_, start)| *start).collect()
+    }
+}
+
+impl Read for VolumeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.len {
+            return Ok(0);
+        }
+        let index = self
+            .volumes
+            .partition_point(|(_, start)| *start <= self.position)
+            - 1;
+        let (path, start) = &self.volumes[index];
+        let end = self
+            .volumes
+            .get(index + 1)
+            .map_or(self.len, |(_, end)| *end);
+        let current = match self.current.take() {
+            Some((open, file, cursor)) if open == index => (open, file, cursor),
+            _ => (index, File::open(path)?, u64::MAX),
+        };
+        let (_, file, cursor) = self.current.insert(current);
+        if *cursor != self.position {
+            file.seek(SeekFrom::Start(self.position - start))?;
+        }
+        let len = buf
+            .len()
+            .min(usize::try_from(end - self.position).unwrap_or(usize::MAX));
+        let read = file.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "a volume is shorter than when it was opened",
+            ));
+        }
+        self.position += read as u64;
+        *cursor = self.position;
+        Ok(read)
+    }
+}
+
+impl Seek for VolumeReader {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek before the first volume")
+        })?;
+        Ok(self.position)
+    }
+}
@@ -1,20 +1,52 @@
-use crate::utils::{create_temp_file, make_permanent, UtilsErr};
+use crate::codec::{decoder, Codec, CodecWriter};
+use crate::crypt::{CryptErr, DecryptReader, EncryptionKey, EncryptionLayer, AGE_MAGIC};
+use crate::entry::{ArchiveEntry, EntryKind};
+use crate::filter::{resolve_files, FileFilter, FilterErr};
+use crate::gzip::{GzipErr, GzipOptions, GzipWriter};
+use crate::manifest::{EntryHasher, HashingReader, ManifestBuilder, ManifestOptions};
+#[cfg(unix)]
+use crate::owner::{group_id, user_id};
+use crate::owner::{group_name, metadata_owner, user_name, Owner, OwnerMapping};
+use crate::split::{remove_volumes_after, split_into_volumes, SplitErr, VolumeReader};
+use crate::transform::{PathTransform, TransformErr};
+use crate::utils::{
+    contained_path, create_temp_file, link_escapes, make_permanent, metadata_mode, modified_secs,
+    prepare_parent, resolve_on_disk, Reproducible, UtilsErr,
+};
 use bzip2::write::BzEncoder;
-use flate2::write::GzEncoder;
 use std::{
+    collections::{HashMap, HashSet},
     error::Error,
-    fs::{self, File},
-    io::{self, Write},
-    path::Path,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use tar::Builder;
+use tar::{Archive, Builder, EntryType, Header, HeaderMode};
+use tempfile::TempDir;
 use xz2::write::XzEncoder;
 
 #[derive(Debug)]
 pub enum TarErr {
     TempFileCreationFailed(UtilsErr),
     ArchiveCreationFailed(io::Error),
+    CompressionSetupFailed(GzipErr),
+    FileSelectionFailed(FilterErr),
+    PathTransformFailed(TransformErr),
+    SymlinkRejected(PathBuf),
+    ExtractionFailed(io::Error),
+    UnsafeEntryPath(PathBuf),
+    UnsafeLinkTarget { path: PathBuf, target: PathBuf },
+    XattrFailed(PathBuf, io::Error),
+    OwnershipFailed(PathBuf, io::Error),
+    UnrepresentableEntry { path: PathBuf, reason: &'static str },
+    NotAppendable { path: PathBuf, reason: &'static str },
+    DuplicateEntry(PathBuf),
+    EncryptionFailed(CryptErr),
     TempFileFinalizationFailed(UtilsErr),
+    VolumeSplitFailed(SplitErr),
+    ManifestWriteFailed(io::Error),
+    UnsupportedAppendOption(&'static str),
 }
 
 impl Error for TarErr {}
@@ -24,92 +56,1961 @@ impl std::fmt::Display for TarErr {
         match self {
             TarErr::TempFileCreationFailed(e) => write!(f, "Temporary file creation failed: {}", e),
             TarErr::ArchiveCreationFailed(e) => write!(f, "Failed to create tar archive: {}", e),
+            TarErr::CompressionSetupFailed(e) => {
+                write!(f, "Failed to set up archive compression: {}", e)
+            }
+            TarErr::FileSelectionFailed(e) => write!(f, "Failed to select input files: {}", e),
+            TarErr::PathTransformFailed(e) => write!(f, "Failed to rewrite an entry path: {}", e),
+            TarErr::SymlinkRejected(path) => {
+                write!(f, "Symbolic link rejected by policy: {}", path.display())
+            }
+            TarErr::ExtractionFailed(e) => write!(f, "Failed to extract tar archive: {}", e),
+            TarErr::UnsafeEntryPath(path) => {
+                write!(f, "Entry path escapes the destination: {}", path.display())
+            }
+            TarErr::UnsafeLinkTarget { path, target } => write!(
+                f,
+                "Link {} points outside the destination: {}",
+                path.display(),
+                target.display()
+            ),
+            TarErr::XattrFailed(path, e) => write!(
+                f,
+                "Failed to access extended attributes of {}: {}",
+                path.display(),
+                e
+            ),
+            TarErr::OwnershipFailed(path, e) => {
+                write!(
+                    f,
+                    "Failed to restore the owner of {}: {}",
+                    path.display(),
+                    e
+                )
+            }
+            TarErr::UnrepresentableEntry { path, reason } => write!(
+                f,
+                "Entry {} cannot be stored in the selected tar format: {}",
+                path.display(),
+                reason
+            ),
+            TarErr::NotAppendable { path, reason } => {
+                write!(f, "Cannot append to {}: {}", path.display(), reason)
+            }
+            TarErr::DuplicateEntry(path) => {
+                write!(
+                    f,
+                    "The archive already contains an entry named {}",
+                    path.display()
+                )
+            }
+            TarErr::EncryptionFailed(e) => write!(f, "Archive encryption failed: {}", e),
             TarErr::TempFileFinalizationFailed(e) => {
                 write!(f, "Failed to finalize the temporary file: {}", e)
             }
+            TarErr::VolumeSplitFailed(e) => write!(f, "Failed to split the archive: {}", e),
+            TarErr::ManifestWriteFailed(e) => write!(f, "Failed to write the manifest: {}", e),
+            TarErr::UnsupportedAppendOption(option) => write!(
+                f,
+                "TarOptions::{} cannot be applied when appending in place",
+                option
+            ),
         }
     }
 }
 
+/// Settings shared by the `create_tar_*_with_options` builders.
+#[derive(Debug, Clone, Default)]
+pub struct TarOptions {
+    /// Header and rsyncable settings used by the gzip-compressed variant.
+    pub gzip: GzipOptions,
+    /// Sorts entries and normalises their metadata for reproducible output.
+    pub reproducible: Option<Reproducible>,
+    /// Include/exclude patterns and walk limits applied to directory inputs.
+    pub filter: FileFilter,
+    /// How symbolic links among the inputs are stored.
+    pub symlinks: SymlinkPolicy,
+    /// Store files seen again under another name (same device and inode) as
+    /// hardlink entries instead of a second copy of the data.
+    pub detect_hardlinks: bool,
+    /// Store extended attributes (including POSIX ACLs and file capabilities)
+    /// matching the filter as `SCHILY.xattr.*` PAX records.
+    pub xattrs: Option<XattrFilter>,
+    /// Detect holes with `SEEK_DATA`/`SEEK_HOLE` (Linux) and store files that
+    /// have them as PAX 1.0 sparse entries containing only their data.
+    pub sparse: bool,
+    /// Header layout used for every entry.
+    pub format: TarFormat,
+    /// Rewrites the owner read from disk, e.g. to `root:root` for rootless builds.
+    pub owner: OwnerMapping,
+    /// Store only numeric ids, without user and group names.
+    pub numeric_owner: bool,
+    /// Rewrites entry names; symbolic link targets are stored unchanged.
+    pub transform: PathTransform,
+    /// Encrypts the (compressed) archive with age.
+    pub encryption: Option<EncryptionKey>,
+    /// Writes the archive as volumes of at most this many bytes,
+    /// `<output>.part001`, `<output>.part002`, ..., instead of one file.
+    pub volume_size: Option<u64>,
+    /// Checksums every file as it is written and stores them in a manifest
+    /// entry or a JSON file next to the archive. Only used by the
+    /// `create_tar_*` builders; appending fails when it is set.
+    pub manifest: Option<ManifestOptions>,
+}
+
+/// Header layout of created tar archives.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TarFormat {
+    /// POSIX.1-1988 ustar: paths up to 255 bytes, link targets up to 100
+    /// bytes, ids up to 2097151 and files below 8 GiB. Anything else,
+    /// including extended attributes and sparse files, is an error.
+    Ustar,
+    /// GNU tar: `././@LongLink` entries for long names and base-256 numbers.
+    #[default]
+    Gnu,
+    /// POSIX.1-2001 pax: ustar headers plus extended header records for
+    /// values that do not fit, and nanosecond modification times.
+    Pax,
+}
+
+/// Selects extended attributes by name prefix, such as `user.`,
+/// `security.capability` or `system.posix_acl_access`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XattrFilter {
+    /// When non-empty, only attributes starting with one of these prefixes are kept.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl XattrFilter {
+    /// Every extended attribute.
+    pub fn all() -> Self {
+        XattrFilter::default()
+    }
+
+    pub fn include<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.include.push(prefix.into());
+        self
+    }
+
+    pub fn exclude<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.exclude.push(prefix.into());
+        self
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        let included = self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|prefix| name.starts_with(prefix.as_str()));
+        included
+            && !self
+                .exclude
+                .iter()
+                .any(|prefix| name.starts_with(prefix.as_str()))
+    }
+}
+
+/// What to do with symbolic links when creating or extracting an archive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Store (or restore) the file the link points to instead of the link.
+    #[default]
+    Follow,
+    /// Store (or restore) the link itself.
+    Preserve,
+    /// Fail when a link is encountered.
+    Reject,
+}
+
 pub fn create_tar_archive<P: AsRef<Path>>(files: &[P], output_path: P) -> Result<(), TarErr> {
+    create_tar_archive_with_options(files, output_path, &TarOptions::default())
+}
+
+pub fn create_tar_archive_with_options<P: AsRef<Path>>(
+    files: &[P],
+    output_path: P,
+    options: &TarOptions,
+) -> Result<(), TarErr> {
     let (temp_dir, temp_file_path) =
         create_temp_file("tar").map_err(TarErr::TempFileCreationFailed)?;
-    let file = File::create(&temp_file_path).map_err(TarErr::ArchiveCreationFailed)?;
-    let mut archive = Builder::new(file);
-
-    for file_path in files {
-        archive
-            .append_path(file_path)
-            .map_err(|e| TarErr::ArchiveCreationFailed(e))?;
-    }
+    let file = encrypted_output(&temp_file_path, options)?;
+    let (writer, manifest) = build_tar(file, files, options)?;
+    writer.finish().map_err(TarErr::ArchiveCreationFailed)?;
 
-    archive
-        .finish()
-        .map_err(|e| TarErr::ArchiveCreationFailed(e))?;
-    make_permanent(temp_dir, &temp_file_path, output_path.as_ref())
-        .map_err(TarErr::TempFileFinalizationFailed)?;
-    Ok(())
+    finish_output(temp_dir, &temp_file_path, output_path.as_ref(), options)?;
+    write_sidecar(manifest, output_path.as_ref())
 }
 
 pub fn create_tar_gz_archive<P: AsRef<Path>>(files: &[P], output_path: P) -> Result<(), TarErr> {
+    create_tar_gz_archive_with_options(files, output_path, &TarOptions::default())
+}
+
+pub fn create_tar_gz_archive_with_options<P: AsRef<Path>>(
+    files: &[P],
+    output_path: P,
+    options: &TarOptions,
+) -> Result<(), TarErr> {
     let (temp_dir, temp_file_path) =
         create_temp_file("tar.gz").map_err(TarErr::TempFileCreationFailed)?;
-    let file = File::create(&temp_file_path).map_err(TarErr::ArchiveCreationFailed)?;
-    let tar_gz_encoder = GzEncoder::new(file, flate2::Compression::best());
-    let mut archive = Builder::new(tar_gz_encoder);
-
-    for file_path in files {
-        archive
-            .append_path(file_path)
-            .map_err(|e| TarErr::ArchiveCreationFailed(e))?;
+    let file = encrypted_output(&temp_file_path, options)?;
+    let mut gzip = options.gzip.clone();
+    if let Some(reproducible) = options.reproducible {
+        gzip.mtime = gzip
+            .mtime
+            .map(|mtime| reproducible.clamp_mtime(mtime.into()) as u32);
     }
-
-    archive
+    let tar_gz_encoder = GzipWriter::new(file, &gzip).map_err(TarErr::CompressionSetupFailed)?;
+    let (encoder, manifest) = build_tar(tar_gz_encoder, files, options)?;
+    encoder
         .finish()
-        .map_err(|e| TarErr::ArchiveCreationFailed(e))?;
-    make_permanent(temp_dir, &temp_file_path, output_path.as_ref())
-        .map_err(TarErr::TempFileFinalizationFailed)?;
-    Ok(())
+        .map_err(TarErr::ArchiveCreationFailed)?
+        .finish()
+        .map_err(TarErr::ArchiveCreationFailed)?;
+
+    finish_output(temp_dir, &temp_file_path, output_path.as_ref(), options)?;
+    write_sidecar(manifest, output_path.as_ref())
 }
 
 pub fn create_tar_bz2_archive<P: AsRef<Path>>(files: &[P], output_path: P) -> Result<(), TarErr> {
+    create_tar_bz2_archive_with_options(files, output_path, &TarOptions::default())
+}
+
+pub fn create_tar_bz2_archive_with_options<P: AsRef<Path>>(
+    files: &[P],
+    output_path: P,
+    options: &TarOptions,
+) -> Result<(), TarErr> {
     let (temp_dir, temp_file_path) =
         create_temp_file("tar.bz2").map_err(TarErr::TempFileCreationFailed)?;
-    let file = File::create(&temp_file_path).map_err(TarErr::ArchiveCreationFailed)?;
-    let mut tar_bz2_encoder = BzEncoder::new(file, bzip2::Compression::best());
-    let mut archive = Builder::new(tar_bz2_encoder);
-
-    for file_path in files {
-        archive
-            .append_path(file_path)
-            .map_err(|e| TarErr::ArchiveCreationFailed(e))?;
-    }
-
-    archive
+    let file = encrypted_output(&temp_file_path, options)?;
+    let tar_bz2_encoder = BzEncoder::new(file, bzip2::Compression::best());
+    let (encoder, manifest) = build_tar(tar_bz2_encoder, files, options)?;
+    encoder
         .finish()
-        .map_err(|e| TarErr::ArchiveCreationFailed(e))?;
-    make_permanent(temp_dir, &temp_file_path, output_path.as_ref())
-        .map_err(TarErr::TempFileFinalizationFailed)?;
-    Ok(())
+        .map_err(TarErr::ArchiveCreationFailed)?
+        .finish()
+        .map_err(TarErr::ArchiveCreationFailed)?;
+
+    finish_output(temp_dir, &temp_file_path, output_path.as_ref(), options)?;
+    write_sidecar(manifest, output_path.as_ref())
 }
 
 pub fn create_tar_xz_archive<P: AsRef<Path>>(files: &[P], output_path: P) -> Result<(), TarErr> {
+    create_tar_xz_archive_with_options(files, output_path, &TarOptions::default())
+}
+
+pub fn create_tar_xz_archive_with_options<P: AsRef<Path>>(
+    files: &[P],
+    output_path: P,
+    options: &TarOptions,
+) -> Result<(), TarErr> {
     let (temp_dir, temp_file_path) =
         create_temp_file("tar.xz").map_err(TarErr::TempFileCreationFailed)?;
-    let file = File::create(&temp_file_path).map_err(TarErr::ArchiveCreationFailed)?;
-    let mut tar_xz_encoder = XzEncoder::new(file, 9);
-    let mut archive = Builder::new(tar_xz_encoder);
+    let file = encrypted_output(&temp_file_path, options)?;
+    let tar_xz_encoder = XzEncoder::new(file, 9);
+    let (encoder, manifest) = build_tar(tar_xz_encoder, files, options)?;
+    encoder
+        .finish()
+        .map_err(TarErr::ArchiveCreationFailed)?
+        .finish()
+        .map_err(TarErr::ArchiveCreationFailed)?;
+
+    finish_output(temp_dir, &temp_file_path, output_path.as_ref(), options)?;
+    write_sidecar(manifest, output_path.as_ref())
+}
+
+/// Moves the finished archive to `output_path`, or splits it into volumes
+/// next to it when `options.volume_size` is set. Whichever form an earlier
+/// archive at the same path took is removed, as readers prefer a plain file
+/// over volumes.
+fn finish_output(
+    temp_dir: TempDir,
+    temp_file_path: &Path,
+    output_path: &Path,
+    options: &TarOptions,
+) -> Result<(), TarErr> {
+    match options.volume_size {
+        Some(volume_size) => {
+            split_into_volumes(temp_file_path, output_path, volume_size)
+                .map_err(TarErr::VolumeSplitFailed)?;
+            if output_path.is_file() {
+                fs::remove_file(output_path).map_err(TarErr::ArchiveCreationFailed)?;
+            }
+            temp_dir
+                .close()
+                .map_err(|e| TarErr::TempFileFinalizationFailed(UtilsErr::TempDirCloseFailed(e)))
+        }
+        None => {
+            make_permanent(temp_dir, temp_file_path, output_path)
+                .map_err(TarErr::TempFileFinalizationFailed)?;
+            remove_volumes_after(output_path, 0).map_err(TarErr::ArchiveCreationFailed)
+        }
+    }
+}
+
+/// Writes the JSON manifest next to the finished archive when asked for.
+fn write_sidecar(manifest: Option<ManifestBuilder>, output_path: &Path) -> Result<(), TarErr> {
+    match manifest {
+        Some(manifest) => manifest
+            .write_sidecar(output_path)
+            .map_err(TarErr::ManifestWriteFailed),
+        None => Ok(()),
+    }
+}
+
+/// Creates the archive file, wrapped in the encryption layer when
+/// `options.encryption` is set.
+fn encrypted_output(path: &Path, options: &TarOptions) -> Result<EncryptionLayer<File>, TarErr> {
+    let file = File::create(path).map_err(TarErr::ArchiveCreationFailed)?;
+    EncryptionLayer::new(file, options.encryption.as_ref()).map_err(TarErr::EncryptionFailed)
+}
+
+/// Writes all `files` as a complete tar stream into `writer` and hands the
+/// writer back so the caller can finish its compressor, along with the
+/// manifest of `options.manifest`.
+fn build_tar<W: Write, P: AsRef<Path>>(
+    writer: W,
+    files: &[P],
+    options: &TarOptions,
+) -> Result<(W, Option<ManifestBuilder>), TarErr> {
+    let entries = tar_entries(files, options)?;
+    let mut manifest = options.manifest.as_ref().map(ManifestBuilder::new);
+    let writer = write_tar_entries(writer, entries, options, manifest.as_mut())?;
+    Ok((writer, manifest))
+}
+
+/// Resolves `files` into `(path on disk, archive name)` pairs.
+fn tar_entries<P: AsRef<Path>>(
+    files: &[P],
+    options: &TarOptions,
+) -> Result<Vec<(PathBuf, PathBuf)>, TarErr> {
+    let filter = FileFilter {
+        follow_links: options.filter.follow_links || options.symlinks == SymlinkPolicy::Follow,
+        ..options.filter.clone()
+    };
+    let mut entries = Vec::new();
+    let mut names = HashSet::new();
+    for input in files {
+        let input = input.as_ref();
+        for file in resolve_files(&[input], &filter).map_err(TarErr::FileSelectionFailed)? {
+            // Files given directly keep the caller's path, as with `tar`;
+            // files found in a directory are named below its parent.
+            let relative = match input.is_dir() {
+                true => file.relative,
+                false => archive_name(&file.path),
+            };
+            let name = options
+                .transform
+                .apply(&relative)
+                .map_err(TarErr::PathTransformFailed)?;
+            if let Some(name) = name {
+                if !names.insert(name.clone()) {
+                    return Err(TarErr::DuplicateEntry(name));
+                }
+                entries.push((file.path, name));
+            }
+        }
+    }
+    if options.reproducible.is_some() {
+        entries.sort_by(|a, b| a.1.cmp(&b.1));
+    }
+    Ok(entries)
+}
+
+/// Archive name for `path`, dropping any root or prefix component the way
+/// GNU tar does, since archive paths must be relative.
+fn archive_name(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| matches!(component, Component::Normal(_) | Component::ParentDir))
+        .collect()
+}
+
+fn write_tar_entries<W: Write>(
+    writer: W,
+    entries: Vec<(PathBuf, PathBuf)>,
+    options: &TarOptions,
+    mut manifest: Option<&mut ManifestBuilder>,
+) -> Result<W, TarErr> {
+    // A file at the archive root may not take the name of the manifest entry.
+    if let Some((manifest_name, _)) = manifest.as_deref().and_then(ManifestBuilder::entry) {
+        if let Some((_, name)) = entries
+            .iter()
+            .find(|(_, name)| name == Path::new(manifest_name))
+        {
+            return Err(TarErr::DuplicateEntry(name.clone()));
+        }
+    }
+    let mut archive = Builder::new(writer);
+    let mut linked = HashMap::new();
+    for (path, name) in entries {
+        append_entry(
+            &mut archive,
+            &path,
+            &name,
+            options,
+            &mut linked,
+            manifest.as_deref_mut(),
+        )?;
+    }
+    if let Some((name, contents)) = manifest.and_then(|manifest| manifest.entry()) {
+        append_manifest(&mut archive, name, &contents, options)?;
+    }
+
+    archive.into_inner().map_err(TarErr::ArchiveCreationFailed)
+}
+
+/// Settings for `append_to_tar_archive_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct TarAppendOptions {
+    /// How the new entries are selected and written, as for `create_tar_*`.
+    /// `gzip`, `encryption`, `volume_size` and `manifest` concern the whole
+    /// output file and fail with `TarErr::UnsupportedAppendOption` when set.
+    pub tar: TarOptions,
+    /// Fail with `TarErr::DuplicateEntry` instead of adding an entry under a
+    /// name the archive already contains.
+    pub unique_names: bool,
+}
+
+pub fn append_to_tar_archive<P: AsRef<Path>>(archive_path: P, files: &[P]) -> Result<(), TarErr> {
+    append_to_tar_archive_with_options(archive_path, files, &TarAppendOptions::default())
+}
+
+/// Adds `files` to an uncompressed tar archive in place: the new entries
+/// overwrite the end-of-archive zero blocks and the archive is terminated
+/// again after them. If writing fails, the archive is cut back to its
+/// previous contents.
+pub fn append_to_tar_archive_with_options<P: AsRef<Path>>(
+    archive_path: P,
+    files: &[P],
+    options: &TarAppendOptions,
+) -> Result<(), TarErr> {
+    check_append_options(&options.tar)?;
+    let archive_path = archive_path.as_ref();
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(archive_path)
+        .map_err(TarErr::ArchiveCreationFailed)?;
+    let (end, names) = scan_tar_end(&mut file, archive_path)?;
+
+    let entries = tar_entries(files, &options.tar)?;
+    if options.unique_names {
+        let mut seen = names;
+        for (_, name) in &entries {
+            if !seen.insert(name.clone()) {
+                return Err(TarErr::DuplicateEntry(name.clone()));
+            }
+        }
+    }
+
+    let truncate = |file: &mut File| -> io::Result<()> {
+        file.set_len(end)?;
+        file.seek(SeekFrom::Start(end)).map(|_| ())
+    };
+    truncate(&mut file).map_err(TarErr::ArchiveCreationFailed)?;
+    if let Err(e) = write_tar_entries(&mut file, entries, &options.tar, None) {
+        truncate(&mut file)
+            .and_then(|_| file.write_all(&[0; 1024]))
+            .map_err(TarErr::ArchiveCreationFailed)?;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Fails for the settings that apply to a whole new output file.
+fn check_append_options(options: &TarOptions) -> Result<(), TarErr> {
+    if options.gzip != GzipOptions::default() {
+        return Err(TarErr::UnsupportedAppendOption("gzip"));
+    }
+    if options.encryption.is_some() {
+        return Err(TarErr::UnsupportedAppendOption("encryption"));
+    }
+    if options.volume_size.is_some() {
+        return Err(TarErr::UnsupportedAppendOption("volume_size"));
+    }
+    if options.manifest.is_some() {
+        return Err(TarErr::UnsupportedAppendOption("manifest"));
+    }
+    Ok(())
+}
 
-    for file_path in files {
-        archive
-            .append_path(file_path)
-            .map_err(|e| TarErr::ArchiveCreationFailed(e))?;
+/// Offset just past the last entry of an uncompressed archive, and the
+/// names of its entries. Only zero blocks may follow that offset.
+fn scan_tar_end(file: &mut File, path: &Path) -> Result<(u64, HashSet<PathBuf>), TarErr> {
+    let not_appendable = |reason| TarErr::NotAppendable {
+        path: path.to_path_buf(),
+        reason,
+    };
+    let mut magic = Vec::new();
+    (&mut *file)
+        .take(6)
+        .read_to_end(&mut magic)
+        .map_err(TarErr::ArchiveCreationFailed)?;
+    if magic.starts_with(&[0x1f, 0x8b])
+        || magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0])
+        || magic.starts_with(b"BZh")
+    {
+        return Err(not_appendable(
+            "compressed archives cannot be appended to in place",
+        ));
+    }
+    if magic == AGE_MAGIC[..6] {
+        return Err(not_appendable(
+            "encrypted archives cannot be appended to in place",
+        ));
+    }
+    file.rewind().map_err(TarErr::ArchiveCreationFailed)?;
+
+    let mut end = 0;
+    let mut names = HashSet::new();
+    let mut archive = Archive::new(&mut *file);
+    for entry in archive
+        .entries_with_seek()
+        .map_err(TarErr::ArchiveCreationFailed)?
+    {
+        let mut entry = entry.map_err(TarErr::ArchiveCreationFailed)?;
+        end = entry.raw_file_position() + entry.size().div_ceil(512) * 512;
+        let pax = pax_records(&mut entry).map_err(TarErr::ArchiveCreationFailed)?;
+        let name = match SparseInfo::from_pax(&pax) {
+            Some(sparse) => sparse.name,
+            None => entry
+                .path()
+                .map_err(TarErr::ArchiveCreationFailed)?
+                .into_owned(),
+        };
+        names.insert(name);
+    }
+
+    file.seek(SeekFrom::Start(end))
+        .map_err(TarErr::ArchiveCreationFailed)?;
+    let mut buffer = [0u8; 8192];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .map_err(TarErr::ArchiveCreationFailed)?;
+        if read == 0 {
+            break;
+        }
+        if buffer[..read].iter().any(|&byte| byte != 0) {
+            return Err(not_appendable("data follows the end-of-archive marker"));
+        }
+    }
+    Ok((end, names))
+}
+
+/// Writes one filesystem object, applying the symlink policy, hardlink
+/// detection and reproducible metadata from `options`.
+fn append_entry<W: Write>(
+    archive: &mut Builder<W>,
+    path: &Path,
+    name: &Path,
+    options: &TarOptions,
+    linked: &mut HashMap<(u64, u64), PathBuf>,
+    manifest: Option<&mut ManifestBuilder>,
+) -> Result<(), TarErr> {
+    let mut metadata = fs::symlink_metadata(path).map_err(TarErr::ArchiveCreationFailed)?;
+    if metadata.file_type().is_symlink() {
+        match options.symlinks {
+            SymlinkPolicy::Reject => return Err(TarErr::SymlinkRejected(path.to_path_buf())),
+            SymlinkPolicy::Follow => {
+                metadata = fs::metadata(path).map_err(TarErr::ArchiveCreationFailed)?;
+            }
+            SymlinkPolicy::Preserve => {
+                let target = fs::read_link(path).map_err(TarErr::ArchiveCreationFailed)?;
+                let pax = match &options.xattrs {
+                    Some(filter) => read_xattrs(path, filter, false)?,
+                    None => Vec::new(),
+                };
+                let mut header = entry_header(&metadata, options);
+                header.set_entry_type(EntryType::Symlink);
+                header.set_size(0);
+                let entry = EntryData::link(name, &target);
+                let stamp = EntryStamp::new(&metadata, options);
+                return write_entry(archive, header, entry, pax, stamp, options);
+            }
+        }
+    }
+
+    if options.detect_hardlinks && metadata.is_file() {
+        if let Some(key) = hardlink_key(&metadata) {
+            if let Some(first) = linked.get(&key) {
+                let mut header = entry_header(&metadata, options);
+                header.set_entry_type(EntryType::Link);
+                header.set_size(0);
+                let entry = EntryData::link(name, first);
+                let stamp = EntryStamp::new(&metadata, options);
+                write_entry(archive, header, entry, Vec::new(), stamp, options)?;
+                if let Some(manifest) = manifest {
+                    let target = first.to_string_lossy();
+                    manifest.add_link(name.to_string_lossy().into_owned(), &target);
+                }
+                return Ok(());
+            }
+            linked.insert(key, name.to_path_buf());
+        }
+    }
+
+    let mut pax = match &options.xattrs {
+        Some(filter) => read_xattrs(path, filter, true)?,
+        None => Vec::new(),
+    };
+    let mut header = entry_header(&metadata, options);
+    let stamp = EntryStamp::new(&metadata, options);
+    if metadata.is_file() {
+        let file = File::open(path).map_err(TarErr::ArchiveCreationFailed)?;
+        let regions = match options.sparse {
+            true => data_regions(&file, metadata.len()).map_err(TarErr::ArchiveCreationFailed)?,
+            false => None,
+        };
+        let mut hasher = manifest.as_ref().map(|manifest| manifest.hasher());
+        match regions {
+            Some(regions) => {
+                let sparse = SparseReader::new(file, &regions, metadata.len(), hasher.as_mut());
+                pax.extend(sparse_pax_records(name, metadata.len()));
+                // GNU tar only honours the sparse records on a POSIX ustar header.
+                let mut header = entry_header_from(Header::new_ustar(), &metadata, options);
+                header.set_size(sparse.len());
+                let sparse_name = sparse_entry_name(name);
+                let entry = EntryData::data(&sparse_name, sparse);
+                write_entry(archive, header, entry, pax, stamp, options)?;
+            }
+            None => match &mut hasher {
+                Some(hasher) => {
+                    let entry = EntryData::data(name, HashingReader::new(file, hasher));
+                    write_entry(archive, header, entry, pax, stamp, options)?;
+                }
+                None => {
+                    let entry = EntryData::data(name, file);
+                    write_entry(archive, header, entry, pax, stamp, options)?;
+                }
+            },
+        }
+        if let (Some(manifest), Some(hasher)) = (manifest, hasher) {
+            manifest.add(name.to_string_lossy().into_owned(), hasher);
+        }
+        Ok(())
+    } else {
+        // Directories and special files: `set_metadata` already recorded the
+        // entry type and device numbers.
+        header.set_size(0);
+        let entry = EntryData::data(name, io::empty());
+        write_entry(archive, header, entry, pax, stamp, options)
+    }
+}
+
+/// Writes the manifest collected by `write_tar_entries` as a regular file
+/// owned by root and stamped with the current time.
+fn append_manifest<W: Write>(
+    archive: &mut Builder<W>,
+    name: &str,
+    contents: &[u8],
+    options: &TarOptions,
+) -> Result<(), TarErr> {
+    let mut header = match options.format {
+        TarFormat::Gnu => Header::new_gnu(),
+        TarFormat::Ustar | TarFormat::Pax => Header::new_ustar(),
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let mtime = options
+        .reproducible
+        .map_or(now, |reproducible| reproducible.clamp_mtime(now));
+    header.set_mtime(mtime);
+    header.set_mode(0o644);
+    header.set_size(contents.len() as u64);
+    let stamp = EntryStamp {
+        owner: Owner::default(),
+        nanos: 0,
+    };
+    let entry = EntryData::data(Path::new(name), contents);
+    write_entry(archive, header, entry, Vec::new(), stamp, options)
+}
+
+/// Name and payload of an entry handed to `write_entry`.
+struct EntryData<'a, R> {
+    name: &'a Path,
+    link: Option<&'a Path>,
+    data: R,
+}
+
+impl<'a> EntryData<'a, io::Empty> {
+    fn link(name: &'a Path, target: &'a Path) -> Self {
+        EntryData {
+            name,
+            link: Some(target),
+            data: io::empty(),
+        }
+    }
+}
+
+impl<'a, R: Read> EntryData<'a, R> {
+    fn data(name: &'a Path, data: R) -> Self {
+        EntryData {
+            name,
+            link: None,
+            data,
+        }
+    }
+}
+
+/// Largest uid/gid that fits the octal ustar id fields.
+const USTAR_MAX_ID: u64 = 0o7777777;
+/// Largest size or mtime that fits the octal ustar 12-byte fields.
+const USTAR_MAX_NUMBER: u64 = 0o77777777777;
+
+/// Writes `pax` records followed by the entry in the layout of
+/// `options.format`. For ustar and PAX the header is filled in here rather
+/// than by the `tar` crate, which would otherwise fall back to GNU long-name
+/// entries and base-256 numbers.
+fn write_entry<W: Write, R: Read>(
+    archive: &mut Builder<W>,
+    mut header: Header,
+    entry: EntryData<'_, R>,
+    mut pax: Vec<(String, Vec<u8>)>,
+    stamp: EntryStamp,
+    options: &TarOptions,
+) -> Result<(), TarErr> {
+    let EntryData { name, link, data } = entry;
+    let unrepresentable = |reason| TarErr::UnrepresentableEntry {
+        path: name.to_path_buf(),
+        reason,
+    };
+    let extended = options.format == TarFormat::Pax;
+
+    let owner = options.owner.apply(stamp.owner);
+    header.set_uid(owner.uid);
+    header.set_gid(owner.gid);
+    if !options.numeric_owner {
+        for (key, value) in [("uname", &owner.uname), ("gname", &owner.gname)] {
+            let Some(value) = value else { continue };
+            let stored = match key {
+                "uname" => header.set_username(value),
+                _ => header.set_groupname(value),
+            };
+            if stored.is_err() {
+                if !extended {
+                    return Err(unrepresentable("user or group name longer than 32 bytes"));
+                }
+                pax.push((key.to_string(), value.clone().into_bytes()));
+            }
+        }
+    }
+
+    if options.format == TarFormat::Gnu {
+        append_pax(archive, &pax)?;
+        return match link {
+            Some(link) => archive.append_link(&mut header, name, link),
+            None => archive.append_data(&mut header, name, data),
+        }
+        .map_err(TarErr::ArchiveCreationFailed);
     }
 
+    if !extended && !pax.is_empty() {
+        return Err(unrepresentable(
+            "extended attributes and sparse files need the PAX or GNU format",
+        ));
+    }
+
+    if header.set_path(name).is_err() {
+        if !extended {
+            return Err(unrepresentable(
+                "path longer than the ustar name and prefix fields",
+            ));
+        }
+        pax.push(("path".to_string(), path_bytes(name)));
+        header
+            .set_path(truncated_name(name))
+            .map_err(TarErr::ArchiveCreationFailed)?;
+    }
+    if let Some(link) = link {
+        if header.set_link_name(link).is_err() {
+            if !extended {
+                return Err(unrepresentable("link target longer than 100 bytes"));
+            }
+            pax.push(("linkpath".to_string(), path_bytes(link)));
+            header
+                .set_link_name(truncated_name(link))
+                .map_err(TarErr::ArchiveCreationFailed)?;
+        }
+    }
+
+    let invalid = TarErr::ArchiveCreationFailed;
+    let uid = header.uid().map_err(invalid)?;
+    let gid = header.gid().map_err(invalid)?;
+    let size = header.entry_size().map_err(invalid)?;
+    let mtime = header.mtime().map_err(invalid)?;
+    for (key, value, limit) in [
+        ("uid", uid, USTAR_MAX_ID),
+        ("gid", gid, USTAR_MAX_ID),
+        ("size", size, USTAR_MAX_NUMBER),
+    ] {
+        if value > limit {
+            if !extended {
+                return Err(unrepresentable(match key {
+                    "size" => "file of 8 GiB or more",
+                    _ => "uid or gid above 2097151",
+                }));
+            }
+            pax.push((key.to_string(), value.to_string().into_bytes()));
+        }
+    }
+    if uid > USTAR_MAX_ID {
+        header.set_uid(0);
+    }
+    if gid > USTAR_MAX_ID {
+        header.set_gid(0);
+    }
+    if size > USTAR_MAX_NUMBER {
+        header.set_size(0);
+    }
+
+    let nanos = stamp.nanos;
+    if mtime > USTAR_MAX_NUMBER && !extended {
+        return Err(unrepresentable("modification time after the year 2242"));
+    }
+    if extended && (nanos > 0 || mtime > USTAR_MAX_NUMBER) {
+        let value = match nanos {
+            0 => mtime.to_string(),
+            nanos => format!("{}.{:09}", mtime, nanos),
+        };
+        pax.push(("mtime".to_string(), value.into_bytes()));
+        header.set_mtime(mtime.min(USTAR_MAX_NUMBER));
+    }
+
+    header.set_cksum();
+    append_pax(archive, &pax)?;
     archive
-        .finish()
-        .map_err(|e| TarErr::ArchiveCreationFailed(e))?;
-    make_permanent(temp_dir, &temp_file_path, output_path.as_ref())
-        .map_err(TarErr::TempFileFinalizationFailed)?;
+        .append(&header, data)
+        .map_err(TarErr::ArchiveCreationFailed)
+}
+
+/// Raw bytes of `path` for PAX `path`/`linkpath` records.
+fn path_bytes(path: &Path) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes().to_vec()
+    }
+    #[cfg(not(unix))]
+    {
+        path.to_string_lossy().into_owned().into_bytes()
+    }
+}
+
+/// Stand-in for a path that only fits a PAX record: the final component cut
+/// to 100 bytes, so readers without PAX support still get a usable name.
+fn truncated_name(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy();
+    let mut end = name.len().min(100);
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    PathBuf::from(&name[..end])
+}
+
+fn append_pax<W: Write>(
+    archive: &mut Builder<W>,
+    records: &[(String, Vec<u8>)],
+) -> Result<(), TarErr> {
+    archive
+        .append_pax_extensions(
+            records
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_slice())),
+        )
+        .map_err(TarErr::ArchiveCreationFailed)
+}
+
+/// Extended attributes of `path` selected by `filter`, as `SCHILY.xattr.*` PAX records.
+#[cfg(unix)]
+fn read_xattrs(
+    path: &Path,
+    filter: &XattrFilter,
+    follow: bool,
+) -> Result<Vec<(String, Vec<u8>)>, TarErr> {
+    let names = match follow {
+        true => xattr::list_deref(path),
+        false => xattr::list(path),
+    };
+    let names = match names {
+        Ok(names) => names,
+        Err(e) if e.kind() == io::ErrorKind::Unsupported => return Ok(Vec::new()),
+        Err(e) => return Err(TarErr::XattrFailed(path.to_path_buf(), e)),
+    };
+
+    let mut records = Vec::new();
+    for name in names {
+        let name = name.to_string_lossy().into_owned();
+        if !filter.matches(&name) {
+            continue;
+        }
+        let value = match follow {
+            true => xattr::get_deref(path, &name),
+            false => xattr::get(path, &name),
+        }
+        .map_err(|e| TarErr::XattrFailed(path.to_path_buf(), e))?;
+        if let Some(value) = value {
+            records.push((format!("{}{}", XATTR_PAX_PREFIX, name), value));
+        }
+    }
+    records.sort();
+    Ok(records)
+}
+
+#[cfg(not(unix))]
+fn read_xattrs(
+    _path: &Path,
+    _filter: &XattrFilter,
+    _follow: bool,
+) -> Result<Vec<(String, Vec<u8>)>, TarErr> {
+    Ok(Vec::new())
+}
+
+const XATTR_PAX_PREFIX: &str = "SCHILY.xattr.";
+
+/// PAX records announcing a GNU 1.0 sparse entry for the file stored as `name`.
+fn sparse_pax_records(name: &Path, real_size: u64) -> Vec<(String, Vec<u8>)> {
+    vec![
+        ("GNU.sparse.major".to_string(), b"1".to_vec()),
+        ("GNU.sparse.minor".to_string(), b"0".to_vec()),
+        (
+            "GNU.sparse.name".to_string(),
+            name.to_string_lossy().into_owned().into_bytes(),
+        ),
+        (
+            "GNU.sparse.realsize".to_string(),
+            real_size.to_string().into_bytes(),
+        ),
+    ]
+}
+
+/// Placeholder header name GNU tar uses for sparse entries, so tools without
+/// sparse support extract the raw map and data under a clearly marked path.
+fn sparse_entry_name(name: &Path) -> PathBuf {
+    let file_name = name.file_name().unwrap_or_default();
+    name.parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join("GNUSparseFile.0")
+        .join(file_name)
+}
+
+/// Data regions `(offset, length)` of a file with holes, found with
+/// `SEEK_DATA`/`SEEK_HOLE`. Returns `None` for files without holes or on
+/// filesystems that cannot report them.
+#[cfg(target_os = "linux")]
+fn data_regions(file: &File, size: u64) -> io::Result<Option<Vec<(u64, u64)>>> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    let mut regions = Vec::new();
+    let mut offset: libc::off_t = 0;
+    while (offset as u64) < size {
+        // SAFETY: `fd` is a valid open descriptor borrowed from `file`.
+        let data = unsafe { libc::lseek(fd, offset, libc::SEEK_DATA) };
+        if data < 0 {
+            let error = io::Error::last_os_error();
+            match error.raw_os_error() {
+                Some(libc::ENXIO) => break,
+                Some(libc::EINVAL) | Some(libc::EOPNOTSUPP) => return Ok(None),
+                _ => return Err(error),
+            }
+        }
+        // SAFETY: as above.
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        regions.push((data as u64, (hole - data) as u64));
+        offset = hole;
+    }
+
+    let data_len: u64 = regions.iter().map(|(_, len)| len).sum();
+    Ok((data_len < size).then_some(regions))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn data_regions(_file: &File, _size: u64) -> io::Result<Option<Vec<(u64, u64)>>> {
+    Ok(None)
+}
+
+/// Produces the body of a PAX 1.0 sparse entry: the decimal region map padded
+/// to a block boundary, followed by the bytes of each data region. The
+/// optional hasher sees the file with its holes as zeros.
+struct SparseReader<'a> {
+    file: File,
+    map: io::Cursor<Vec<u8>>,
+    regions: Vec<(u64, u64)>,
+    current: usize,
+    remaining: u64,
+    hasher: Option<&'a mut EntryHasher>,
+    /// Offset in the file up to which `hasher` has been fed.
+    hashed: u64,
+}
+
+impl<'a> SparseReader<'a> {
+    fn new(
+        file: File,
+        regions: &[(u64, u64)],
+        real_size: u64,
+        hasher: Option<&'a mut EntryHasher>,
+    ) -> Self {
+        let mut regions = regions.to_vec();
+        // A trailing hole is recorded as an empty region ending the file.
+        if regions
+            .last()
+            .is_none_or(|(offset, len)| offset + len < real_size)
+        {
+            regions.push((real_size, 0));
+        }
+
+        let mut map = format!("{}\n", regions.len());
+        for (offset, len) in &regions {
+            map.push_str(&format!("{}\n{}\n", offset, len));
+        }
+        let mut map = map.into_bytes();
+        map.resize(map.len().div_ceil(512) * 512, 0);
+
+        SparseReader {
+            file,
+            map: io::Cursor::new(map),
+            remaining: regions.first().map_or(0, |(_, len)| *len),
+            regions,
+            current: 0,
+            hasher,
+            hashed: 0,
+        }
+    }
+
+    /// Feeds the hasher the zeros of the hole before `offset`.
+    fn hash_hole(&mut self, offset: u64) -> io::Result<()> {
+        if let Some(hasher) = &mut self.hasher {
+            io::copy(&mut io::repeat(0).take(offset - self.hashed), hasher)?;
+        }
+        self.hashed = offset;
+        Ok(())
+    }
+
+    fn len(&self) -> u64 {
+        let data: u64 = self.regions.iter().map(|(_, len)| len).sum();
+        self.map.get_ref().len() as u64 + data
+    }
+}
+
+impl Read for SparseReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.map.read(buf)?;
+        if read > 0 {
+            return Ok(read);
+        }
+        while self.remaining == 0 {
+            self.current += 1;
+            match self.regions.get(self.current) {
+                Some((_, len)) => self.remaining = *len,
+                None => {
+                    // The last region ends at the real size of the file.
+                    let end = self.regions.last().map_or(0, |(offset, len)| offset + len);
+                    self.hash_hole(end)?;
+                    return Ok(0);
+                }
+            }
+        }
+
+        let (offset, len) = self.regions[self.current];
+        let position = offset + len - self.remaining;
+        self.hash_hole(position)?;
+        self.file.seek(SeekFrom::Start(position))?;
+        let limit = buf.len().min(self.remaining as usize);
+        let read = self.file.read(&mut buf[..limit])?;
+        if read == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "sparse file shrank while archiving",
+            ));
+        }
+        self.remaining -= read as u64;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..read]);
+        }
+        self.hashed += read as u64;
+        Ok(read)
+    }
+}
+
+fn entry_header(metadata: &fs::Metadata, options: &TarOptions) -> Header {
+    let header = match options.format {
+        TarFormat::Gnu => Header::new_gnu(),
+        TarFormat::Ustar | TarFormat::Pax => Header::new_ustar(),
+    };
+    entry_header_from(header, metadata, options)
+}
+
+fn entry_header_from(mut header: Header, metadata: &fs::Metadata, options: &TarOptions) -> Header {
+    match options.reproducible {
+        Some(reproducible) => {
+            header.set_metadata_in_mode(metadata, HeaderMode::Deterministic);
+            header.set_mtime(reproducible.clamp_mtime(modified_secs(metadata)));
+            header
+                .set_mode(reproducible.normalize_mode(metadata_mode(metadata), metadata.is_dir()));
+        }
+        None => header.set_metadata(metadata),
+    }
+    header
+}
+
+/// Owner (before `TarOptions::owner` is applied) and sub-second part of the
+/// modification time of an entry.
+pub(crate) struct EntryStamp {
+    pub(crate) owner: Owner,
+    pub(crate) nanos: u32,
+}
+
+impl EntryStamp {
+    fn new(metadata: &fs::Metadata, options: &TarOptions) -> Self {
+        let nanos = match options.reproducible {
+            Some(_) => 0,
+            None => metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |since| since.subsec_nanos()),
+        };
+        EntryStamp {
+            owner: entry_owner(metadata, options),
+            nanos,
+        }
+    }
+}
+
+/// Owner of an entry before `TarOptions::owner` is applied: the ids on disk
+/// and their local names, or nobody in reproducible mode.
+fn entry_owner(metadata: &fs::Metadata, options: &TarOptions) -> Owner {
+    if options.reproducible.is_some() {
+        return Owner::default();
+    }
+    let (uid, gid) = metadata_owner(metadata);
+    match options.numeric_owner {
+        true => Owner::new(uid, gid),
+        false => Owner {
+            uid,
+            gid,
+            uname: user_name(uid),
+            gname: group_name(gid),
+        },
+    }
+}
+
+/// Identity of a file with more than one link, used to store repeats as hardlinks.
+#[cfg(unix)]
+fn hardlink_key(metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn hardlink_key(_metadata: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Settings for `extract_tar_archive`.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    /// How symbolic link entries are restored. `Follow` writes a copy of the
+    /// (already extracted) target instead of a link.
+    pub symlinks: SymlinkPolicy,
+    /// Restore extended attributes stored as `SCHILY.xattr.*` PAX records that
+    /// match the filter. Writing `security.*` or `trusted.*` usually requires root.
+    pub xattrs: Option<XattrFilter>,
+    /// Give extracted entries the owner stored in the archive (after
+    /// `owner` is applied). Changing ownership usually requires root.
+    pub restore_owner: bool,
+    /// Rewrites the stored owner before it is restored.
+    pub owner: OwnerMapping,
+    /// Use the stored numeric ids even when the stored user and group names
+    /// exist on this system.
+    pub numeric_owner: bool,
+    /// Rewrites entry paths (and hardlink targets) before they are placed
+    /// under the destination. Entries rewritten to nothing are skipped.
+    pub transform: PathTransform,
+    /// Key for archives encrypted with age.
+    pub decryption: Option<EncryptionKey>,
+}
+
+/// Extracts a tar archive, optionally gzip, xz, bzip2 or zstd compressed
+/// (detected from its magic bytes), into `destination`. An archive split
+/// into `.partNNN` volumes is read from its volumes.
+///
+/// Entries with absolute paths or `..` components are refused, as are
+/// symbolic and hard links whose target would resolve outside `destination`.
+/// Links are created last, so a followed link may precede its target.
+pub fn extract_tar_archive<P: AsRef<Path>>(
+    archive_path: P,
+    destination: P,
+    options: &ExtractOptions,
+) -> Result<(), TarErr> {
+    let destination = destination.as_ref();
+    fs::create_dir_all(destination).map_err(TarErr::ExtractionFailed)?;
+    let root = destination
+        .canonicalize()
+        .map_err(TarErr::ExtractionFailed)?;
+    let (reader, encrypted) = open_tar_reader(archive_path.as_ref(), options.decryption.as_ref())?;
+    let mut archive = Archive::new(reader);
+    let mut links = Vec::new();
+
+    for entry in archive.entries().map_err(TarErr::ExtractionFailed)? {
+        let mut entry = entry.map_err(TarErr::ExtractionFailed)?;
+        let pax = pax_records(&mut entry).map_err(TarErr::ExtractionFailed)?;
+        let sparse = SparseInfo::from_pax(&pax);
+        let owner = stored_owner(entry.header(), &pax);
+        let path = match &sparse {
+            Some(sparse) => sparse.name.clone(),
+            None => entry.path().map_err(TarErr::ExtractionFailed)?.into_owned(),
+        };
+        let Some(path) = options
+            .transform
+            .apply(&path)
+            .map_err(TarErr::PathTransformFailed)?
+        else {
+            continue;
+        };
+        let relative = contained_path(Path::new(""), &path)
+            .ok_or_else(|| TarErr::UnsafeEntryPath(path.clone()))?;
+        let entry_type = entry.header().entry_type();
+        let xattrs: Vec<_> = match &options.xattrs {
+            Some(filter) => pax
+                .iter()
+                .filter_map(|(key, value)| {
+                    let name = key.strip_prefix(XATTR_PAX_PREFIX)?;
+                    filter
+                        .matches(name)
+                        .then(|| (name.to_string(), value.clone()))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        if let Some(sparse) = sparse {
+            if !prepare_parent(&root, &relative, true).map_err(TarErr::ExtractionFailed)? {
+                return Err(TarErr::UnsafeEntryPath(path));
+            }
+            let target = root.join(&relative);
+            unpack_sparse(&mut entry, &target, sparse.real_size)
+                .map_err(TarErr::ExtractionFailed)?;
+            write_xattrs(&target, &xattrs)?;
+            restore_owner(&target, owner, options)?;
+            continue;
+        }
+
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let target = entry
+                .link_name()
+                .map_err(TarErr::ExtractionFailed)?
+                .ok_or_else(|| TarErr::UnsafeEntryPath(path.clone()))?
+                .into_owned();
+            let target = match entry_type.is_hard_link() {
+                true => match options
+                    .transform
+                    .apply(&target)
+                    .map_err(TarErr::PathTransformFailed)?
+                {
+                    Some(target) => target,
+                    None => continue,
+                },
+                false => target,
+            };
+            // Symlink targets are relative to the link, hardlink targets to the archive root.
+            let base = match entry_type.is_symlink() {
+                true => relative.parent().unwrap_or_else(|| Path::new("")),
+                false => Path::new(""),
+            };
+            if contained_path(base, &target).is_none() {
+                return Err(TarErr::UnsafeLinkTarget { path, target });
+            }
+            if entry_type.is_symlink() && options.symlinks == SymlinkPolicy::Reject {
+                return Err(TarErr::SymlinkRejected(relative));
+            }
+            links.push(PendingLink {
+                path,
+                relative,
+                target,
+                is_symlink: entry_type.is_symlink(),
+                owner,
+            });
+            continue;
+        }
+
+        if !prepare_parent(&root, &relative, true).map_err(TarErr::ExtractionFailed)? {
+            return Err(TarErr::UnsafeEntryPath(path));
+        }
+        let target = root.join(&relative);
+        entry.unpack(&target).map_err(TarErr::ExtractionFailed)?;
+        write_xattrs(&target, &xattrs)?;
+        restore_owner(&target, owner, options)?;
+    }
+
+    // Authenticate the rest of an encrypted stream, past the end-of-archive
+    // marker, so that truncation there is still reported.
+    if encrypted {
+        io::copy(&mut archive.into_inner(), &mut io::sink())
+            .map_err(|e| TarErr::EncryptionFailed(CryptErr::from_read_error(e)))?;
+    }
+    restore_links(&root, links, options)
+}
+
+/// Reads every entry of a tar archive for `convert_archive`, handing each
+/// to `add` with its data. Tells whether the archive was encrypted.
+pub(crate) fn read_tar_entries<F, E>(
+    archive_path: &Path,
+    key: Option<&EncryptionKey>,
+    mut add: F,
+) -> Result<bool, E>
+where
+    F: FnMut(ArchiveEntry, &mut dyn Read) -> Result<(), E>,
+    E: From<TarErr>,
+{
+    let (reader, encrypted) = open_tar_reader(archive_path, key)?;
+    let mut archive = Archive::new(reader);
+
+    for entry in archive.entries().map_err(TarErr::ExtractionFailed)? {
+        let mut entry = entry.map_err(TarErr::ExtractionFailed)?;
+        let pax = pax_records(&mut entry).map_err(TarErr::ExtractionFailed)?;
+        let header = entry.header();
+        let entry_type = header.entry_type();
+        let link = || -> Result<String, TarErr> {
+            let target = entry.link_name().map_err(TarErr::ExtractionFailed)?;
+            Ok(target.unwrap_or_default().to_string_lossy().into_owned())
+        };
+        let kind = match entry_type {
+            EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => EntryKind::File,
+            EntryType::Directory => EntryKind::Directory,
+            EntryType::Symlink => EntryKind::Symlink(link()?),
+            EntryType::Link => EntryKind::HardLink(link()?),
+            EntryType::Char | EntryType::Block | EntryType::Fifo => {
+                let device = |number: io::Result<Option<u32>>| number.ok().flatten().unwrap_or(0);
+                EntryKind::Special {
+                    entry_type,
+                    device: (device(header.device_major()), device(header.device_minor())),
+                }
+            }
+            // Global PAX headers and other entries without a file behind them.
+            _ => continue,
+        };
+        let (mtime, mtime_nanos) = stored_mtime(header, &pax);
+        let mut converted = ArchiveEntry {
+            name: entry
+                .path()
+                .map_err(TarErr::ExtractionFailed)?
+                .to_string_lossy()
+                .into_owned(),
+            kind,
+            size: entry.size(),
+            mode: header.mode().unwrap_or(0o644) & 0o7777,
+            mtime,
+            mtime_nanos,
+            owner: Some(stored_owner(header, &pax)),
+            xattrs: pax
+                .iter()
+                .filter_map(|(key, value)| {
+                    let name = key.strip_prefix(XATTR_PAX_PREFIX)?;
+                    Some((name.to_string(), value.clone()))
+                })
+                .collect(),
+            comment: String::new(),
+            extra_fields: Vec::new(),
+            encrypted: false,
+            sparse: false,
+        };
+
+        match SparseInfo::from_pax(&pax) {
+            Some(sparse) => {
+                converted.name = sparse.name.to_string_lossy().into_owned();
+                converted.size = sparse.real_size;
+                converted.sparse = true;
+                let mut data = SparseExpander::new(BufReader::new(&mut entry), sparse.real_size)
+                    .map_err(TarErr::ExtractionFailed)?;
+                add(converted, &mut data)?;
+            }
+            None => add(converted, &mut entry)?,
+        }
+    }
+
+    if encrypted {
+        io::copy(&mut archive.into_inner(), &mut io::sink())
+            .map_err(|e| TarErr::EncryptionFailed(CryptErr::from_read_error(e)))?;
+    }
+    Ok(encrypted)
+}
+
+/// Modification time of an entry in seconds and nanoseconds, preferring
+/// the PAX record over the header field.
+fn stored_mtime(header: &Header, pax: &[(String, Vec<u8>)]) -> (u64, u32) {
+    let record = pax
+        .iter()
+        .find(|(key, _)| key == "mtime")
+        .and_then(|(_, value)| std::str::from_utf8(value).ok());
+    let parsed = record.and_then(|record| {
+        let (secs, fraction) = record.split_once('.').unwrap_or((record, ""));
+        let digits: String = fraction.chars().take(9).collect();
+        let nanos = format!("{:0<9}", digits).parse().ok()?;
+        Some((secs.parse().ok()?, nanos))
+    });
+    parsed.unwrap_or_else(|| (header.mtime().unwrap_or(0), 0))
+}
+
+/// Tar output of `convert_archive`, written to a temporary file until
+/// `finish`.
+pub(crate) struct TarConvertWriter<'a> {
+    pub(crate) options: &'a TarOptions,
+    temp_dir: TempDir,
+    temp_file_path: PathBuf,
+    archive: Builder<CodecWriter<EncryptionLayer<File>>>,
+}
+
+impl<'a> TarConvertWriter<'a> {
+    /// `codec` compresses the archive; `None` writes a plain tar.
+    pub(crate) fn new(codec: Option<Codec>, options: &'a TarOptions) -> Result<Self, TarErr> {
+        let (temp_dir, temp_file_path) =
+            create_temp_file("tar").map_err(TarErr::TempFileCreationFailed)?;
+        let file = encrypted_output(&temp_file_path, options)?;
+        let compressor = match codec {
+            Some(Codec::Gzip) => {
+                let mut gzip = options.gzip.clone();
+                if let Some(reproducible) = options.reproducible {
+                    gzip.mtime = gzip
+                        .mtime
+                        .map(|mtime| reproducible.clamp_mtime(mtime.into()) as u32);
+                }
+                let encoder =
+                    GzipWriter::new(file, &gzip).map_err(TarErr::CompressionSetupFailed)?;
+                CodecWriter::Gzip(encoder)
+            }
+            Some(codec) => codec_writer(file, codec)?,
+            None => CodecWriter::Plain(file),
+        };
+        Ok(TarConvertWriter {
+            options,
+            temp_dir,
+            temp_file_path,
+            archive: Builder::new(compressor),
+        })
+    }
+
+    /// Writes `entry`, keeping all of its extended attributes unless
+    /// `options.xattrs` filters them.
+    pub(crate) fn add(&mut self, entry: &ArchiveEntry, data: &mut dyn Read) -> Result<(), TarErr> {
+        let options = self.options;
+        let mut header = match options.format {
+            TarFormat::Gnu => Header::new_gnu(),
+            TarFormat::Ustar | TarFormat::Pax => Header::new_ustar(),
+        };
+        let (entry_type, size) = match &entry.kind {
+            EntryKind::File => (EntryType::Regular, entry.size),
+            EntryKind::Directory => (EntryType::Directory, 0),
+            EntryKind::Symlink(_) => (EntryType::Symlink, 0),
+            EntryKind::HardLink(_) => (EntryType::Link, 0),
+            EntryKind::Special {
+                entry_type,
+                device: (major, minor),
+            } => {
+                header
+                    .set_device_major(*major)
+                    .map_err(TarErr::ArchiveCreationFailed)?;
+                header
+                    .set_device_minor(*minor)
+                    .map_err(TarErr::ArchiveCreationFailed)?;
+                (*entry_type, 0)
+            }
+        };
+        header.set_entry_type(entry_type);
+        header.set_size(size);
+        let stamp = match options.reproducible {
+            Some(reproducible) => {
+                header.set_mtime(reproducible.clamp_mtime(entry.mtime));
+                header.set_mode(reproducible.normalize_mode(entry.mode, entry_type.is_dir()));
+                EntryStamp {
+                    owner: Owner::default(),
+                    nanos: 0,
+                }
+            }
+            None => {
+                header.set_mtime(entry.mtime);
+                header.set_mode(entry.mode);
+                EntryStamp {
+                    owner: entry.owner.clone().unwrap_or_default(),
+                    nanos: entry.mtime_nanos,
+                }
+            }
+        };
+        let pax = entry
+            .xattrs
+            .iter()
+            .filter(|(name, _)| {
+                options
+                    .xattrs
+                    .as_ref()
+                    .is_none_or(|filter| filter.matches(name))
+            })
+            .map(|(name, value)| (format!("{}{}", XATTR_PAX_PREFIX, name), value.clone()))
+            .collect();
+
+        let name = Path::new(&entry.name);
+        match &entry.kind {
+            EntryKind::Symlink(target) | EntryKind::HardLink(target) => {
+                let link = EntryData::link(name, Path::new(target));
+                write_entry(&mut self.archive, header, link, pax, stamp, options)
+            }
+            _ => {
+                let entry = EntryData::data(name, data);
+                write_entry(&mut self.archive, header, entry, pax, stamp, options)
+            }
+        }
+    }
+
+    pub(crate) fn finish(self, output_path: &Path) -> Result<(), TarErr> {
+        self.archive
+            .into_inner()
+            .map_err(TarErr::ArchiveCreationFailed)?
+            .finish()
+            .map_err(TarErr::ArchiveCreationFailed)?
+            .finish()
+            .map_err(TarErr::ArchiveCreationFailed)?;
+        finish_output(
+            self.temp_dir,
+            &self.temp_file_path,
+            output_path,
+            self.options,
+        )
+    }
+}
+
+/// Owner recorded for an entry, preferring PAX records over the header fields.
+fn stored_owner(header: &Header, pax: &[(String, Vec<u8>)]) -> Owner {
+    let record = |key: &str| {
+        pax.iter()
+            .find(|(name, _)| name == key)
+            .and_then(|(_, value)| String::from_utf8(value.clone()).ok())
+    };
+    let header_name = |name: Result<Option<&str>, std::str::Utf8Error>| {
+        name.ok()
+            .flatten()
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+    };
+    Owner {
+        uid: record("uid")
+            .and_then(|uid| uid.parse().ok())
+            .or_else(|| header.uid().ok())
+            .unwrap_or(0),
+        gid: record("gid")
+            .and_then(|gid| gid.parse().ok())
+            .or_else(|| header.gid().ok())
+            .unwrap_or(0),
+        uname: record("uname").or_else(|| header_name(header.username())),
+        gname: record("gname").or_else(|| header_name(header.groupname())),
+    }
+}
+
+/// Applies `ExtractOptions::owner` and changes the owner of `path` (not of
+/// the target of a symbolic link). Names that exist locally win over the
+/// stored ids unless `numeric_owner` is set, as with GNU tar.
+#[cfg(unix)]
+fn restore_owner(path: &Path, stored: Owner, options: &ExtractOptions) -> Result<(), TarErr> {
+    if !options.restore_owner {
+        return Ok(());
+    }
+    let mut owner = options.owner.apply(stored);
+    if !options.numeric_owner {
+        if let Some(uid) = owner.uname.as_deref().and_then(user_id) {
+            owner.uid = uid;
+        }
+        if let Some(gid) = owner.gname.as_deref().and_then(group_id) {
+            owner.gid = gid;
+        }
+    }
+    let failed = |e| TarErr::OwnershipFailed(path.to_path_buf(), e);
+    let out_of_range = || {
+        failed(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "id out of range",
+        ))
+    };
+    let uid = u32::try_from(owner.uid).map_err(|_| out_of_range())?;
+    let gid = u32::try_from(owner.gid).map_err(|_| out_of_range())?;
+    std::os::unix::fs::lchown(path, Some(uid), Some(gid)).map_err(failed)
+}
+
+#[cfg(not(unix))]
+fn restore_owner(_path: &Path, _stored: Owner, _options: &ExtractOptions) -> Result<(), TarErr> {
     Ok(())
 }
+
+fn pax_records<R: Read>(entry: &mut tar::Entry<'_, R>) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let mut records = Vec::new();
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let extension = extension?;
+            if let Ok(key) = extension.key() {
+                records.push((key.to_string(), extension.value_bytes().to_vec()));
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// Real name and size of a PAX 1.0 sparse entry.
+struct SparseInfo {
+    name: PathBuf,
+    real_size: u64,
+}
+
+impl SparseInfo {
+    fn from_pax(records: &[(String, Vec<u8>)]) -> Option<Self> {
+        let value = |key: &str| {
+            records
+                .iter()
+                .find(|(record, _)| record == key)
+                .and_then(|(_, value)| std::str::from_utf8(value).ok())
+        };
+        if value("GNU.sparse.major")? != "1" {
+            return None;
+        }
+        Some(SparseInfo {
+            name: PathBuf::from(value("GNU.sparse.name")?),
+            real_size: value("GNU.sparse.realsize")?.parse().ok()?,
+        })
+    }
+}
+
+/// Recreates a sparse file from a PAX 1.0 entry body, seeking over the holes.
+fn unpack_sparse<R: Read>(
+    entry: &mut tar::Entry<'_, R>,
+    target: &Path,
+    real_size: u64,
+) -> io::Result<()> {
+    let header = entry.header().clone();
+    let mut reader = BufReader::new(entry);
+    let regions = read_sparse_map(&mut reader)?;
+
+    // Replace a link left at the target instead of writing through it.
+    if fs::symlink_metadata(target).is_ok_and(|metadata| !metadata.is_dir()) {
+        fs::remove_file(target)?;
+    }
+    let mut file = File::options().write(true).create_new(true).open(target)?;
+    for (offset, len) in regions {
+        file.seek(SeekFrom::Start(offset))?;
+        if io::copy(&mut (&mut reader).take(len), &mut file)? != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "sparse entry is truncated",
+            ));
+        }
+    }
+    file.set_len(real_size)?;
+
+    if let Ok(mtime) = header.mtime() {
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+    }
+    #[cfg(unix)]
+    if let Ok(mode) = header.mode() {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(target, fs::Permissions::from_mode(mode & 0o777))?;
+    }
+    Ok(())
+}
+
+/// Reads the `(offset, length)` data regions at the start of a PAX 1.0
+/// sparse entry body, leaving `reader` at the first byte of data.
+fn read_sparse_map<R: BufRead>(reader: &mut R) -> io::Result<Vec<(u64, u64)>> {
+    let mut consumed = 0;
+    let mut read_number = |reader: &mut R| -> io::Result<u64> {
+        let mut line = Vec::new();
+        consumed += reader.read_until(b'\n', &mut line)?;
+        std::str::from_utf8(&line)
+            .ok()
+            .and_then(|line| line.trim_end().parse().ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed sparse map"))
+    };
+
+    let count = read_number(reader)?;
+    let mut regions = Vec::new();
+    for _ in 0..count {
+        regions.push((read_number(reader)?, read_number(reader)?));
+    }
+    let padding = (512 - consumed % 512) % 512;
+    io::copy(&mut reader.take(padding as u64), &mut io::sink())?;
+    Ok(regions)
+}
+
+/// Reads a PAX 1.0 sparse entry body as the file it stands for, with the
+/// holes as zeros.
+struct SparseExpander<R> {
+    reader: R,
+    regions: Vec<(u64, u64)>,
+    /// Index of the first region not read to its end.
+    region: usize,
+    position: u64,
+    real_size: u64,
+}
+
+impl<R: BufRead> SparseExpander<R> {
+    fn new(mut reader: R, real_size: u64) -> io::Result<Self> {
+        let regions = read_sparse_map(&mut reader)?;
+        Ok(SparseExpander {
+            reader,
+            regions,
+            region: 0,
+            position: 0,
+            real_size,
+        })
+    }
+}
+
+impl<R: BufRead> Read for SparseExpander<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(&(offset, len)) = self.regions.get(self.region) {
+            if self.position < offset + len {
+                break;
+            }
+            self.region += 1;
+        }
+        let (hole_end, data) = match self.regions.get(self.region) {
+            Some(&(offset, _)) if self.position < offset => (offset, false),
+            Some(&(offset, len)) => (offset + len, true),
+            None => (self.real_size, false),
+        };
+        let remaining = hole_end.min(self.real_size).saturating_sub(self.position);
+        let len = buf
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        if len == 0 {
+            return Ok(0);
+        }
+        let read = match data {
+            true => match self.reader.read(&mut buf[..len])? {
+                0 => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "sparse entry is truncated",
+                    ))
+                }
+                read => read,
+            },
+            false => {
+                buf[..len].fill(0);
+                len
+            }
+        };
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn write_xattrs(path: &Path, xattrs: &[(String, Vec<u8>)]) -> Result<(), TarErr> {
+    for (name, value) in xattrs {
+        xattr::set(path, name, value).map_err(|e| TarErr::XattrFailed(path.to_path_buf(), e))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn write_xattrs(_path: &Path, _xattrs: &[(String, Vec<u8>)]) -> Result<(), TarErr> {
+    Ok(())
+}
+
+/// A link entry, restored once every other entry is on disk so that links
+/// cannot redirect later entries and copies find their target.
+struct PendingLink {
+    path: PathBuf,
+    relative: PathBuf,
+    target: PathBuf,
+    is_symlink: bool,
+    owner: Owner,
+}
+
+/// Creates the deferred links in archive order. A hard link or followed
+/// copy whose source is not there yet, because it is itself a later link,
+/// is retried until a round makes no progress.
+fn restore_links(
+    root: &Path,
+    mut links: Vec<PendingLink>,
+    options: &ExtractOptions,
+) -> Result<(), TarErr> {
+    let mut created = Vec::new();
+    while !links.is_empty() {
+        let count = links.len();
+        let mut waiting = Vec::new();
+        for link in links {
+            match restore_link(root, &link, options)? {
+                true => created.push(link),
+                false => waiting.push(link),
+            }
+        }
+        if waiting.len() == count {
+            let link = &waiting[0];
+            return Err(TarErr::ExtractionFailed(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "target {} of link {} was not extracted",
+                    link.target.display(),
+                    link.path.display()
+                ),
+            )));
+        }
+        links = waiting;
+    }
+
+    // A link created early can be redirected by a later one, so check every
+    // symbolic link against the finished tree.
+    for link in &created {
+        let link_path = root.join(&link.relative);
+        if link_escapes(root, &link_path) {
+            let target = fs::read_link(&link_path).map_err(TarErr::ExtractionFailed)?;
+            fs::remove_file(&link_path).map_err(TarErr::ExtractionFailed)?;
+            return Err(TarErr::UnsafeLinkTarget {
+                path: link.path.clone(),
+                target,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Restores one link, or returns `false` when its source does not exist yet.
+fn restore_link(root: &Path, link: &PendingLink, options: &ExtractOptions) -> Result<bool, TarErr> {
+    let unsafe_target = || TarErr::UnsafeLinkTarget {
+        path: link.path.clone(),
+        target: link.target.clone(),
+    };
+    if !prepare_parent(root, &link.relative, false).map_err(TarErr::ExtractionFailed)? {
+        return Err(TarErr::UnsafeEntryPath(link.path.clone()));
+    }
+    let link_path = root.join(&link.relative);
+    let parent = link_path.parent().unwrap_or(root);
+    let source = match link.is_symlink {
+        true => resolve_on_disk(root, parent, &link.target, true),
+        false => resolve_on_disk(root, root, &link.target, false),
+    }
+    .ok_or_else(unsafe_target)?;
+    let needs_source = !link.is_symlink || options.symlinks == SymlinkPolicy::Follow;
+    if needs_source && fs::symlink_metadata(&source).is_err() {
+        return Ok(false);
+    }
+    if fs::symlink_metadata(&link_path).is_ok_and(|metadata| !metadata.is_dir()) {
+        fs::remove_file(&link_path).map_err(TarErr::ExtractionFailed)?;
+    }
+
+    match (link.is_symlink, options.symlinks) {
+        (false, _) => fs::hard_link(&source, &link_path).map_err(TarErr::ExtractionFailed)?,
+        (true, SymlinkPolicy::Reject) => {
+            return Err(TarErr::SymlinkRejected(link.relative.clone()))
+        }
+        (true, SymlinkPolicy::Follow) => {
+            if link_path.starts_with(&source) {
+                return Err(unsafe_target());
+            }
+            copy_followed(root, &source, &link_path, link)?;
+        }
+        (true, SymlinkPolicy::Preserve) => create_symlink(&link.target, &link_path)?,
+    }
+    restore_owner(&link_path, link.owner.clone(), options)?;
+    Ok(true)
+}
+
+/// Copies `source` to `target` for a followed link, recursing into
+/// directories. Links inside a copied directory are followed only to
+/// regular files inside `root`.
+fn copy_followed(
+    root: &Path,
+    source: &Path,
+    target: &Path,
+    link: &PendingLink,
+) -> Result<(), TarErr> {
+    let metadata = fs::metadata(source).map_err(TarErr::ExtractionFailed)?;
+    if !metadata.is_dir() {
+        return fs::copy(source, target)
+            .map(|_| ())
+            .map_err(TarErr::ExtractionFailed);
+    }
+    fs::create_dir_all(target).map_err(TarErr::ExtractionFailed)?;
+    for child in fs::read_dir(source).map_err(TarErr::ExtractionFailed)? {
+        let child = child.map_err(TarErr::ExtractionFailed)?;
+        let mut child_source = child.path();
+        if child
+            .file_type()
+            .map_err(TarErr::ExtractionFailed)?
+            .is_symlink()
+        {
+            child_source = resolve_on_disk(root, source, Path::new(&child.file_name()), true)
+                .filter(|resolved| resolved.is_file())
+                .ok_or_else(|| TarErr::UnsafeLinkTarget {
+                    path: link.path.clone(),
+                    target: child.path(),
+                })?;
+        }
+        copy_followed(root, &child_source, &target.join(child.file_name()), link)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, link_path: &Path) -> Result<(), TarErr> {
+    std::os::unix::fs::symlink(target, link_path).map_err(TarErr::ExtractionFailed)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, link_path: &Path) -> Result<(), TarErr> {
+    let resolved = link_path.parent().map(|parent| parent.join(target));
+    match resolved.is_some_and(|resolved| resolved.is_dir()) {
+        true => std::os::windows::fs::symlink_dir(target, link_path),
+        false => std::os::windows::fs::symlink_file(target, link_path),
+    }
+    .map_err(TarErr::ExtractionFailed)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn create_symlink(_target: &Path, _link_path: &Path) -> Result<(), TarErr> {
+    Err(TarErr::ExtractionFailed(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symbolic links are not supported on this platform",
+    )))
+}
+
+/// Opens a tar file, or its `.partNNN` volumes when it was split, decrypting
+/// it when it is an age file and wrapping it in the decoder matching its
+/// magic bytes. Also tells whether it was encrypted.
+fn open_tar_reader(
+    path: &Path,
+    key: Option<&EncryptionKey>,
+) -> Result<(Box<dyn Read>, bool), TarErr> {
+    let mut reader = BufReader::new(VolumeReader::open(path).map_err(TarErr::ExtractionFailed)?);
+    let magic = reader.fill_buf().map_err(TarErr::ExtractionFailed)?;
+    if !magic.starts_with(AGE_MAGIC) {
+        let reader = decompressing_reader(reader).map_err(TarErr::ExtractionFailed)?;
+        return Ok((reader, false));
+    }
+    let key = key.ok_or(TarErr::EncryptionFailed(CryptErr::KeyRequired))?;
+    let reader = DecryptReader::new(reader, key).map_err(TarErr::EncryptionFailed)?;
+    let reader = decompressing_reader(BufReader::new(reader)).map_err(TarErr::ExtractionFailed)?;
+    Ok((reader, true))
+}
+
+fn codec_writer(
+    file: EncryptionLayer<File>,
+    codec: Codec,
+) -> Result<CodecWriter<EncryptionLayer<File>>, TarErr> {
+    CodecWriter::new(file, codec, codec.default_level()).map_err(TarErr::ArchiveCreationFailed)
+}
+
+fn decompressing_reader<R: BufRead + 'static>(mut reader: R) -> io::Result<Box<dyn Read>> {
+    // Zlib headers are too short to tell a tar stream from a zlib one.
+    match Codec::detect(reader.fill_buf()?) {
+        Some(Codec::Zlib) | None => Ok(Box::new(reader)),
+        Some(codec) => decoder(reader, codec),
+    }
+}
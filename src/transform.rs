@@ -0,0 +1,180 @@
+use regex::Regex;
+use std::{
+    error::Error,
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+
+#[derive(Debug)]
+pub enum TransformErr {
+    InvalidPattern(regex::Error),
+    NonUnicodePath(PathBuf),
+    EscapingPath {
+        original: PathBuf,
+        rewritten: PathBuf,
+    },
+}
+
+impl fmt::Display for TransformErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransformErr::InvalidPattern(e) => write!(f, "Invalid path rewrite pattern: {}", e),
+            TransformErr::NonUnicodePath(path) => {
+                write!(
+                    f,
+                    "Cannot apply a pattern to non-UTF-8 path {}",
+                    path.display()
+                )
+            }
+            TransformErr::EscapingPath {
+                original,
+                rewritten,
+            } => write!(
+                f,
+                "Rewriting {} produced {}, which is not a relative path inside the archive",
+                original.display(),
+                rewritten.display()
+            ),
+        }
+    }
+}
+
+impl Error for TransformErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TransformErr::InvalidPattern(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A single rewrite step of a `PathTransform`.
+#[derive(Debug, Clone)]
+pub enum PathRule {
+    /// Prepend a directory, e.g. `myapp-1.2.3`.
+    AddPrefix(PathBuf),
+    /// Remove a leading path if present; other paths are left untouched.
+    StripPrefix(PathBuf),
+    /// Drop this many leading components, like `tar --strip-components`.
+    /// Paths with no components left are skipped.
+    StripComponents(usize),
+    /// Regex substitution on the `/`-separated path, like `tar --transform`.
+    /// The replacement may refer to groups as `$1` or `${name}`.
+    Replace {
+        pattern: Regex,
+        replacement: String,
+        all: bool,
+    },
+}
+
+/// Ordered list of rules rewriting entry names when creating or extracting
+/// archives. Rewritten paths must stay relative and free of `..`.
+#[derive(Debug, Clone, Default)]
+pub struct PathTransform {
+    pub rules: Vec<PathRule>,
+}
+
+impl PathTransform {
+    pub fn add_prefix<P: Into<PathBuf>>(mut self, prefix: P) -> Self {
+        self.rules.push(PathRule::AddPrefix(prefix.into()));
+        self
+    }
+
+    pub fn strip_prefix<P: Into<PathBuf>>(mut self, prefix: P) -> Self {
+        self.rules.push(PathRule::StripPrefix(prefix.into()));
+        self
+    }
+
+    pub fn strip_components(mut self, count: usize) -> Self {
+        self.rules.push(PathRule::StripComponents(count));
+        self
+    }
+
+    /// Replaces the first match of `pattern`.
+    pub fn replace<S: Into<String>>(
+        self,
+        pattern: &str,
+        replacement: S,
+    ) -> Result<Self, TransformErr> {
+        self.push_replace(pattern, replacement.into(), false)
+    }
+
+    /// Replaces every match of `pattern`.
+    pub fn replace_all<S: Into<String>>(
+        self,
+        pattern: &str,
+        replacement: S,
+    ) -> Result<Self, TransformErr> {
+        self.push_replace(pattern, replacement.into(), true)
+    }
+
+    fn push_replace(
+        mut self,
+        pattern: &str,
+        replacement: String,
+        all: bool,
+    ) -> Result<Self, TransformErr> {
+        let pattern = Regex::new(pattern).map_err(TransformErr::InvalidPattern)?;
+        self.rules.push(PathRule::Replace {
+            pattern,
+            replacement,
+            all,
+        });
+        Ok(self)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Rewrites `path` through every rule in order. Returns `None` when the
+    /// entry should be skipped because nothing is left of its path.
+    pub fn apply(&self, path: &Path) -> Result<Option<PathBuf>, TransformErr> {
+        if self.rules.is_empty() {
+            return Ok(Some(path.to_path_buf()));
+        }
+        let mut current = path.to_path_buf();
+        for rule in &self.rules {
+            current = match rule {
+                PathRule::AddPrefix(prefix) => prefix.join(&current),
+                PathRule::StripPrefix(prefix) => match current.strip_prefix(prefix) {
+                    Ok(rest) => rest.to_path_buf(),
+                    Err(_) => current,
+                },
+                PathRule::StripComponents(count) => current.components().skip(*count).collect(),
+                PathRule::Replace {
+                    pattern,
+                    replacement,
+                    all,
+                } => {
+                    let text = current
+                        .iter()
+                        .map(|part| part.to_str())
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or_else(|| TransformErr::NonUnicodePath(path.to_path_buf()))?
+                        .join("/");
+                    let rewritten = match all {
+                        true => pattern.replace_all(&text, replacement.as_str()),
+                        false => pattern.replace(&text, replacement.as_str()),
+                    };
+                    PathBuf::from(rewritten.into_owned())
+                }
+            };
+        }
+
+        let mut relative = PathBuf::new();
+        for component in current.components() {
+            match component {
+                Component::Normal(part) => relative.push(part),
+                Component::CurDir => {}
+                _ => {
+                    return Err(TransformErr::EscapingPath {
+                        original: path.to_path_buf(),
+                        rewritten: current,
+                    })
+                }
+            }
+        }
+        Ok((!relative.as_os_str().is_empty()).then_some(relative))
+    }
+}
@@ -1,12 +1,15 @@
 // utils.rs
 use std::{
+    env,
     error::Error,
+    ffi::{OsStr, OsString},
     fmt,
-    fs::{self, DirBuilder, File},
+    fs::{self, DirBuilder},
     io,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
+    time::UNIX_EPOCH,
 };
-use tempfile::{tempdir, tempfile, TempDir};
+use tempfile::{tempdir, TempDir};
 
 #[derive(Debug)]
 pub enum UtilsErr {
@@ -41,6 +44,66 @@ impl fmt::Display for UtilsErr {
 
 impl Error for UtilsErr {}
 
+/// Settings for byte-for-byte reproducible archives: entries are sorted,
+/// modification times are clamped to `timestamp`, ownership is zeroed and
+/// permissions are normalised to `0o644`/`0o755`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reproducible {
+    pub timestamp: u64,
+}
+
+impl Reproducible {
+    pub fn new(timestamp: u64) -> Self {
+        Reproducible { timestamp }
+    }
+
+    /// Uses the `SOURCE_DATE_EPOCH` environment variable, if it is set to a
+    /// valid number of seconds.
+    pub fn from_source_date_epoch() -> Option<Self> {
+        env::var("SOURCE_DATE_EPOCH")
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .map(Reproducible::new)
+    }
+
+    pub fn clamp_mtime(&self, mtime: u64) -> u64 {
+        mtime.min(self.timestamp)
+    }
+
+    pub fn normalize_mode(&self, mode: u32, is_dir: bool) -> u32 {
+        if is_dir || mode & 0o100 != 0 {
+            0o755
+        } else {
+            0o644
+        }
+    }
+}
+
+pub(crate) fn modified_secs(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(unix)]
+pub(crate) fn metadata_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn metadata_mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.is_dir() {
+        0o755
+    } else if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    }
+}
+
 pub fn create_temp_file(extension: &str) -> Result<(TempDir, PathBuf), UtilsErr> {
     let temp_dir = tempdir().map_err(UtilsErr::TempFileCreationFailed)?;
     let temp_file_path = temp_dir.path().join(format!("temp_file.{}", extension));
@@ -69,9 +132,139 @@ pub fn prepare_directory_with_files<P: AsRef<Path>>(
     for file_path in files {
         if let Some(filename) = file_path.as_ref().file_name() {
             let target_path = target_directory.as_ref().join(filename);
-            fs::copy(&file_path, &target_path).map_err(UtilsErr::FileCopyFailed)?;
+            fs::copy(file_path, &target_path).map_err(UtilsErr::FileCopyFailed)?;
         }
     }
 
     Ok(())
 }
+
+/// Lexically resolves `path` against `base`, returning `None` when the result
+/// is absolute or climbs above the root it is relative to.
+pub(crate) fn contained_path(base: &Path, path: &Path) -> Option<PathBuf> {
+    let mut resolved: Vec<&OsStr> = base
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop()?;
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(resolved.iter().collect())
+}
+
+/// Symbolic links followed while resolving one link target, as with `ELOOP`.
+const MAX_LINK_HOPS: usize = 40;
+
+/// Creates the missing directories of `relative` under the canonical
+/// `root`. Symbolic links met on the way must resolve inside `root`, and are
+/// refused outright unless `through_links` is set. Returns `false` when the
+/// path was refused.
+pub(crate) fn create_contained_dir(
+    root: &Path,
+    relative: &Path,
+    through_links: bool,
+) -> io::Result<bool> {
+    let mut current = root.to_path_buf();
+    for component in relative.components() {
+        current.push(component);
+        match fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                if !through_links {
+                    return Ok(false);
+                }
+                match current.canonicalize() {
+                    Ok(real) if real.starts_with(root) => current = real,
+                    _ => return Ok(false),
+                }
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => fs::create_dir(&current)?,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+/// `create_contained_dir` for the parent directory of `relative`.
+pub(crate) fn prepare_parent(
+    root: &Path,
+    relative: &Path,
+    through_links: bool,
+) -> io::Result<bool> {
+    match relative.parent() {
+        Some(parent) => create_contained_dir(root, parent, through_links),
+        None => Ok(true),
+    }
+}
+
+/// Resolves `target` from the directory `start` the way the file system
+/// would, following the symbolic links already on disk. Returns `None` when
+/// the result leaves the canonical `root`. With `follow_last` unset the
+/// final component is left alone even when it is a link, as `link()` does.
+pub(crate) fn resolve_on_disk(
+    root: &Path,
+    start: &Path,
+    target: &Path,
+    follow_last: bool,
+) -> Option<PathBuf> {
+    // Components still to walk, last one first; `None` stands for `..`.
+    fn push_components(pending: &mut Vec<Option<OsString>>, path: &Path) -> Option<()> {
+        let mut components = Vec::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(part) => components.push(Some(part.to_os_string())),
+                Component::ParentDir => components.push(None),
+                Component::CurDir => {}
+                Component::RootDir | Component::Prefix(_) => return None,
+            }
+        }
+        pending.extend(components.into_iter().rev());
+        Some(())
+    }
+
+    let mut current = start.to_path_buf();
+    let mut pending = Vec::new();
+    push_components(&mut pending, target)?;
+    let mut hops = 0;
+    while let Some(component) = pending.pop() {
+        let Some(part) = component else {
+            if current == root || !current.pop() {
+                return None;
+            }
+            continue;
+        };
+        current.push(part);
+        let is_link =
+            fs::symlink_metadata(&current).is_ok_and(|metadata| metadata.file_type().is_symlink());
+        if is_link && (follow_last || !pending.is_empty()) {
+            hops += 1;
+            if hops > MAX_LINK_HOPS {
+                return None;
+            }
+            let link = fs::read_link(&current).ok()?;
+            current.pop();
+            push_components(&mut pending, &link)?;
+        }
+    }
+    current.starts_with(root).then_some(current)
+}
+
+/// Whether `path` is a symbolic link that resolves outside the canonical
+/// `root`, through the links around it as they are now.
+pub(crate) fn link_escapes(root: &Path, path: &Path) -> bool {
+    let Ok(target) = fs::read_link(path) else {
+        return false;
+    };
+    let parent = path.parent().unwrap_or(root);
+    resolve_on_disk(root, parent, &target, true).is_none()
+}
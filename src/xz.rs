@@ -1,16 +1,13 @@
+use crate::crypt::{CryptErr, EncryptionKey, EncryptionLayer};
 use crate::utils::{create_temp_file, make_permanent, UtilsErr};
-use std::{
-    error::Error,
-    fs::File,
-    io::{self, Write},
-    path::Path,
-};
+use std::{error::Error, fs::File, io, path::Path};
 use xz2::write::XzEncoder;
 
 #[derive(Debug)]
 pub enum Xz {
     TempFileCreationFailed(UtilsErr),
     CompressionFailed(io::Error),
+    EncryptionFailed(CryptErr),
     TempFileFinalizationFailed(UtilsErr),
 }
 
@@ -23,6 +20,7 @@ impl std::fmt::Display for Xz {
                 write!(f, "Temporary file creation failed: {}", e)
             }
             Xz::CompressionFailed(e) => write!(f, "Bzip2 compression failed: {}", e),
+            Xz::EncryptionFailed(e) => write!(f, "Encryption failed: {}", e),
             Xz::TempFileFinalizationFailed(e) => {
                 write!(f, "Failed to finalize the temporary file: {}", e)
             }
@@ -31,20 +29,37 @@ impl std::fmt::Display for Xz {
 }
 
 pub fn create_file_xz<P: AsRef<Path>>(input_path: P, output_path: P) -> Result<(), Xz> {
+    write_file_xz(input_path.as_ref(), output_path.as_ref(), None)
+}
+
+/// Like `create_file_xz`, with the compressed output encrypted with age.
+pub fn create_file_xz_encrypted<P: AsRef<Path>>(
+    input_path: P,
+    output_path: P,
+    key: &EncryptionKey,
+) -> Result<(), Xz> {
+    write_file_xz(input_path.as_ref(), output_path.as_ref(), Some(key))
+}
+
+fn write_file_xz(
+    input_path: &Path,
+    output_path: &Path,
+    key: Option<&EncryptionKey>,
+) -> Result<(), Xz> {
     let (temp_dir, temp_file_path) = create_temp_file("xz").map_err(Xz::TempFileCreationFailed)?;
     let temp_file = File::create(&temp_file_path).map_err(Xz::CompressionFailed)?;
-    let mut encoder = XzEncoder::new(temp_file, 9);
+    let output = EncryptionLayer::new(temp_file, key).map_err(Xz::EncryptionFailed)?;
+    let mut encoder = XzEncoder::new(output, 9);
 
     let mut input_file = File::open(input_path).map_err(Xz::CompressionFailed)?;
     io::copy(&mut input_file, &mut encoder).map_err(Xz::CompressionFailed)?;
-    encoder.finish().map_err(|_| {
-        Xz::CompressionFailed(io::Error::new(
-            io::ErrorKind::Other,
-            "Failed to finish compression",
-        ))
-    })?;
-
-    make_permanent(temp_dir, &temp_file_path, output_path.as_ref())
+    encoder
+        .finish()
+        .map_err(|_| Xz::CompressionFailed(io::Error::other("Failed to finish compression")))?
+        .finish()
+        .map_err(Xz::CompressionFailed)?;
+
+    make_permanent(temp_dir, &temp_file_path, output_path)
         .map_err(Xz::TempFileFinalizationFailed)?;
     Ok(())
 }
@@ -1,15 +1,38 @@
+use crate::entry::{ArchiveEntry, EntryKind};
+use crate::filter::{resolve_files, FileFilter, FilterErr};
+use crate::manifest::{HashingReader, ManifestBuilder, ManifestOptions};
+use crate::split::{VolumeReader, VolumeWriter};
+use crate::transform::{PathTransform, TransformErr};
 use crate::utils::UtilsErr;
-use crate::utils::{create_temp_file, make_permanent};
+use crate::utils::{
+    contained_path, create_contained_dir, create_temp_file, link_escapes, make_permanent,
+    metadata_mode, modified_secs, prepare_parent, resolve_on_disk, Reproducible,
+};
+use bzip2::read::BzDecoder;
+use encoding_rs::Encoding;
+use flate2::read::DeflateDecoder;
+use flate2::Crc;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use std::{
+    collections::{HashMap, HashSet},
     error::Error,
-    fmt::{self, Display},
+    fmt,
     fs::{self, File},
-    io::{self, Write},
-    path::Path,
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tempfile::TempDir;
+use xz2::read::XzDecoder;
+use xz2::stream::{LzmaOptions, Stream};
+use xz2::write::XzEncoder;
+use zip::read::{ZipArchive, ZipFile};
 use zip::result::ZipError;
-use zip::write::{FileOptions, ZipWriter};
+use zip::unstable::write::FileOptionsExt;
+use zip::write::{FullFileOptions, SimpleFileOptions, StreamWriter, ZipWriter};
+use zip::{AesMode, CompressionMethod, DateTime};
+use zstd::stream::read::Decoder as ZstdDecoder;
 
 #[derive(Debug)]
 pub enum ZippingErr {
@@ -19,6 +42,26 @@ pub enum ZippingErr {
     WriteFailed(io::Error),
     FinishFailed(zip::result::ZipError),
     ZipOperationFailed(ZipError),
+    FileSelectionFailed(FilterErr),
+    PathTransformFailed(TransformErr),
+    EntryNotFound(String),
+    DuplicateEntry(String),
+    InvalidPattern(globset::Error),
+    PasswordRequired(String),
+    WrongPassword(String),
+    CorruptEntry(String, io::Error),
+    EncryptionUnsupported(String),
+    UnsafeEntryPath(String),
+    ExtractionFailed(io::Error),
+    FieldTooLong(String),
+    ReservedExtraField(u16),
+    StreamReadFailed(io::Error),
+    MalformedStream(String),
+    InvalidVolumeSize(u64),
+    ManifestWriteFailed(io::Error),
+    TempFileFinalizationFailed(UtilsErr),
+    EntryTooLarge(String),
+    UnsupportedEditOption(&'static str),
 }
 
 impl fmt::Display for ZippingErr {
@@ -32,10 +75,68 @@ impl fmt::Display for ZippingErr {
             ZippingErr::WriteFailed(e) => write!(f, "Failed to write to the zip archive: {}", e),
             ZippingErr::FinishFailed(e) => write!(f, "Failed to finalize the zip archive: {}", e),
             ZippingErr::ZipOperationFailed(e) => write!(f, "Zip operation failed: {}", e),
+            ZippingErr::FileSelectionFailed(e) => write!(f, "Failed to select input files: {}", e),
+            ZippingErr::PathTransformFailed(e) => {
+                write!(f, "Failed to rewrite an entry path: {}", e)
+            }
+            ZippingErr::EntryNotFound(name) => write!(f, "No entry named {} in the archive", name),
+            ZippingErr::DuplicateEntry(name) => {
+                write!(f, "The archive already contains an entry named {}", name)
+            }
+            ZippingErr::InvalidPattern(e) => write!(f, "Invalid entry name pattern: {}", e),
+            ZippingErr::PasswordRequired(name) => {
+                write!(f, "Entry {} is encrypted and no password was given", name)
+            }
+            ZippingErr::WrongPassword(name) => write!(f, "Wrong password for entry {}", name),
+            ZippingErr::CorruptEntry(name, e) => write!(f, "Entry {} is corrupt: {}", name, e),
+            ZippingErr::EncryptionUnsupported(name) => {
+                write!(
+                    f,
+                    "Entry {} cannot be encrypted with its compression method",
+                    name
+                )
+            }
+            ZippingErr::UnsafeEntryPath(name) => {
+                write!(f, "Refusing to extract {} outside the destination", name)
+            }
+            ZippingErr::ExtractionFailed(e) => {
+                write!(f, "Failed to extract the zip archive: {}", e)
+            }
+            ZippingErr::FieldTooLong(field) => {
+                write!(f, "{} is longer than {} bytes", field, MAX_FIELD_LEN)
+            }
+            ZippingErr::ReservedExtraField(id) => {
+                write!(f, "Extra field {:#06x} is managed by zippylib", id)
+            }
+            ZippingErr::StreamReadFailed(e) => write!(f, "Failed to read the zip stream: {}", e),
+            ZippingErr::MalformedStream(reason) => write!(f, "Malformed zip stream: {}", reason),
+            ZippingErr::InvalidVolumeSize(size) => write!(
+                f,
+                "Zip volumes must hold at least {} bytes, got {}",
+                MIN_VOLUME_SIZE, size
+            ),
+            ZippingErr::ManifestWriteFailed(e) => write!(f, "Failed to write the manifest: {}", e),
+            ZippingErr::TempFileFinalizationFailed(e) => {
+                write!(f, "Failed to finalize the temporary file: {}", e)
+            }
+            ZippingErr::EntryTooLarge(name) => {
+                write!(
+                    f,
+                    "Entry {} is too large for LZMA compression (4 GiB)",
+                    name
+                )
+            }
+            ZippingErr::UnsupportedEditOption(option) => write!(
+                f,
+                "ZipOptions::{} cannot be applied to an existing archive",
+                option
+            ),
         }
     }
 }
 
+impl Error for ZippingErr {}
+
 impl From<std::io::Error> for ZippingErr {
     fn from(error: std::io::Error) -> Self {
         ZippingErr::FileOpenFailed(error)
@@ -51,33 +152,3041 @@ impl From<ZipError> for ZippingErr {
     }
 }
 
+/// Compression method of a zip entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum ZipMethod {
+    Stored,
+    #[default]
+    Deflated,
+    Bzip2,
+    Zstd,
+    /// LZMA (method 14), with an end-of-stream marker. Entries are marked
+    /// as needing version 6.3, the first APPNOTE version with LZMA, so
+    /// readers without LZMA support such as Info-ZIP `unzip` 6.0 skip them.
+    Lzma,
+    /// XZ (method 95).
+    Xz,
+}
+
+impl From<ZipMethod> for CompressionMethod {
+    fn from(method: ZipMethod) -> Self {
+        match method {
+            ZipMethod::Stored => CompressionMethod::Stored,
+            ZipMethod::Deflated => CompressionMethod::Deflated,
+            ZipMethod::Bzip2 => CompressionMethod::Bzip2,
+            ZipMethod::Zstd => CompressionMethod::Zstd,
+            ZipMethod::Lzma => CompressionMethod::Lzma,
+            ZipMethod::Xz => CompressionMethod::Xz,
+        }
+    }
+}
+
+/// Extensions of formats that are already compressed and gain nothing from
+/// another pass.
+const PRECOMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "avif", "br", "bz2", "docx", "flac", "gif", "gz", "heic", "jar", "jpeg", "jpg", "lz4",
+    "m4a", "mkv", "mov", "mp3", "mp4", "ogg", "opus", "png", "pptx", "rar", "webm", "webp", "xlsx",
+    "xz", "zip", "zst",
+];
+
+/// Chooses the compression method of each zip entry.
+#[derive(Clone)]
+pub enum MethodSelector {
+    /// The same method for every entry.
+    Fixed(ZipMethod),
+    /// Looks up the lowercase file extension, falling back to `default`.
+    ByExtension {
+        methods: HashMap<String, ZipMethod>,
+        default: ZipMethod,
+    },
+    /// Called with the source path and the entry name.
+    Callback(MethodCallback),
+}
+
+/// Picks a method from the source path and the entry name.
+pub type MethodCallback = Arc<dyn Fn(&Path, &str) -> ZipMethod + Send + Sync>;
+
+impl Default for MethodSelector {
+    fn default() -> Self {
+        MethodSelector::Fixed(ZipMethod::Deflated)
+    }
+}
+
+impl fmt::Debug for MethodSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MethodSelector::Fixed(method) => f.debug_tuple("Fixed").field(method).finish(),
+            MethodSelector::ByExtension { methods, default } => f
+                .debug_struct("ByExtension")
+                .field("methods", methods)
+                .field("default", default)
+                .finish(),
+            MethodSelector::Callback(_) => write!(f, "Callback(..)"),
+        }
+    }
+}
+
+impl MethodSelector {
+    pub fn by_extension(default: ZipMethod) -> Self {
+        MethodSelector::ByExtension {
+            methods: HashMap::new(),
+            default,
+        }
+    }
+
+    /// Uses `default` except for well-known compressed formats (images,
+    /// audio, video, archives, office documents), which are stored.
+    pub fn precompressed(default: ZipMethod) -> Self {
+        PRECOMPRESSED_EXTENSIONS.iter().fold(
+            MethodSelector::by_extension(default),
+            |selector, extension| selector.extension(extension, ZipMethod::Stored),
+        )
+    }
+
+    /// Adds an extension mapping; turns any other selector into
+    /// `ByExtension` with its previous fixed method (or deflate) as default.
+    pub fn extension(self, extension: &str, method: ZipMethod) -> Self {
+        let (mut methods, default) = match self {
+            MethodSelector::ByExtension { methods, default } => (methods, default),
+            MethodSelector::Fixed(default) => (HashMap::new(), default),
+            MethodSelector::Callback(_) => (HashMap::new(), ZipMethod::Deflated),
+        };
+        methods.insert(extension.trim_start_matches('.').to_lowercase(), method);
+        MethodSelector::ByExtension { methods, default }
+    }
+
+    pub fn callback<F: Fn(&Path, &str) -> ZipMethod + Send + Sync + 'static>(callback: F) -> Self {
+        MethodSelector::Callback(Arc::new(callback))
+    }
+
+    pub fn select(&self, path: &Path, name: &str) -> ZipMethod {
+        match self {
+            MethodSelector::Fixed(method) => *method,
+            MethodSelector::ByExtension { methods, default } => path
+                .extension()
+                .and_then(|extension| methods.get(&extension.to_string_lossy().to_lowercase()))
+                .copied()
+                .unwrap_or(*default),
+            MethodSelector::Callback(callback) => callback(path, name),
+        }
+    }
+}
+
+/// Looks up the password of an entry by name; `None` means no password.
+pub type PasswordCallback = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+/// Passwords of zip entries: used to encrypt entries when writing and to
+/// decrypt them when extracting.
+#[derive(Clone, Default)]
+pub enum ZipPassword {
+    #[default]
+    None,
+    /// The same password for every entry.
+    Fixed(String),
+    /// Asked for each entry; entries without a password are written unencrypted.
+    Callback(PasswordCallback),
+}
+
+impl fmt::Debug for ZipPassword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ZipPassword::None => write!(f, "None"),
+            ZipPassword::Fixed(_) => write!(f, "Fixed(..)"),
+            ZipPassword::Callback(_) => write!(f, "Callback(..)"),
+        }
+    }
+}
+
+impl ZipPassword {
+    pub fn callback<F: Fn(&str) -> Option<String> + Send + Sync + 'static>(callback: F) -> Self {
+        ZipPassword::Callback(Arc::new(callback))
+    }
+
+    /// Uses `password` for entries equal to or matching one of the glob
+    /// `patterns` and no password for the others.
+    pub fn matching(password: &str, patterns: &[&str]) -> Result<Self, ZippingErr> {
+        let globs = entry_globs(patterns)?;
+        let patterns: Vec<String> = patterns.iter().map(|pattern| pattern.to_string()).collect();
+        let password = password.to_string();
+        Ok(ZipPassword::callback(move |name| {
+            (patterns.iter().any(|pattern| pattern == name) || globs.is_match(name))
+                .then(|| password.clone())
+        }))
+    }
+
+    pub fn for_entry(&self, name: &str) -> Option<String> {
+        match self {
+            ZipPassword::None => None,
+            ZipPassword::Fixed(password) => Some(password.clone()),
+            ZipPassword::Callback(callback) => callback(name),
+        }
+    }
+}
+
+/// Encryption used for entries that have a password.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ZipCipher {
+    /// WinZip AES-256, written as AE-2 so no checksum of the plaintext is stored.
+    #[default]
+    Aes256,
+    /// Legacy PKWARE encryption. It is easily broken; use it only for
+    /// readers that cannot handle AES.
+    ZipCrypto,
+}
+
+/// An extra field of a zip entry: a header id and its data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ZipExtraField {
+    pub id: u16,
+    pub data: Vec<u8>,
+}
+
+/// Comment and extra fields of a zip entry. When writing, `extra_fields`
+/// are added next to the fields this crate writes itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ZipEntryInfo {
+    pub comment: String,
+    pub extra_fields: Vec<ZipExtraField>,
+}
+
+/// Looks up the comment and extra fields of an entry from the source path
+/// and the entry name; `None` leaves the entry without any.
+pub type EntryInfoCallback = Arc<dyn Fn(&Path, &str) -> Option<ZipEntryInfo> + Send + Sync>;
+
+/// Comments and extra fields given to new zip entries.
+#[derive(Clone, Default)]
+pub enum ZipEntryMetadata {
+    #[default]
+    None,
+    /// Looked up by entry name.
+    Named(HashMap<String, ZipEntryInfo>),
+    /// Called with the source path and the entry name.
+    Callback(EntryInfoCallback),
+}
+
+impl fmt::Debug for ZipEntryMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ZipEntryMetadata::None => write!(f, "None"),
+            ZipEntryMetadata::Named(entries) => f.debug_tuple("Named").field(entries).finish(),
+            ZipEntryMetadata::Callback(_) => write!(f, "Callback(..)"),
+        }
+    }
+}
+
+impl ZipEntryMetadata {
+    pub fn callback<F: Fn(&Path, &str) -> Option<ZipEntryInfo> + Send + Sync + 'static>(
+        callback: F,
+    ) -> Self {
+        ZipEntryMetadata::Callback(Arc::new(callback))
+    }
+
+    pub fn for_entry(&self, path: &Path, name: &str) -> Option<ZipEntryInfo> {
+        match self {
+            ZipEntryMetadata::None => None,
+            ZipEntryMetadata::Named(entries) => entries.get(name).cloned(),
+            ZipEntryMetadata::Callback(callback) => callback(path, name),
+        }
+    }
+}
+
+/// Settings for `create_zip_archive_with_options`.
+#[derive(Debug, Clone, Default)]
+pub struct ZipOptions {
+    /// Sorts entries and normalises their metadata for reproducible output.
+    pub reproducible: Option<Reproducible>,
+    /// Include/exclude patterns and walk limits applied to directory inputs.
+    pub filter: FileFilter,
+    /// Rewrites entry names, e.g. to put everything under `myapp-1.2.3/`.
+    pub transform: PathTransform,
+    /// Compression method of each entry.
+    pub method: MethodSelector,
+    /// Compression level passed to the selected method (ignored for stored entries).
+    pub level: Option<i64>,
+    /// Unix permissions given to every entry instead of those of the source files.
+    pub permissions: Option<u32>,
+    /// Automatic mode: compress a sample of each file first and store the
+    /// entry instead when the fraction saved is below this value (e.g. `0.05`).
+    pub store_below: Option<f64>,
+    /// Encrypts the entries that have a password (LZMA entries cannot be encrypted).
+    pub password: ZipPassword,
+    /// Cipher of the encrypted entries.
+    pub cipher: ZipCipher,
+    /// Archive comment, at most 65535 bytes. Appending replaces the existing
+    /// comment only when this is set.
+    pub comment: Option<String>,
+    /// Entry comments (at most 65535 bytes) and extra fields (at most 65535
+    /// bytes per entry, including the fields written by this crate).
+    pub entry_metadata: ZipEntryMetadata,
+    /// Writes a split archive of volumes of at most this many bytes (64 KiB
+    /// or more) when the archive is larger: `<output>.z01`, `<output>.z02`,
+    /// ..., with the last volume at the output path. Only used by
+    /// `create_zip_archive_with_options` and `convert_archive`; appending
+    /// and replacing entries fail when it is set.
+    pub volume_size: Option<u64>,
+    /// Checksums every file as it is written and stores them in a manifest
+    /// entry or a JSON file next to the archive. Used by
+    /// `create_zip_archive_with_options` and, for the manifest entry only,
+    /// `ZipStreamWriter`; appending and replacing entries fail when it is set.
+    pub manifest: Option<ManifestOptions>,
+}
+
 pub fn create_zip_archive<P: AsRef<Path>>(files: &[P], output_path: P) -> Result<(), ZippingErr> {
+    create_zip_archive_with_options(files, output_path, &ZipOptions::default())
+}
+
+pub fn create_zip_archive_with_options<P: AsRef<Path>>(
+    files: &[P],
+    output_path: P,
+    options: &ZipOptions,
+) -> Result<(), ZippingErr> {
+    if let Some(volume_size) = options.volume_size.filter(|&size| size < MIN_VOLUME_SIZE) {
+        return Err(ZippingErr::InvalidVolumeSize(volume_size));
+    }
     let (temp_dir, temp_file_path) =
         create_temp_file("zip").map_err(ZippingErr::TempFileCreationFailed)?;
-    let temp_file = File::create(&temp_file_path).map_err(ZippingErr::FileOpenFailed)?;
+    let temp_file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&temp_file_path)
+        .map_err(ZippingErr::FileOpenFailed)?;
 
     let mut zip = ZipWriter::new(temp_file);
+    let mut manifest = options.manifest.as_ref().map(ManifestBuilder::new);
+    let entries = zip_entries(files, options)?;
+    // A file at the archive root may not take the name of the manifest entry.
+    if let Some((manifest_name, _)) = manifest.as_ref().and_then(ManifestBuilder::entry) {
+        if entries.iter().any(|(_, name)| name == manifest_name) {
+            return Err(ZippingErr::DuplicateEntry(manifest_name.to_string()));
+        }
+    }
+    for (file_path, file_name) in entries {
+        write_file_entry(&mut zip, &file_path, file_name, options, manifest.as_mut())?;
+    }
+    if let Some(manifest) = &manifest {
+        write_manifest(&mut zip, manifest, options)?;
+    }
+    if let Some(comment) = &options.comment {
+        set_archive_comment(&mut zip, comment)?;
+    }
+
+    let mut archive = zip.finish().map_err(ZippingErr::FinishFailed)?;
+    if !matches!(options.password, ZipPassword::None) {
+        mark_ae2(&mut archive, &HashSet::new())?;
+    }
+    drop(archive);
+    finish_zip_output(temp_dir, &temp_file_path, output_path.as_ref(), options)?;
+    match manifest {
+        Some(manifest) => manifest
+            .write_sidecar(output_path.as_ref())
+            .map_err(ZippingErr::ManifestWriteFailed),
+        None => Ok(()),
+    }
+}
 
-    let options = FileOptions::default()
-        .compression_method(zip::CompressionMethod::Deflated)
-        .unix_permissions(0o755);
+/// Writes a zip archive to any `Write`, such as an HTTP response body or a
+/// pipe, without seeking. Each entry is followed by a data descriptor with
+/// its CRC and sizes (a ZIP64 descriptor for entries of 4 GiB or more), and
+/// `finish` writes the central directory, after the manifest entry when
+/// `options.manifest` asks for one.
+pub struct ZipStreamWriter<W: Write> {
+    zip: ZipWriter<StreamWriter<W>>,
+    options: ZipOptions,
+    names: HashSet<String>,
+    manifest: Option<ManifestBuilder>,
+}
 
-    for file_path in files {
-        let file_name = file_path
-            .as_ref()
-            .file_name()
-            .ok_or(ZippingErr::InvalidFileName)?;
-        zip.start_file(file_name.to_string_lossy(), options)
-            .map_err(|e| ZippingErr::from(e));
+impl<W: Write> ZipStreamWriter<W> {
+    pub fn new(inner: W, options: &ZipOptions) -> Self {
+        ZipStreamWriter {
+            zip: ZipWriter::new_stream(inner).set_auto_large_file(),
+            options: options.clone(),
+            names: HashSet::new(),
+            manifest: options.manifest.as_ref().map(ManifestBuilder::new),
+        }
+    }
 
-        let mut file = File::open(file_path).map_err(ZippingErr::FileOpenFailed)?;
-        let mut buffer = Vec::new();
-        io::copy(&mut file, &mut buffer).map_err(ZippingErr::WriteFailed)?;
-        zip.write_all(&buffer).map_err(ZippingErr::WriteFailed)?;
+    /// Adds `files`, selected and named as by `create_zip_archive_with_options`.
+    pub fn add_files<P: AsRef<Path>>(&mut self, files: &[P]) -> Result<(), ZippingErr> {
+        for (file_path, file_name) in zip_entries(files, &self.options)? {
+            self.claim(&file_name)?;
+            let manifest = self.manifest.as_mut();
+            write_file_entry(
+                &mut self.zip,
+                &file_path,
+                file_name,
+                &self.options,
+                manifest,
+            )?;
+        }
+        Ok(())
     }
 
-    zip.finish().map_err(ZippingErr::FinishFailed)?;
-    make_permanent(temp_dir, &temp_file_path, output_path.as_ref())
-        .map_err(ZippingErr::TempFileCreationFailed)?;
+    /// Adds an entry named `name` holding the data of `reader`, stamped with
+    /// the current time and mode 0644. Whether the entry needs ZIP64 fields
+    /// must be known before its data, so an unknown `size` (`None`) writes
+    /// them in any case.
+    pub fn add_reader<R: Read>(
+        &mut self,
+        name: &str,
+        reader: &mut R,
+        size: Option<u64>,
+    ) -> Result<(), ZippingErr> {
+        self.claim(name)?;
+        let method = self.options.method.select(Path::new(name), name);
+        check_lzma_size(method, size, name)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let entry = NewEntry {
+            name: name.to_string(),
+            stamp: EntryStamp::new(now, 0o644, &self.options),
+            method,
+            large_file: size.is_none_or(|size| size >= ZIP64_THRESHOLD),
+            info: entry_info(Path::new(name), name, &self.options),
+        };
+        match &mut self.manifest {
+            Some(manifest) => {
+                let mut hasher = manifest.hasher();
+                let mut reader = HashingReader::new(reader, &mut hasher);
+                write_entry(&mut self.zip, &mut reader, entry, &self.options)?;
+                manifest.add(name.to_string(), hasher);
+                Ok(())
+            }
+            None => write_entry(&mut self.zip, reader, entry, &self.options),
+        }
+    }
+
+    /// Writes the central directory and returns the inner writer.
+    pub fn finish(mut self) -> Result<W, ZippingErr> {
+        if let Some(manifest) = self.manifest.take() {
+            if let Some((name, _)) = manifest.entry() {
+                self.claim(name)?;
+            }
+            write_manifest(&mut self.zip, &manifest, &self.options)?;
+        }
+        if let Some(comment) = &self.options.comment {
+            set_archive_comment(&mut self.zip, comment)?;
+        }
+        let output = self.zip.finish().map_err(ZippingErr::FinishFailed)?;
+        Ok(output.into_inner())
+    }
+
+    fn claim(&mut self, name: &str) -> Result<(), ZippingErr> {
+        match self.names.insert(name.to_string()) {
+            true => Ok(()),
+            false => Err(ZippingErr::DuplicateEntry(name.to_string())),
+        }
+    }
+}
+
+/// Streams a zip archive of `files` into `output`, see `ZipStreamWriter`.
+pub fn write_zip_archive<P: AsRef<Path>, W: Write>(
+    files: &[P],
+    output: W,
+    options: &ZipOptions,
+) -> Result<W, ZippingErr> {
+    let mut writer = ZipStreamWriter::new(output, options);
+    writer.add_files(files)?;
+    writer.finish()
+}
+
+/// A difference between the local headers read by `ZipStreamReader` and the
+/// central directory at the end of the archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ZipInconsistency {
+    /// The stream ended without a central directory.
+    MissingCentralDirectory,
+    /// A central directory record points at no local header of the stream.
+    OnlyInCentralDirectory(String),
+    /// An entry of the stream is not listed in the central directory, as
+    /// with entries deleted by rewriting only the central directory.
+    OnlyInLocalHeaders(String),
+    /// The central directory and the local header (or data descriptor) of an
+    /// entry disagree about `field`: its name, method, CRC or a size.
+    FieldMismatch { name: String, field: &'static str },
+    /// The end of central directory record disagrees with the records it
+    /// closes about `field`: the entry count, or the directory offset or size.
+    EndRecordMismatch(&'static str),
+}
+
+/// Reads a zip archive front to back from any `Read`, such as stdin or a
+/// socket, without the central directory.
+///
+/// Entries are returned by `next_entry` in the order of their local headers;
+/// the end of data followed by a data descriptor is found by scanning for
+/// the descriptor signature with a matching compressed size. An entry that
+/// is not read to the end is skipped by the next call. `finish` then reads
+/// the central directory and reports where it disagrees with the stream.
+pub struct ZipStreamReader<R: Read> {
+    source: StreamSource<R>,
+    name_encoding: ZipNameEncoding,
+    at_central_directory: bool,
+}
+
+impl<R: Read> ZipStreamReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_encoding(inner, ZipNameEncoding::default())
+    }
+
+    /// Like `new`, decoding legacy entry names with `name_encoding`.
+    pub fn with_encoding(inner: R, name_encoding: ZipNameEncoding) -> Self {
+        ZipStreamReader {
+            source: StreamSource {
+                inner,
+                buffer: Vec::new(),
+                start: 0,
+                eof: false,
+                offset: 0,
+                data: EntryData::Idle,
+                entries: Vec::new(),
+            },
+            name_encoding,
+            at_central_directory: false,
+        }
+    }
+
+    /// The next entry, or `None` once the central directory (or the end of
+    /// the input) is reached.
+    pub fn next_entry(&mut self) -> Result<Option<ZipStreamEntry<'_, R>>, ZippingErr> {
+        if self.at_central_directory {
+            return Ok(None);
+        }
+        let source = &mut self.source;
+        source.finish_entry().map_err(|e| {
+            let name = source.entries.last().map(|entry| entry.name.clone());
+            ZippingErr::CorruptEntry(name.unwrap_or_default(), e)
+        })?;
+        loop {
+            if source.fill(4).map_err(ZippingErr::StreamReadFailed)? < 4 {
+                return Ok(None);
+            }
+            match source.le32(0) {
+                // Marker at the start of split archives.
+                DATA_DESCRIPTOR_SIG if source.offset == 0 => source.consume(4),
+                LOCAL_HEADER_SIG => break,
+                CENTRAL_HEADER_SIG | END_OF_CENTRAL_SIG | ZIP64_END_OF_CENTRAL_SIG => {
+                    self.at_central_directory = true;
+                    return Ok(None);
+                }
+                signature => {
+                    return Err(ZippingErr::MalformedStream(format!(
+                        "unexpected signature {:#010x} at offset {}",
+                        signature, source.offset
+                    )))
+                }
+            }
+        }
+
+        let offset = source.offset;
+        let header = source.take(30).map_err(ZippingErr::StreamReadFailed)?;
+        let le16 = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]);
+        let le32 = |at: usize| {
+            u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+        };
+        let (flags, method) = (le16(6), le16(8));
+        let name_raw = source
+            .take(usize::from(le16(26)))
+            .map_err(ZippingErr::StreamReadFailed)?;
+        let extra = source
+            .take(usize::from(le16(28)))
+            .map_err(ZippingErr::StreamReadFailed)?;
+        let name = self.name_encoding.decode(&name_raw, flags, &extra);
+
+        let mut size = u64::from(le32(22));
+        let mut compressed_size = u64::from(le32(18));
+        let zip64 = extra_field(&extra, 0x0001);
+        if let Some((at, len)) = zip64 {
+            let mut values = extra[at..at + len]
+                .chunks_exact(8)
+                .map(|value| u64::from_le_bytes(value.try_into().unwrap_or_default()));
+            if size == ZIP64_THRESHOLD {
+                size = values.next().unwrap_or(size);
+            }
+            if compressed_size == ZIP64_THRESHOLD {
+                compressed_size = values.next().unwrap_or(compressed_size);
+            }
+        }
+        source.data = match flags & 0x0008 {
+            0 => EntryData::Sized(compressed_size),
+            _ => EntryData::Described {
+                consumed: 0,
+                zip64: zip64.is_some(),
+            },
+        };
+        source.entries.push(StreamedEntry {
+            name: name.clone(),
+            name_raw,
+            offset,
+            method,
+            crc: le32(14),
+            compressed_size,
+            size,
+        });
+
+        let modified = extra_field(&extra, EXTENDED_TIMESTAMP_ID)
+            .filter(|&(at, len)| len >= 5 && extra[at] & 0x01 != 0)
+            .map(|(at, _)| {
+                u64::from(u32::from_le_bytes([
+                    extra[at + 1],
+                    extra[at + 2],
+                    extra[at + 3],
+                    extra[at + 4],
+                ]))
+            })
+            .or_else(|| {
+                DateTime::try_from_msdos(le16(12), le16(10))
+                    .ok()
+                    .map(dos_secs)
+            });
+        let encrypted = flags & 0x0001 != 0;
+        let lzma_size = match flags & 0x000A {
+            0 => size,
+            _ => u64::MAX,
+        };
+        let decoder = EntryDecoder::new(StreamData(source), method, encrypted, lzma_size)
+            .map_err(|e| ZippingErr::CorruptEntry(name.clone(), e))?;
+        Ok(Some(ZipStreamEntry {
+            name,
+            encrypted,
+            modified,
+            extra_fields: extra_fields(&extra),
+            decoder,
+            crc: Crc::new(),
+            size: 0,
+            verified: false,
+        }))
+    }
+
+    /// Skips the remaining entries, reads the central directory and returns
+    /// its disagreements with the local headers of the stream. Entries that
+    /// were read to the end have already been checked against their CRC.
+    pub fn finish(mut self) -> Result<Vec<ZipInconsistency>, ZippingErr> {
+        while self.next_entry()?.is_some() {}
+        let source = &mut self.source;
+        let mut inconsistencies = Vec::new();
+        if !self.at_central_directory {
+            inconsistencies.push(ZipInconsistency::MissingCentralDirectory);
+            return Ok(inconsistencies);
+        }
+
+        let read = ZippingErr::StreamReadFailed;
+        let central_offset = source.offset;
+        let mut listed = HashSet::new();
+        let mut records = 0u64;
+        while source.fill(4).map_err(read)? >= 4 && source.le32(0) == CENTRAL_HEADER_SIG {
+            let header = source.take(46).map_err(read)?;
+            let le16 = |at: usize| usize::from(u16::from_le_bytes([header[at], header[at + 1]]));
+            let le32 = |at: usize| {
+                u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+            };
+            let name_raw = source.take(le16(28)).map_err(read)?;
+            let extra = source.take(le16(30)).map_err(read)?;
+            source.take(le16(32)).map_err(read)?;
+            records += 1;
+
+            // ZIP64 values are stored in this order, each only when its
+            // header field holds the 0xFFFFFFFF marker.
+            let mut values = extra_field(&extra, 0x0001)
+                .map(|(at, len)| extra[at..at + len].to_vec())
+                .unwrap_or_default()
+                .chunks_exact(8)
+                .map(|value| u64::from_le_bytes(value.try_into().unwrap_or_default()))
+                .collect::<Vec<_>>()
+                .into_iter();
+            let mut field = |at: usize| match u64::from(le32(at)) {
+                ZIP64_THRESHOLD => values.next().unwrap_or(ZIP64_THRESHOLD),
+                value => value,
+            };
+            let (size, compressed_size, offset) = (field(24), field(20), field(42));
+
+            let name = self.name_encoding.decode(&name_raw, le16(8) as u16, &extra);
+            let Some(streamed) = source.entries.iter().find(|entry| entry.offset == offset) else {
+                inconsistencies.push(ZipInconsistency::OnlyInCentralDirectory(name));
+                continue;
+            };
+            listed.insert(offset);
+            let fields = [
+                ("name", streamed.name_raw != name_raw),
+                (
+                    "compression method",
+                    usize::from(streamed.method) != le16(10),
+                ),
+                ("CRC", streamed.crc != le32(16)),
+                (
+                    "compressed size",
+                    streamed.compressed_size != compressed_size,
+                ),
+                ("size", streamed.size != size),
+            ];
+            for (field, _) in fields.into_iter().filter(|(_, differs)| *differs) {
+                inconsistencies.push(ZipInconsistency::FieldMismatch {
+                    name: streamed.name.clone(),
+                    field,
+                });
+            }
+        }
+        let central_size = source.offset - central_offset;
+        for entry in source
+            .entries
+            .iter()
+            .filter(|entry| !listed.contains(&entry.offset))
+        {
+            inconsistencies.push(ZipInconsistency::OnlyInLocalHeaders(entry.name.clone()));
+        }
+
+        // The ZIP64 end record, if any, holds the values its classic
+        // counterpart marks as 0xFFFF or 0xFFFFFFFF.
+        let mut zip64_end = None;
+        if source.fill(4).map_err(read)? >= 4 && source.le32(0) == ZIP64_END_OF_CENTRAL_SIG {
+            let record = source.take(56).map_err(read)?;
+            let le64 =
+                |at: usize| u64::from_le_bytes(record[at..at + 8].try_into().unwrap_or_default());
+            let extensible = le64(4).saturating_sub(44);
+            source
+                .take(usize::try_from(extensible).unwrap_or(usize::MAX))
+                .map_err(read)?;
+            zip64_end = Some((le64(32), le64(40), le64(48)));
+            if source.fill(4).map_err(read)? >= 4 && source.le32(0) == ZIP64_LOCATOR_SIG {
+                source.take(20).map_err(read)?;
+            }
+        }
+        if source.fill(4).map_err(read)? < 4 || source.le32(0) != END_OF_CENTRAL_SIG {
+            return Err(ZippingErr::MalformedStream(
+                "missing end of central directory record".to_string(),
+            ));
+        }
+        let record = source.take(22).map_err(read)?;
+        let le16 = |at: usize| u64::from(u16::from_le_bytes([record[at], record[at + 1]]));
+        let le32 = |at: usize| {
+            u64::from(u32::from_le_bytes([
+                record[at],
+                record[at + 1],
+                record[at + 2],
+                record[at + 3],
+            ]))
+        };
+        let (mut count, mut size, mut offset) = (le16(10), le32(12), le32(16));
+        if let Some((zip64_count, zip64_size, zip64_offset)) = zip64_end {
+            (count, size, offset) = (zip64_count, zip64_size, zip64_offset);
+        }
+        let end_fields = [
+            ("entry count", count != records),
+            ("central directory size", size != central_size),
+            ("central directory offset", offset != central_offset),
+        ];
+        for (field, _) in end_fields.into_iter().filter(|(_, differs)| *differs) {
+            inconsistencies.push(ZipInconsistency::EndRecordMismatch(field));
+        }
+        Ok(inconsistencies)
+    }
+}
+
+/// An entry of a `ZipStreamReader`; reading it yields the decompressed data.
+/// Reaching the end checks the data against the CRC and size of the local
+/// header or data descriptor, failing with `InvalidData` on a mismatch.
+/// Encrypted entries and compression methods other than those of `ZipMethod`
+/// cannot be read, only skipped.
+pub struct ZipStreamEntry<'a, R: Read> {
+    name: String,
+    encrypted: bool,
+    modified: Option<u64>,
+    extra_fields: Vec<ZipExtraField>,
+    decoder: EntryDecoder<'a, R>,
+    crc: Crc,
+    size: u64,
+    verified: bool,
+}
+
+impl<R: Read> ZipStreamEntry<'_, R> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    pub fn encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// Modification time in seconds since the Unix epoch, from the extended
+    /// timestamp field, else the DOS timestamp.
+    pub fn modified(&self) -> Option<u64> {
+        self.modified
+    }
+
+    /// Extra fields of the local header. The unix mode and comment are only
+    /// stored in the central directory.
+    pub fn extra_fields(&self) -> &[ZipExtraField] {
+        &self.extra_fields
+    }
+}
+
+impl<R: Read> Read for ZipStreamEntry<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.verified || buf.is_empty() {
+            return Ok(0);
+        }
+        let read = self.decoder.read(buf)?;
+        if read > 0 {
+            self.crc.update(&buf[..read]);
+            self.size += read as u64;
+            return Ok(read);
+        }
+        let source = self.decoder.source();
+        source.finish_entry()?;
+        let Some(entry) = source.entries.last() else {
+            return Ok(0);
+        };
+        if entry.crc != self.crc.sum() || entry.size != self.size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "entry data does not match its CRC or size",
+            ));
+        }
+        self.verified = true;
+        Ok(0)
+    }
+}
+
+/// Signatures of the zip records.
+const LOCAL_HEADER_SIG: u32 = 0x0403_4b50;
+const CENTRAL_HEADER_SIG: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_SIG: u32 = 0x0605_4b50;
+const ZIP64_END_OF_CENTRAL_SIG: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIG: u32 = 0x0706_4b50;
+const DATA_DESCRIPTOR_SIG: u32 = 0x0807_4b50;
+
+/// How much `StreamSource` reads from its input at a time.
+const STREAM_CHUNK: usize = 64 * 1024;
+
+/// Local header fields of an entry read by `ZipStreamReader`, with the CRC
+/// and sizes of its data descriptor once that has been read.
+struct StreamedEntry {
+    name: String,
+    name_raw: Vec<u8>,
+    offset: u64,
+    method: u16,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+}
+
+/// Where the data of the current entry ends.
+enum EntryData {
+    /// Between entries.
+    Idle,
+    /// This many compressed bytes are left.
+    Sized(u64),
+    /// The data ends at a data descriptor whose compressed size matches the
+    /// bytes consumed so far; ZIP64 descriptors hold 8-byte sizes.
+    Described { consumed: u64, zip64: bool },
+}
+
+/// CRC and sizes of a data descriptor, and its length in bytes.
+struct Descriptor {
+    crc: u32,
+    size: u64,
+    len: usize,
+}
+
+/// The input of a `ZipStreamReader` with a look-ahead buffer, which lets
+/// the end of data followed by a data descriptor be found before handing
+/// out the data.
+struct StreamSource<R> {
+    inner: R,
+    buffer: Vec<u8>,
+    start: usize,
+    eof: bool,
+    /// Archive offset of `buffer[start]`.
+    offset: u64,
+    data: EntryData,
+    entries: Vec<StreamedEntry>,
+}
+
+impl<R: Read> StreamSource<R> {
+    /// Buffers at least `len` bytes unless the input ends first and returns
+    /// the number of buffered bytes.
+    fn fill(&mut self, len: usize) -> io::Result<usize> {
+        while self.buffer.len() - self.start < len && !self.eof {
+            self.buffer.drain(..self.start);
+            self.start = 0;
+            let filled = self.buffer.len();
+            self.buffer.resize(filled + STREAM_CHUNK.max(len), 0);
+            let read = loop {
+                match self.inner.read(&mut self.buffer[filled..]) {
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    read => break read,
+                }
+            };
+            self.buffer.truncate(filled + *read.as_ref().unwrap_or(&0));
+            self.eof = read? == 0;
+        }
+        Ok(self.buffer.len() - self.start)
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.start += len;
+        self.offset += len as u64;
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<Vec<u8>> {
+        if self.fill(len)? < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let bytes = self.buffer[self.start..self.start + len].to_vec();
+        self.consume(len);
+        Ok(bytes)
+    }
+
+    /// Little-endian `u32` at `at` of the buffered bytes, which must be there.
+    fn le32(&self, at: usize) -> u32 {
+        let at = self.start + at;
+        u32::from_le_bytes([
+            self.buffer[at],
+            self.buffer[at + 1],
+            self.buffer[at + 2],
+            self.buffer[at + 3],
+        ])
+    }
+
+    /// Reads compressed data of the current entry.
+    fn read_data(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = match self.data {
+            EntryData::Idle | EntryData::Sized(0) => return Ok(0),
+            EntryData::Sized(remaining) => match self.fill(1)? {
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                available => available.min(usize::try_from(remaining).unwrap_or(usize::MAX)),
+            },
+            EntryData::Described { consumed, zip64 } => self.described_len(consumed, zip64)?,
+        };
+        let len = available.min(out.len());
+        out[..len].copy_from_slice(&self.buffer[self.start..self.start + len]);
+        self.consume(len);
+        match &mut self.data {
+            EntryData::Sized(remaining) => *remaining -= len as u64,
+            EntryData::Described { consumed, .. } => *consumed += len as u64,
+            EntryData::Idle => {}
+        }
+        Ok(len)
+    }
+
+    /// Number of buffered bytes that certainly belong to the data of an
+    /// entry ending at a data descriptor; 0 when the descriptor is next.
+    fn described_len(&mut self, consumed: u64, zip64: bool) -> io::Result<usize> {
+        let signature = DATA_DESCRIPTOR_SIG.to_le_bytes();
+        let mut from = 0;
+        loop {
+            let available = self.fill(from + signature.len())?;
+            let window = &self.buffer[self.start..self.start + available];
+            let found = window
+                .get(from..)
+                .and_then(|rest| rest.windows(signature.len()).position(|w| w == signature));
+            match found {
+                Some(0) if from == 0 => match self.descriptor(consumed, zip64)? {
+                    Some(_) => return Ok(0),
+                    None => from = 1,
+                },
+                Some(found) => return Ok(from + found),
+                None if self.eof => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "data descriptor not found",
+                    ))
+                }
+                // A signature may straddle the end of the buffer.
+                None => return Ok(available - (signature.len() - 1)),
+            }
+        }
+    }
+
+    /// The data descriptor at the front of the buffer, if there is one for
+    /// `consumed` compressed bytes followed by another record or the end of
+    /// the input.
+    fn descriptor(&mut self, consumed: u64, zip64: bool) -> io::Result<Option<Descriptor>> {
+        let available = self.fill(28)?;
+        let window = &self.buffer[self.start..self.start + available];
+        let le32 = |at: usize| {
+            u64::from(u32::from_le_bytes(
+                window[at..at + 4].try_into().unwrap_or_default(),
+            ))
+        };
+        let le64 =
+            |at: usize| u64::from_le_bytes(window[at..at + 8].try_into().unwrap_or_default());
+        let lens = match zip64 {
+            true => [24, 16],
+            false => [16, 24],
+        };
+        for len in lens.into_iter().filter(|&len| len <= available) {
+            let (compressed_size, size) = match len {
+                16 => (le32(8), le32(12)),
+                _ => (le64(8), le64(16)),
+            };
+            let followed = match window.get(len..len + 4) {
+                Some(_) => [
+                    LOCAL_HEADER_SIG,
+                    CENTRAL_HEADER_SIG,
+                    END_OF_CENTRAL_SIG,
+                    ZIP64_END_OF_CENTRAL_SIG,
+                ]
+                .contains(&(le32(len) as u32)),
+                None => available == len,
+            };
+            if compressed_size == consumed && followed {
+                return Ok(Some(Descriptor {
+                    crc: le32(4) as u32,
+                    size,
+                    len,
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Skips the rest of the current entry and reads its data descriptor.
+    fn finish_entry(&mut self) -> io::Result<()> {
+        let mut skipped = [0u8; 8 * 1024];
+        while self.read_data(&mut skipped)? > 0 {}
+        if let EntryData::Described { consumed, zip64 } = self.data {
+            let descriptor = self.descriptor(consumed, zip64)?.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "data descriptor not found")
+            })?;
+            self.consume(descriptor.len);
+            if let Some(entry) = self.entries.last_mut() {
+                entry.crc = descriptor.crc;
+                entry.compressed_size = consumed;
+                entry.size = descriptor.size;
+            }
+        }
+        self.data = EntryData::Idle;
+        Ok(())
+    }
+}
+
+/// Compressed data of the current entry of a `StreamSource`.
+struct StreamData<'a, R>(&'a mut StreamSource<R>);
+
+impl<R: Read> Read for StreamData<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read_data(buf)
+    }
+}
+
+/// Decompressor of the current entry of a `ZipStreamReader`.
+enum EntryDecoder<'a, R: Read> {
+    Stored(StreamData<'a, R>),
+    Deflated(DeflateDecoder<StreamData<'a, R>>),
+    Bzip2(BzDecoder<StreamData<'a, R>>),
+    Zstd(ZstdDecoder<'static, io::BufReader<StreamData<'a, R>>>),
+    Lzma(XzDecoder<io::Chain<io::Cursor<Vec<u8>>, StreamData<'a, R>>>),
+    Xz(XzDecoder<StreamData<'a, R>>),
+    Unreadable(StreamData<'a, R>, &'static str),
+}
+
+impl<'a, R: Read> EntryDecoder<'a, R> {
+    /// `lzma_size` is the uncompressed size for LZMA data without an end
+    /// marker, `u64::MAX` otherwise.
+    fn new(
+        mut data: StreamData<'a, R>,
+        method: u16,
+        encrypted: bool,
+        lzma_size: u64,
+    ) -> io::Result<Self> {
+        Ok(match (encrypted, method) {
+            (true, _) => {
+                EntryDecoder::Unreadable(data, "encrypted entries cannot be read from a stream")
+            }
+            (_, 0) => EntryDecoder::Stored(data),
+            (_, 8) => EntryDecoder::Deflated(DeflateDecoder::new(data)),
+            (_, 12) => EntryDecoder::Bzip2(BzDecoder::new(data)),
+            (_, 93) => EntryDecoder::Zstd(ZstdDecoder::new(data)?),
+            (_, 14) => {
+                // SDK version and properties size, then the properties; see
+                // `lzma_entry`. liblzma reads them as an LZMA-alone header.
+                let mut prefix = [0u8; 4];
+                data.read_exact(&mut prefix)?;
+                let mut header = vec![0u8; usize::from(u16::from_le_bytes([prefix[2], prefix[3]]))];
+                data.read_exact(&mut header)?;
+                header.extend_from_slice(&lzma_size.to_le_bytes());
+                let stream = Stream::new_lzma_decoder(u64::MAX).map_err(io::Error::other)?;
+                EntryDecoder::Lzma(XzDecoder::new_stream(
+                    io::Cursor::new(header).chain(data),
+                    stream,
+                ))
+            }
+            (_, 95) => EntryDecoder::Xz(XzDecoder::new(data)),
+            _ => EntryDecoder::Unreadable(data, "unsupported compression method"),
+        })
+    }
+
+    fn source(&mut self) -> &mut StreamSource<R> {
+        match self {
+            EntryDecoder::Stored(data) | EntryDecoder::Unreadable(data, _) => data.0,
+            EntryDecoder::Deflated(decoder) => decoder.get_mut().0,
+            EntryDecoder::Bzip2(decoder) => decoder.get_mut().0,
+            EntryDecoder::Zstd(decoder) => decoder.get_mut().get_mut().0,
+            EntryDecoder::Lzma(decoder) => {
+                let (_, data) = decoder.get_mut().get_mut();
+                data.0
+            }
+            EntryDecoder::Xz(decoder) => decoder.get_mut().0,
+        }
+    }
+}
+
+impl<R: Read> Read for EntryDecoder<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            EntryDecoder::Stored(data) => data.read(buf),
+            EntryDecoder::Deflated(decoder) => decoder.read(buf),
+            EntryDecoder::Bzip2(decoder) => decoder.read(buf),
+            EntryDecoder::Zstd(decoder) => decoder.read(buf),
+            EntryDecoder::Lzma(decoder) => decoder.read(buf),
+            EntryDecoder::Xz(decoder) => decoder.read(buf),
+            EntryDecoder::Unreadable(_, reason) => {
+                Err(io::Error::new(io::ErrorKind::Unsupported, *reason))
+            }
+        }
+    }
+}
+
+/// Adds `files` to an existing archive, selected and named the same way as
+/// by `create_zip_archive_with_options`. Existing entries are copied without
+/// recompression; a new entry whose name is already taken is an error.
+pub fn append_to_zip_archive<P: AsRef<Path>>(
+    archive_path: P,
+    files: &[P],
+    options: &ZipOptions,
+) -> Result<(), ZippingErr> {
+    check_edit_options(options)?;
+    let additions = zip_entries(files, options)?;
+    rewrite_zip_archive(
+        archive_path.as_ref(),
+        |_| EntryEdit::Keep,
+        |names, zip| {
+            let mut seen = names.clone();
+            for (_, file_name) in &additions {
+                if !seen.insert(file_name.clone()) {
+                    return Err(ZippingErr::DuplicateEntry(file_name.clone()));
+                }
+            }
+            for (file_path, file_name) in additions {
+                write_file_entry(zip, &file_path, file_name, options, None)?;
+            }
+            match &options.comment {
+                Some(comment) => set_archive_comment(zip, comment),
+                None => Ok(()),
+            }
+        },
+    )
+}
+
+/// Replaces the data of entry `name` with the contents of `file`, keeping
+/// its position in the archive.
+pub fn replace_zip_entry<P: AsRef<Path>>(
+    archive_path: P,
+    name: &str,
+    file: P,
+    options: &ZipOptions,
+) -> Result<(), ZippingErr> {
+    check_edit_options(options)?;
+    let mut found = false;
+    let edit = |entry_name: &str| {
+        if entry_name != name {
+            return EntryEdit::Keep;
+        }
+        found = true;
+        EntryEdit::Replace(file.as_ref(), options)
+    };
+    rewrite_zip_archive(archive_path.as_ref(), edit, |_, _| Ok(()))?;
+    match found {
+        true => Ok(()),
+        false => Err(ZippingErr::EntryNotFound(name.to_string())),
+    }
+}
+
+/// Fails for the settings that only apply to a newly written archive.
+fn check_edit_options(options: &ZipOptions) -> Result<(), ZippingErr> {
+    if options.volume_size.is_some() {
+        return Err(ZippingErr::UnsupportedEditOption("volume_size"));
+    }
+    if options.manifest.is_some() {
+        return Err(ZippingErr::UnsupportedEditOption("manifest"));
+    }
     Ok(())
 }
+
+/// Removes every entry whose name equals or matches one of the glob
+/// `patterns` (`*` does not cross `/`, `**` does) and returns the removed names.
+pub fn delete_zip_entries<P: AsRef<Path>>(
+    archive_path: P,
+    patterns: &[&str],
+) -> Result<Vec<String>, ZippingErr> {
+    let globs = entry_globs(patterns)?;
+    let mut deleted = Vec::new();
+    let edit = |name: &str| match patterns.contains(&name) || globs.is_match(name) {
+        true => {
+            deleted.push(name.to_string());
+            EntryEdit::Delete
+        }
+        false => EntryEdit::Keep,
+    };
+    rewrite_zip_archive(archive_path.as_ref(), edit, |_, _| Ok(()))?;
+    Ok(deleted)
+}
+
+/// Joins a split archive (`archive.z01`, `archive.z02`, ..., `archive.zip`,
+/// found next to `archive_path`) into a single-file archive at `output_path`.
+/// Reading and extracting functions do this on the fly, in a temporary file.
+pub fn join_split_zip<P: AsRef<Path>>(archive_path: P, output_path: P) -> Result<(), ZippingErr> {
+    let (temp_dir, temp_file_path) =
+        create_temp_file("zip").map_err(ZippingErr::TempFileCreationFailed)?;
+    let mut output =
+        BufWriter::new(File::create(&temp_file_path).map_err(ZippingErr::WriteFailed)?);
+    join_zip_volumes(&zip_volumes(archive_path.as_ref()), &mut output)?;
+    output.flush().map_err(ZippingErr::WriteFailed)?;
+    drop(output);
+    make_permanent(temp_dir, &temp_file_path, output_path.as_ref())
+        .map_err(ZippingErr::TempFileFinalizationFailed)
+}
+
+/// Encoding of entry names stored as legacy bytes: without the UTF-8
+/// (language encoding) flag and without an Info-ZIP Unicode Path extra
+/// field. Names written by this crate always carry the flag when they are
+/// not ASCII.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ZipNameEncoding {
+    /// IBM code page 437, the encoding the zip format specifies.
+    #[default]
+    Cp437,
+    /// Another encoding, e.g. `encoding_rs::SHIFT_JIS` for Japanese Windows zips.
+    Other(&'static Encoding),
+}
+
+impl ZipNameEncoding {
+    /// Looks up an encoding by its WHATWG label, such as `"shift_jis"` or `"gbk"`.
+    pub fn from_label(label: &str) -> Option<Self> {
+        Encoding::for_label(label.as_bytes()).map(ZipNameEncoding::Other)
+    }
+
+    /// Name of `entry`. The `zip` crate reads names with the language
+    /// encoding flag or a Unicode Path field as UTF-8, leaving `name_raw`
+    /// and `name` identical; any other name is a legacy one.
+    fn entry_name<R: Read>(&self, entry: &ZipFile<'_, R>) -> String {
+        let raw = entry.name_raw();
+        match entry.name().as_bytes() == raw {
+            true => entry.name().to_string(),
+            false => self.decode_legacy(raw),
+        }
+    }
+
+    /// Decodes the `raw` name of a header with general purpose `flags` and
+    /// `extra` fields the way `entry_name` does, for headers read without
+    /// the `zip` crate.
+    fn decode(&self, raw: &[u8], flags: u16, extra: &[u8]) -> String {
+        if flags & 0x0800 != 0 {
+            return String::from_utf8_lossy(raw).into_owned();
+        }
+        if let Some(name) = unicode_field(extra, UNICODE_PATH_ID, raw) {
+            return String::from_utf8_lossy(&name).into_owned();
+        }
+        self.decode_legacy(raw)
+    }
+
+    /// Decodes a name stored as legacy bytes with this encoding.
+    fn decode_legacy(&self, raw: &[u8]) -> String {
+        match self {
+            ZipNameEncoding::Cp437 => raw
+                .iter()
+                .map(|&byte| match byte {
+                    0..=0x7F => char::from(byte),
+                    _ => CP437_HIGH[usize::from(byte - 0x80)],
+                })
+                .collect(),
+            ZipNameEncoding::Other(encoding) => {
+                encoding.decode_without_bom_handling(raw).0.into_owned()
+            }
+        }
+    }
+}
+
+/// Characters of the bytes 0x80-0xFF in code page 437.
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', 'É', 'æ', 'Æ',
+    'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', 'á', 'í', 'ó', 'ú', 'ñ', 'Ñ',
+    'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕',
+    '╣', '║', '╗', '╝', '╜', '╛', '┐', '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦',
+    '╠', '═', '╬', '╧', '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐',
+    '▀', 'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩', '≡', '±',
+    '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// Comments and extra fields of a zip archive, see `read_zip_info`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ZipInfo {
+    pub comment: String,
+    /// Entry names with their comment and central directory extra fields,
+    /// in archive order.
+    pub entries: Vec<(String, ZipEntryInfo)>,
+}
+
+/// Reads the archive comment and the comment and extra fields of every
+/// entry. Extra fields written by this crate and the `zip` crate (such as
+/// the extended timestamp or ZIP64 sizes) are included.
+pub fn read_zip_info<P: AsRef<Path>>(archive_path: P) -> Result<ZipInfo, ZippingErr> {
+    read_zip_info_with_encoding(archive_path, ZipNameEncoding::default())
+}
+
+/// Like `read_zip_info`, decoding legacy entry names with `name_encoding`.
+pub fn read_zip_info_with_encoding<P: AsRef<Path>>(
+    archive_path: P,
+    name_encoding: ZipNameEncoding,
+) -> Result<ZipInfo, ZippingErr> {
+    let source = open_zip_file(archive_path.as_ref())?;
+    let mut archive = ZipArchive::new(source)?;
+    let mut info = ZipInfo {
+        comment: String::from_utf8_lossy(archive.comment()).into_owned(),
+        entries: Vec::with_capacity(archive.len()),
+    };
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index)?;
+        let entry_info = ZipEntryInfo {
+            comment: entry.comment().to_string(),
+            extra_fields: entry.extra_data().map(extra_fields).unwrap_or_default(),
+        };
+        info.entries
+            .push((name_encoding.entry_name(&entry), entry_info));
+    }
+    Ok(info)
+}
+
+/// Settings for `extract_zip_archive`.
+#[derive(Debug, Clone, Default)]
+pub struct ZipExtractOptions {
+    /// Passwords of encrypted entries, AES or legacy ZipCrypto.
+    pub password: ZipPassword,
+    /// Rewrites entry paths before they are placed under the destination.
+    /// Entries rewritten to nothing are skipped.
+    pub transform: PathTransform,
+    /// Fallback encoding of entry names without the UTF-8 flag.
+    pub name_encoding: ZipNameEncoding,
+}
+
+/// Extracts a zip archive into `destination`, restoring unix permissions and
+/// modification times. A split archive is read from the `.zNN` volumes next
+/// to `archive_path`.
+///
+/// Entries with absolute paths or `..` components are refused, as are
+/// symbolic links whose target would resolve outside `destination`. A wrong
+/// password fails with `WrongPassword`; data that fails its checksum or
+/// authentication code fails with `CorruptEntry` and is not left behind.
+pub fn extract_zip_archive<P: AsRef<Path>>(
+    archive_path: P,
+    destination: P,
+    options: &ZipExtractOptions,
+) -> Result<(), ZippingErr> {
+    let destination = destination.as_ref();
+    fs::create_dir_all(destination).map_err(ZippingErr::ExtractionFailed)?;
+    let root = destination
+        .canonicalize()
+        .map_err(ZippingErr::ExtractionFailed)?;
+    let source = open_zip_file(archive_path.as_ref())?;
+    let mut archive = ZipArchive::new(source)?;
+    let mut links = Vec::new();
+
+    for index in 0..archive.len() {
+        let (name, encrypted) = {
+            let entry = archive.by_index_raw(index)?;
+            (options.name_encoding.entry_name(&entry), entry.encrypted())
+        };
+        let Some(path) = options
+            .transform
+            .apply(Path::new(&name))
+            .map_err(ZippingErr::PathTransformFailed)?
+        else {
+            continue;
+        };
+        let relative = contained_path(Path::new(""), &path)
+            .filter(|relative| !relative.as_os_str().is_empty())
+            .ok_or_else(|| ZippingErr::UnsafeEntryPath(name.clone()))?;
+
+        let entry = match encrypted {
+            true => {
+                let password = options
+                    .password
+                    .for_entry(&name)
+                    .ok_or_else(|| ZippingErr::PasswordRequired(name.clone()))?;
+                archive.by_index_decrypt(index, password.as_bytes())
+            }
+            false => archive.by_index(index),
+        };
+        let mut entry = entry.map_err(|e| match e {
+            ZipError::InvalidPassword => ZippingErr::WrongPassword(name.clone()),
+            e => e.into(),
+        })?;
+
+        let unsafe_path = || ZippingErr::UnsafeEntryPath(name.clone());
+        let target = root.join(&relative);
+        if entry.is_dir() {
+            if !create_contained_dir(&root, &relative, true)
+                .map_err(ZippingErr::ExtractionFailed)?
+            {
+                return Err(unsafe_path());
+            }
+            continue;
+        }
+        // No link is created inside another link, so links stay where their names say.
+        let is_symlink = entry.is_symlink();
+        if !prepare_parent(&root, &relative, !is_symlink).map_err(ZippingErr::ExtractionFailed)? {
+            return Err(unsafe_path());
+        }
+
+        if is_symlink {
+            let mut link = String::new();
+            entry
+                .read_to_string(&mut link)
+                .map_err(|e| ZippingErr::CorruptEntry(name.clone(), e))?;
+            let base = relative.parent().unwrap_or_else(|| Path::new(""));
+            contained_path(base, Path::new(&link)).ok_or_else(unsafe_path)?;
+            let parent = target.parent().unwrap_or(&root);
+            resolve_on_disk(&root, parent, Path::new(&link), true).ok_or_else(unsafe_path)?;
+            restore_symlink(&link, &target).map_err(ZippingErr::ExtractionFailed)?;
+            links.push((target, name));
+            continue;
+        }
+
+        // Replace an earlier link of the same name instead of writing through it.
+        if fs::symlink_metadata(&target).is_ok_and(|metadata| metadata.is_symlink()) {
+            fs::remove_file(&target).map_err(ZippingErr::ExtractionFailed)?;
+        }
+        let mut output = File::create(&target).map_err(ZippingErr::ExtractionFailed)?;
+        if let Err(e) = copy_entry(&mut entry, &mut output, &name) {
+            drop(output);
+            let _ = fs::remove_file(&target);
+            return Err(e);
+        }
+        restore_metadata(&output, &entry).map_err(ZippingErr::ExtractionFailed)?;
+    }
+
+    // A link can be redirected by a later one, so check them against the
+    // finished tree.
+    for (link, name) in links {
+        if link_escapes(&root, &link) {
+            fs::remove_file(&link).map_err(ZippingErr::ExtractionFailed)?;
+            return Err(ZippingErr::UnsafeEntryPath(name));
+        }
+    }
+    Ok(())
+}
+
+/// Copies the data of entry `name`. Read errors mean the entry failed its
+/// checksum or authentication code (or cannot be decompressed); write
+/// errors are reported as extraction failures.
+fn copy_entry<R: Read, W: Write>(
+    entry: &mut R,
+    output: &mut W,
+    name: &str,
+) -> Result<(), ZippingErr> {
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = match entry.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(ZippingErr::CorruptEntry(name.to_string(), e)),
+        };
+        output
+            .write_all(&buffer[..read])
+            .map_err(ZippingErr::ExtractionFailed)?;
+    }
+}
+
+/// Applies the stored permissions and modification time to an extracted file.
+fn restore_metadata<R: Read>(output: &File, entry: &zip::read::ZipFile<'_, R>) -> io::Result<()> {
+    #[cfg(unix)]
+    if let Some(mode) = entry.unix_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        output.set_permissions(fs::Permissions::from_mode(mode & 0o7777))?;
+    }
+    match entry_mtime(entry) {
+        Some(mtime) => output.set_modified(UNIX_EPOCH + Duration::from_secs(mtime)),
+        None => Ok(()),
+    }
+}
+
+/// Modification time of an entry, from the extended timestamp field, else
+/// the DOS timestamp.
+fn entry_mtime<R: Read>(entry: &ZipFile<'_, R>) -> Option<u64> {
+    entry
+        .extra_data_fields()
+        .find_map(|field| match field {
+            zip::ExtraField::ExtendedTimestamp(timestamp) => timestamp.mod_time(),
+            _ => None,
+        })
+        .map(u64::from)
+        .or_else(|| entry.last_modified().map(dos_secs))
+}
+
+#[cfg(unix)]
+fn restore_symlink(link: &str, target: &Path) -> io::Result<()> {
+    if target.symlink_metadata().is_ok() {
+        fs::remove_file(target)?;
+    }
+    std::os::unix::fs::symlink(link, target)
+}
+
+/// Without symlink support the link target is written as the file contents,
+/// as `unzip` does.
+#[cfg(not(unix))]
+fn restore_symlink(link: &str, target: &Path) -> io::Result<()> {
+    fs::write(target, link)
+}
+
+/// Reads every entry of a zip archive for `convert_archive`, handing each
+/// to `add` with its (decrypted and decompressed) data. Returns the archive
+/// comment.
+pub(crate) fn read_zip_entries<F, E>(
+    archive_path: &Path,
+    password: &ZipPassword,
+    name_encoding: ZipNameEncoding,
+    mut add: F,
+) -> Result<Option<String>, E>
+where
+    F: FnMut(ArchiveEntry, &mut dyn Read) -> Result<(), E>,
+    E: From<ZippingErr>,
+{
+    let source = open_zip_file(archive_path)?;
+    let mut archive = ZipArchive::new(source).map_err(ZippingErr::from)?;
+    let comment = String::from_utf8_lossy(archive.comment()).into_owned();
+
+    for index in 0..archive.len() {
+        let (name, encrypted) = {
+            let entry = archive.by_index_raw(index).map_err(ZippingErr::from)?;
+            (name_encoding.entry_name(&entry), entry.encrypted())
+        };
+        let entry = match encrypted {
+            true => {
+                let password = password
+                    .for_entry(&name)
+                    .ok_or_else(|| ZippingErr::PasswordRequired(name.clone()))?;
+                archive.by_index_decrypt(index, password.as_bytes())
+            }
+            false => archive.by_index(index),
+        };
+        let mut entry = entry.map_err(|e| match e {
+            ZipError::InvalidPassword => ZippingErr::WrongPassword(name.clone()),
+            e => e.into(),
+        })?;
+
+        let kind = if entry.is_dir() {
+            EntryKind::Directory
+        } else if entry.is_symlink() {
+            let mut link = String::new();
+            entry
+                .read_to_string(&mut link)
+                .map_err(|e| ZippingErr::CorruptEntry(name.clone(), e))?;
+            EntryKind::Symlink(link)
+        } else {
+            EntryKind::File
+        };
+        let default_mode = match kind {
+            EntryKind::Directory => 0o755,
+            _ => 0o644,
+        };
+        let converted = ArchiveEntry {
+            name: name.trim_end_matches('/').to_string(),
+            size: match kind {
+                EntryKind::File => entry.size(),
+                _ => 0,
+            },
+            kind,
+            mode: entry.unix_mode().map_or(default_mode, |mode| mode & 0o7777),
+            mtime: entry_mtime(&entry).unwrap_or(0),
+            mtime_nanos: 0,
+            owner: None,
+            xattrs: Vec::new(),
+            comment: entry.comment().to_string(),
+            extra_fields: entry
+                .extra_data()
+                .map(extra_fields)
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|field| !MANAGED_EXTRA_IDS.contains(&field.id))
+                .map(|field| (field.id, field.data))
+                .collect(),
+            encrypted,
+            sparse: false,
+        };
+        add(converted, &mut entry)?;
+    }
+    Ok(Some(comment))
+}
+
+/// Zip output of `convert_archive`, written to a temporary file until
+/// `finish`.
+pub(crate) struct ZipConvertWriter<'a> {
+    pub(crate) options: &'a ZipOptions,
+    temp_dir: TempDir,
+    temp_file_path: PathBuf,
+    zip: ZipWriter<File>,
+}
+
+impl<'a> ZipConvertWriter<'a> {
+    pub(crate) fn new(options: &'a ZipOptions) -> Result<Self, ZippingErr> {
+        if let Some(volume_size) = options.volume_size.filter(|&size| size < MIN_VOLUME_SIZE) {
+            return Err(ZippingErr::InvalidVolumeSize(volume_size));
+        }
+        let (temp_dir, temp_file_path) =
+            create_temp_file("zip").map_err(ZippingErr::TempFileCreationFailed)?;
+        let temp_file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp_file_path)
+            .map_err(ZippingErr::FileOpenFailed)?;
+        Ok(ZipConvertWriter {
+            options,
+            temp_dir,
+            temp_file_path,
+            zip: ZipWriter::new(temp_file),
+        })
+    }
+
+    /// Writes `entry`; a hard link becomes a copy of its (already written)
+    /// target. Files get the comment and extra fields `options.entry_metadata`
+    /// gives them, else those of the input.
+    pub(crate) fn add(
+        &mut self,
+        entry: &ArchiveEntry,
+        data: &mut dyn Read,
+    ) -> Result<(), ZippingErr> {
+        let options = self.options;
+        let name = entry.name.clone();
+        let stamp = EntryStamp::new(entry.mtime, entry.mode, options);
+        let mut link_options = FullFileOptions::default()
+            .last_modified_time(stamp.dos_time())
+            .unix_permissions(stamp.mode);
+        link_options.add_extra_data(EXTENDED_TIMESTAMP_ID, stamp.extended_timestamp(), false)?;
+        match &entry.kind {
+            EntryKind::Directory => self.zip.add_directory(name, link_options)?,
+            EntryKind::Symlink(target) => self.zip.add_symlink(name, target, link_options)?,
+            EntryKind::HardLink(target) => self.zip.deep_copy_file(target, &name)?,
+            EntryKind::File | EntryKind::Special { .. } => {
+                let method = options.method.select(Path::new(&name), &name);
+                check_lzma_size(method, Some(entry.size), &name)?;
+                let info = options
+                    .entry_metadata
+                    .for_entry(Path::new(&name), &name)
+                    .unwrap_or_else(|| ZipEntryInfo {
+                        comment: entry.comment.clone(),
+                        extra_fields: entry
+                            .extra_fields
+                            .iter()
+                            .map(|(id, data)| ZipExtraField {
+                                id: *id,
+                                data: data.clone(),
+                            })
+                            .collect(),
+                    });
+                let new_entry = NewEntry {
+                    method,
+                    name,
+                    stamp,
+                    large_file: entry.size >= ZIP64_THRESHOLD,
+                    info,
+                };
+                write_entry(&mut self.zip, data, new_entry, options)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Finishes the archive with `options.comment`, else the comment of the
+    /// input, and moves it to `output_path`.
+    pub(crate) fn finish(
+        mut self,
+        output_path: &Path,
+        comment: Option<&str>,
+    ) -> Result<(), ZippingErr> {
+        if let Some(comment) = self.options.comment.as_deref().or(comment) {
+            set_archive_comment(&mut self.zip, comment)?;
+        }
+        let mut archive = self.zip.finish().map_err(ZippingErr::FinishFailed)?;
+        if !matches!(self.options.password, ZipPassword::None) {
+            mark_ae2(&mut archive, &HashSet::new())?;
+        }
+        drop(archive);
+        finish_zip_output(
+            self.temp_dir,
+            &self.temp_file_path,
+            output_path,
+            self.options,
+        )
+    }
+}
+
+/// Glob set matching entry names; `*` does not cross `/`, `**` does.
+fn entry_globs(patterns: &[&str]) -> Result<GlobSet, ZippingErr> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(ZippingErr::InvalidPattern)?;
+        builder.add(glob);
+    }
+    builder.build().map_err(ZippingErr::InvalidPattern)
+}
+
+/// What happens to an existing entry while an archive is rewritten.
+enum EntryEdit<'a> {
+    Keep,
+    Delete,
+    Replace(&'a Path, &'a ZipOptions),
+}
+
+/// Copies `archive_path` into a temporary archive, applying `edit` to each
+/// existing entry and then letting `add` write new ones (it receives the
+/// names already present), and moves the result over the original.
+fn rewrite_zip_archive<'a, E, A>(archive_path: &Path, mut edit: E, add: A) -> Result<(), ZippingErr>
+where
+    E: FnMut(&str) -> EntryEdit<'a>,
+    A: FnOnce(&HashSet<String>, &mut ZipWriter<File>) -> Result<(), ZippingErr>,
+{
+    let (temp_dir, temp_file_path) =
+        create_temp_file("zip").map_err(ZippingErr::TempFileCreationFailed)?;
+    let source = File::open(archive_path).map_err(ZippingErr::FileOpenFailed)?;
+    let mut source = ZipArchive::new(source)?;
+    let temp_file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&temp_file_path)
+        .map_err(ZippingErr::FileOpenFailed)?;
+    let mut raw = File::open(archive_path).map_err(ZippingErr::FileOpenFailed)?;
+    let mut zip = ZipWriter::new(temp_file);
+    zip.set_raw_comment(source.comment().into())?;
+
+    let mut names = HashSet::new();
+    let mut kept = HashSet::new();
+    for index in 0..source.len() {
+        let entry = source.by_index_raw(index)?;
+        let name = entry.name().to_string();
+        match edit(&name) {
+            EntryEdit::Keep => {
+                let location = EntryLocation {
+                    header_start: entry.header_start(),
+                    data_end: entry.data_start().unwrap_or_default() + entry.compressed_size(),
+                    central_header_start: entry.central_header_start(),
+                };
+                drop(entry);
+                copy_verbatim(&mut zip, &mut raw, location)?;
+                kept.insert(name.clone());
+            }
+            EntryEdit::Delete => continue,
+            EntryEdit::Replace(file_path, options) => {
+                drop(entry);
+                write_file_entry(&mut zip, file_path, name.clone(), options, None)?;
+            }
+        }
+        names.insert(name);
+    }
+    add(&names, &mut zip)?;
+
+    let mut archive = zip.finish().map_err(ZippingErr::FinishFailed)?;
+    mark_ae2(&mut archive, &kept)?;
+    make_permanent(temp_dir, &temp_file_path, archive_path)
+        .map_err(ZippingErr::TempFileFinalizationFailed)?;
+    Ok(())
+}
+
+/// Files selected by `options.filter` with their (transformed) entry names.
+fn zip_entries<P: AsRef<Path>>(
+    files: &[P],
+    options: &ZipOptions,
+) -> Result<Vec<(PathBuf, String)>, ZippingErr> {
+    let mut entries = Vec::new();
+    let mut names = HashSet::new();
+    for file in resolve_files(files, &options.filter).map_err(ZippingErr::FileSelectionFailed)? {
+        let relative = options
+            .transform
+            .apply(&file.relative)
+            .map_err(ZippingErr::PathTransformFailed)?;
+        if let Some(relative) = relative {
+            let name = entry_name(&relative).ok_or(ZippingErr::InvalidFileName)?;
+            if !names.insert(name.clone()) {
+                return Err(ZippingErr::DuplicateEntry(name));
+            }
+            entries.push((file.path, name));
+        }
+    }
+    if options.reproducible.is_some() {
+        entries.sort_by(|a, b| a.1.cmp(&b.1));
+    }
+    Ok(entries)
+}
+
+fn write_file_entry<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    file_path: &Path,
+    file_name: String,
+    options: &ZipOptions,
+    manifest: Option<&mut ManifestBuilder>,
+) -> Result<(), ZippingErr> {
+    let metadata = fs::metadata(file_path).map_err(ZippingErr::FileOpenFailed)?;
+    let mut method = options.method.select(file_path, &file_name);
+    if let Some(min_savings) = options.store_below {
+        if method != ZipMethod::Stored
+            && trial_savings(file_path, method, options.level)? < min_savings
+        {
+            method = ZipMethod::Stored;
+        }
+    }
+    check_lzma_size(method, Some(metadata.len()), &file_name)?;
+    let entry = NewEntry {
+        info: entry_info(file_path, &file_name, options),
+        name: file_name,
+        stamp: EntryStamp::new(modified_secs(&metadata), metadata_mode(&metadata), options),
+        method,
+        large_file: metadata.len() >= ZIP64_THRESHOLD,
+    };
+    let mut file = File::open(file_path).map_err(ZippingErr::FileOpenFailed)?;
+    match manifest {
+        Some(manifest) => {
+            let name = entry.name.clone();
+            let mut hasher = manifest.hasher();
+            write_entry(
+                zip,
+                &mut HashingReader::new(file, &mut hasher),
+                entry,
+                options,
+            )?;
+            manifest.add(name, hasher);
+            Ok(())
+        }
+        None => write_entry(zip, &mut file, entry, options),
+    }
+}
+
+/// Adds the manifest entry, stamped with the current time, unless the
+/// manifest goes only to the sidecar file.
+fn write_manifest<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    manifest: &ManifestBuilder,
+    options: &ZipOptions,
+) -> Result<(), ZippingErr> {
+    let Some((name, contents)) = manifest.entry() else {
+        return Ok(());
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let entry = NewEntry {
+        name: name.to_string(),
+        stamp: EntryStamp::new(now, 0o644, options),
+        method: options.method.select(Path::new(name), name),
+        large_file: false,
+        info: ZipEntryInfo::default(),
+    };
+    write_entry(zip, &mut contents.as_slice(), entry, options)
+}
+
+/// An entry about to be written.
+struct NewEntry {
+    name: String,
+    stamp: EntryStamp,
+    method: ZipMethod,
+    large_file: bool,
+    /// Comment and extra fields of the entry.
+    info: ZipEntryInfo,
+}
+
+/// Comment and extra fields `options.entry_metadata` gives the entry `name`.
+fn entry_info(path: &Path, name: &str, options: &ZipOptions) -> ZipEntryInfo {
+    options
+        .entry_metadata
+        .for_entry(path, name)
+        .unwrap_or_default()
+}
+
+fn write_entry<W: Write + Seek, R: Read + ?Sized>(
+    zip: &mut ZipWriter<W>,
+    reader: &mut R,
+    entry: NewEntry,
+    options: &ZipOptions,
+) -> Result<(), ZippingErr> {
+    let NewEntry {
+        name,
+        stamp,
+        method,
+        large_file,
+        info,
+    } = entry;
+    let password = options.password.for_entry(&name);
+    // Extra fields written alongside: the extended timestamp, the AES field
+    // when encrypting, and the central directory ZIP64 field: both sizes and
+    // the offset for large files, and the offset alone for any entry that
+    // starts past 4 GiB.
+    let zip64 = if large_file { 28 } else { 12 };
+    let managed = 9 + 11 * usize::from(password.is_some()) + zip64;
+    check_entry_info(&info, &name, managed)?;
+
+    if method == ZipMethod::Lzma {
+        if password.is_some() {
+            return Err(ZippingErr::EncryptionUnsupported(name));
+        }
+        let payload = lzma_entry(reader, &name, stamp, &info, options.level)?;
+        zip.merge_archive(ZipArchive::new(payload)?)?;
+        return Ok(());
+    }
+
+    let mut file_options = FullFileOptions::default()
+        .compression_method(method.into())
+        .compression_level(options.level.filter(|_| method != ZipMethod::Stored))
+        .last_modified_time(stamp.dos_time())
+        .unix_permissions(stamp.mode)
+        .large_file(large_file);
+    if !info.comment.is_empty() {
+        file_options = file_options.with_file_comment(info.comment.as_str());
+    }
+    file_options.add_extra_data(EXTENDED_TIMESTAMP_ID, stamp.extended_timestamp(), false)?;
+    for field in &info.extra_fields {
+        file_options.add_extra_data(field.id, &field.data, false)?;
+    }
+    let file_options = match (&password, options.cipher) {
+        (None, _) => file_options,
+        (Some(password), ZipCipher::Aes256) => {
+            file_options.with_aes_encryption(AesMode::Aes256, password)
+        }
+        (Some(password), ZipCipher::ZipCrypto) => {
+            file_options.with_deprecated_encryption(password.as_bytes())?
+        }
+    };
+    zip.start_file(name, file_options)?;
+    io::copy(reader, zip).map_err(ZippingErr::WriteFailed)?;
+    Ok(())
+}
+
+/// Longest archive comment, entry comment or extra field block.
+const MAX_FIELD_LEN: usize = u16::MAX as usize;
+
+/// Header ids of the extra fields this crate and the `zip` crate write or
+/// interpret themselves: ZIP64 sizes, the extended timestamp, WinZip AES and
+/// the Info-ZIP Unicode Path and Comment fields.
+const MANAGED_EXTRA_IDS: &[u16] = &[
+    0x0001,
+    EXTENDED_TIMESTAMP_ID,
+    AES_EXTRA_ID,
+    UNICODE_PATH_ID,
+    UNICODE_COMMENT_ID,
+];
+
+fn set_archive_comment<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    comment: &str,
+) -> Result<(), ZippingErr> {
+    if comment.len() > MAX_FIELD_LEN {
+        return Err(ZippingErr::FieldTooLong("The archive comment".to_string()));
+    }
+    zip.set_comment(comment)?;
+    Ok(())
+}
+
+/// Checks the comment and extra fields of entry `name` against the limits
+/// of the format; `managed` is the size of the extra fields written with them.
+fn check_entry_info(info: &ZipEntryInfo, name: &str, managed: usize) -> Result<(), ZippingErr> {
+    if info.comment.len() > MAX_FIELD_LEN {
+        return Err(ZippingErr::FieldTooLong(format!("The comment of {}", name)));
+    }
+    let mut len = managed;
+    for field in &info.extra_fields {
+        if MANAGED_EXTRA_IDS.contains(&field.id) {
+            return Err(ZippingErr::ReservedExtraField(field.id));
+        }
+        len += 4 + field.data.len();
+    }
+    match len > MAX_FIELD_LEN {
+        true => Err(ZippingErr::FieldTooLong(format!(
+            "The extra fields of {}",
+            name
+        ))),
+        false => Ok(()),
+    }
+}
+
+/// Header id of the WinZip AES extra field.
+const AES_EXTRA_ID: u16 = 0x9901;
+
+/// The `zip` crate writes AES entries of 20 bytes or more as AE-1, which
+/// keeps the CRC of the plaintext. Rewrites every AE-1 entry not in `kept`
+/// as AE-2: vendor version 2 in the AES extra field and a zero CRC, in both
+/// the local and the central header.
+fn mark_ae2(file: &mut File, kept: &HashSet<String>) -> Result<(), ZippingErr> {
+    let mut headers = Vec::new();
+    let mut archive = ZipArchive::new(&mut *file)?;
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index)?;
+        let ae1 = entry
+            .extra_data()
+            .is_some_and(|extra| aes_vendor_version(extra) == Some(1));
+        if ae1 && !kept.contains(entry.name()) {
+            headers.push((entry.header_start(), entry.central_header_start()));
+        }
+    }
+    drop(archive);
+
+    for (local, central) in headers {
+        patch_ae2_header(file, local, 14, 26, 30).map_err(ZippingErr::WriteFailed)?;
+        patch_ae2_header(file, central, 16, 28, 46).map_err(ZippingErr::WriteFailed)?;
+    }
+    Ok(())
+}
+
+/// Where an entry's headers and data sit in its archive.
+struct EntryLocation {
+    header_start: u64,
+    data_end: u64,
+    central_header_start: u64,
+}
+
+/// Copies an entry of the archive `source` byte for byte, data descriptor
+/// included, by merging a one-entry archive built from its headers.
+/// `raw_copy_file` writes fresh headers, which would drop extra fields and
+/// leave ZipCrypto entries looking unencrypted.
+fn copy_verbatim(
+    zip: &mut ZipWriter<File>,
+    source: &mut File,
+    location: EntryLocation,
+) -> Result<(), ZippingErr> {
+    let mut read_at = |offset: u64, len: usize| -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0u8; len];
+        source.seek(SeekFrom::Start(offset))?;
+        source.read_exact(&mut bytes)?;
+        Ok(bytes)
+    };
+    let le16 =
+        |bytes: &[u8], at: usize| usize::from(u16::from_le_bytes([bytes[at], bytes[at + 1]]));
+    let le32 = |bytes: &[u8], at: usize| {
+        u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    };
+
+    let fixed = read_at(location.header_start, 30)?;
+    let local_len = 30 + le16(&fixed, 26) + le16(&fixed, 28);
+    let mut head = read_at(location.header_start, local_len)?;
+    let data_start = location.header_start + local_len as u64;
+    let mut end = location.data_end;
+    if le16(&head, 6) & 0x0008 != 0 {
+        let zip64 = extra_field(&head[30 + le16(&head, 26)..], 0x0001).is_some();
+        if le32(&read_at(end, 4)?, 0) == 0x0807_4b50 {
+            end += 4;
+        }
+        end += if zip64 { 20 } else { 12 };
+    }
+
+    let fixed = read_at(location.central_header_start, 46)?;
+    let central_len = 46 + le16(&fixed, 28) + le16(&fixed, 30) + le16(&fixed, 32);
+    let mut tail = read_at(location.central_header_start, central_len)?;
+    // The `zip` crate reads the names of Info-ZIP Unicode Path and Comment
+    // fields into the entry and would write them back next to the fields,
+    // whose checksums then no longer match. Store them as UTF-8 instead.
+    if let Some((name, comment)) = unicode_fields(&tail) {
+        head = utf8_header(&head, false, &name, None);
+        tail = utf8_header(&tail, true, &name, comment.as_deref());
+    }
+    // The entry now starts at offset 0, which is stored in the ZIP64 extra
+    // field when the header holds the 0xFFFFFFFF marker.
+    if le32(&tail, 42) == u32::MAX {
+        let extra_start = 46 + le16(&tail, 28);
+        let extra = &tail[extra_start..extra_start + le16(&tail, 30)];
+        let (data, _) = extra_field(extra, 0x0001).ok_or_else(|| {
+            ZippingErr::ZipOperationFailed(ZipError::InvalidArchive("missing ZIP64 offset".into()))
+        })?;
+        let skipped = 8 * [24, 20]
+            .iter()
+            .filter(|&&at| le32(&tail, at) == u32::MAX)
+            .count();
+        let at = extra_start + data + skipped;
+        tail[at..at + 8].copy_from_slice(&0u64.to_le_bytes());
+    } else {
+        tail[42..46].copy_from_slice(&0u32.to_le_bytes());
+    }
+
+    let directory = CentralDirectory {
+        entries: 1,
+        size: tail.len() as u64,
+        offset: head.len() as u64 + end - data_start,
+        entries_on_last_disk: 1,
+        ..CentralDirectory::default()
+    };
+    tail.extend_from_slice(&directory.end_records(directory.offset + directory.size, b""));
+
+    let entry = VerbatimEntry {
+        head,
+        source,
+        start: data_start,
+        len: end - data_start,
+        tail,
+        position: 0,
+    };
+    zip.merge_archive(ZipArchive::new(entry)?)?;
+    Ok(())
+}
+
+/// One-entry archive made of a local header held in memory, the entry data
+/// read from a byte range of another archive and a central directory held
+/// in memory, so the data is streamed.
+struct VerbatimEntry<'a> {
+    head: Vec<u8>,
+    source: &'a mut File,
+    start: u64,
+    len: u64,
+    tail: Vec<u8>,
+    position: u64,
+}
+
+impl Read for VerbatimEntry<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let head = self.head.len() as u64;
+        let read = if self.position >= head && self.position < head + self.len {
+            let offset = self.position - head;
+            let wanted = (self.len - offset).min(buf.len() as u64) as usize;
+            self.source.seek(SeekFrom::Start(self.start + offset))?;
+            self.source.read(&mut buf[..wanted])?
+        } else {
+            let (bytes, at) = match self.position < head {
+                true => (&self.head, self.position),
+                false => (&self.tail, self.position - head - self.len),
+            };
+            let rest = usize::try_from(at)
+                .ok()
+                .and_then(|at| bytes.get(at..))
+                .unwrap_or_default();
+            let read = rest.len().min(buf.len());
+            buf[..read].copy_from_slice(&rest[..read]);
+            read
+        };
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for VerbatimEntry<'_> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let end = self.head.len() as u64 + self.len + self.tail.len() as u64;
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => end.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before the start"))?;
+        Ok(self.position)
+    }
+}
+
+/// Smallest volume size of split archives, the minimum of Info-ZIP `zip -s`.
+const MIN_VOLUME_SIZE: u64 = 64 * 1024;
+
+/// Path of volume `number` (counting from 1) of a split archive whose last
+/// volume is `archive_path`: `archive.z01`, `archive.z02`, ...
+fn zip_volume_path(archive_path: &Path, number: usize) -> PathBuf {
+    archive_path.with_extension(format!("z{:02}", number))
+}
+
+/// Volumes of the archive at `archive_path`: the `.zNN` volumes next to it,
+/// if any, followed by the archive itself.
+fn zip_volumes(archive_path: &Path) -> Vec<PathBuf> {
+    let mut volumes: Vec<PathBuf> = (1..)
+        .map(|number| zip_volume_path(archive_path, number))
+        .take_while(|volume| volume.exists())
+        .collect();
+    volumes.push(archive_path.to_path_buf());
+    volumes
+}
+
+/// Opens an archive for reading; a split archive is joined into an
+/// anonymous temporary file first.
+fn open_zip_file(archive_path: &Path) -> Result<File, ZippingErr> {
+    let volumes = zip_volumes(archive_path);
+    if volumes.len() == 1 {
+        return File::open(archive_path).map_err(ZippingErr::FileOpenFailed);
+    }
+    let mut joined = tempfile::tempfile().map_err(ZippingErr::FileOpenFailed)?;
+    let mut output = BufWriter::new(&mut joined);
+    join_zip_volumes(&volumes, &mut output)?;
+    output.flush().map_err(ZippingErr::WriteFailed)?;
+    drop(output);
+    joined.rewind().map_err(ZippingErr::FileOpenFailed)?;
+    Ok(joined)
+}
+
+/// Moves the finished archive to `output_path`, or writes it as split
+/// volumes when it is larger than `options.volume_size`. Volumes left over
+/// from an earlier archive at the same path are removed.
+fn finish_zip_output(
+    temp_dir: TempDir,
+    temp_file_path: &Path,
+    output_path: &Path,
+    options: &ZipOptions,
+) -> Result<(), ZippingErr> {
+    let len = fs::metadata(temp_file_path)
+        .map_err(ZippingErr::FileOpenFailed)?
+        .len();
+    let mut written = 0;
+    match options.volume_size {
+        Some(volume_size) if len > volume_size => {
+            let volumes = write_zip_volumes(temp_file_path, temp_dir.path(), volume_size)?;
+            written = volumes.len() - 1;
+            for (number, volume) in volumes[..written].iter().enumerate() {
+                fs::rename(volume, zip_volume_path(output_path, number + 1))
+                    .map_err(ZippingErr::WriteFailed)?;
+            }
+            make_permanent(temp_dir, &volumes[written], output_path)
+        }
+        _ => make_permanent(temp_dir, temp_file_path, output_path),
+    }
+    .map_err(ZippingErr::TempFileFinalizationFailed)?;
+
+    for stale in (written + 1..)
+        .map(|number| zip_volume_path(output_path, number))
+        .take_while(|volume| volume.exists())
+    {
+        fs::remove_file(stale).map_err(ZippingErr::WriteFailed)?;
+    }
+    Ok(())
+}
+
+/// Rewrites the single-file archive at `archive_path` as the volumes of a
+/// split archive of at most `volume_size` bytes each, in `directory`.
+/// Entry data may span volumes, headers and records may not. Offsets in
+/// the central directory become relative to the volume they point into.
+fn write_zip_volumes(
+    archive_path: &Path,
+    directory: &Path,
+    volume_size: u64,
+) -> Result<Vec<PathBuf>, ZippingErr> {
+    let mut archive =
+        ZipArchive::new(File::open(archive_path).map_err(ZippingErr::FileOpenFailed)?)?;
+    let central_start = archive.central_directory_start();
+    let mut headers = (0..archive.len())
+        .map(|index| Ok(archive.by_index_raw(index)?.header_start()))
+        .collect::<Result<Vec<_>, ZipError>>()?;
+    headers.sort_unstable();
+    let comment = archive.comment().to_vec();
+    let mut source = BufReader::new(archive.into_inner());
+
+    let write = ZippingErr::WriteFailed;
+    let mut volumes = VolumeWriter::new(directory, volume_size);
+    // Signature at the start of the first volume of a split archive.
+    volumes
+        .write_all(&DATA_DESCRIPTOR_SIG.to_le_bytes())
+        .map_err(write)?;
+    let mut moved = HashMap::new();
+    for (index, &start) in headers.iter().enumerate() {
+        let end = headers.get(index + 1).copied().unwrap_or(central_start);
+        source.seek(SeekFrom::Start(start))?;
+        let mut header = vec![0u8; 30];
+        source.read_exact(&mut header)?;
+        let len = 30
+            + usize::from(u16::from_le_bytes([header[26], header[27]]))
+            + usize::from(u16::from_le_bytes([header[28], header[29]]));
+        header.resize(len, 0);
+        source.read_exact(&mut header[30..])?;
+        volumes.keep_together(len as u64).map_err(write)?;
+        moved.insert(start, volumes.position());
+        volumes.write_all(&header).map_err(write)?;
+        io::copy(
+            &mut (&mut source).take(end - start - len as u64),
+            &mut volumes,
+        )
+        .map_err(write)?;
+    }
+
+    source.seek(SeekFrom::Start(central_start))?;
+    let mut directory = CentralDirectory::default();
+    for _ in 0..headers.len() {
+        let record = read_central_record(&mut source)?;
+        let (_, offset) = central_offset(&record);
+        let &(disk, relative) = moved.get(&offset).ok_or_else(|| {
+            ZippingErr::ZipOperationFailed(ZipError::InvalidArchive(
+                "central directory record without a local header".into(),
+            ))
+        })?;
+        let record = with_central_offset(record, disk, relative)?;
+        volumes.keep_together(record.len() as u64).map_err(write)?;
+        directory.add_record(volumes.position(), record.len() as u64);
+        volumes.write_all(&record).map_err(write)?;
+    }
+    let len = directory.end_records(0, &comment).len() as u64;
+    volumes.keep_together(len).map_err(write)?;
+    let (disk, position) = volumes.position();
+    directory.end_on(disk, position);
+    volumes
+        .write_all(&directory.end_records(position, &comment))
+        .map_err(write)?;
+    volumes.finish().map_err(write)
+}
+
+/// Writes the split archive made of `volumes` as a single-file archive:
+/// the volumes are concatenated without the split signature and the
+/// offsets of the central directory are made absolute.
+fn join_zip_volumes<W: Write>(volumes: &[PathBuf], output: &mut W) -> Result<(), ZippingErr> {
+    let mut reader = VolumeReader::new(volumes.to_vec()).map_err(ZippingErr::FileOpenFailed)?;
+    let starts = reader.volume_starts();
+    let (end, comment) = read_end_records(&mut reader, &starts)?;
+    let malformed = || {
+        ZippingErr::ZipOperationFailed(ZipError::InvalidArchive(
+            "offset outside the split archive".into(),
+        ))
+    };
+
+    // Split archives start with the split signature, and archives that were
+    // meant to be split but fit in one volume start with the "PK00" marker;
+    // either is dropped.
+    let mut marker = [0u8; 4];
+    reader.rewind()?;
+    let skipped = match reader.read_exact(&mut marker) {
+        Ok(()) if [DATA_DESCRIPTOR_SIG, 0x3030_4b50].contains(&u32::from_le_bytes(marker)) => 4,
+        _ => 0,
+    };
+    let absolute = |disk: u32, offset: u64| {
+        starts
+            .get(disk as usize)
+            .and_then(|start| (start + offset).checked_sub(skipped))
+            .ok_or_else(malformed)
+    };
+
+    let central_start = absolute(end.disk, end.offset)?;
+    reader.seek(SeekFrom::Start(skipped))?;
+    io::copy(&mut (&mut reader).take(central_start), output).map_err(ZippingErr::WriteFailed)?;
+    reader.seek(SeekFrom::Start(central_start + skipped))?;
+    let mut reader = BufReader::new(reader);
+    let mut size = 0;
+    for _ in 0..end.entries {
+        let record = read_central_record(&mut reader)?;
+        let (disk, offset) = central_offset(&record);
+        let record = with_central_offset(record, 0, absolute(disk, offset)?)?;
+        size += record.len() as u64;
+        output.write_all(&record).map_err(ZippingErr::WriteFailed)?;
+    }
+    let directory = CentralDirectory {
+        entries: end.entries,
+        size,
+        offset: central_start,
+        entries_on_last_disk: end.entries,
+        ..CentralDirectory::default()
+    };
+    output
+        .write_all(&directory.end_records(central_start + size, &comment))
+        .map_err(ZippingErr::WriteFailed)
+}
+
+/// Location of the central directory of a possibly split archive, as held
+/// by its end records.
+#[derive(Debug, Default)]
+struct CentralDirectory {
+    entries: u64,
+    size: u64,
+    /// Volume of the first record and its offset in that volume.
+    disk: u32,
+    offset: u64,
+    /// Volume holding the end records and the number of records on it.
+    last_disk: u32,
+    entries_on_last_disk: u64,
+}
+
+impl CentralDirectory {
+    /// Counts a record of `len` bytes written at `position` (volume, offset).
+    fn add_record(&mut self, (disk, offset): (u32, u64), len: u64) {
+        self.end_on(disk, offset);
+        self.entries += 1;
+        self.entries_on_last_disk += 1;
+        self.size += len;
+    }
+
+    /// Notes that the next record or the end records start at `offset` in
+    /// `disk`.
+    fn end_on(&mut self, disk: u32, offset: u64) {
+        if self.entries == 0 {
+            (self.disk, self.offset) = (disk, offset);
+        }
+        if disk != self.last_disk {
+            self.last_disk = disk;
+            self.entries_on_last_disk = 0;
+        }
+    }
+
+    /// End of central directory record, preceded by the ZIP64 end record
+    /// and its locator when a value does not fit; `position` is their
+    /// offset in the last volume.
+    fn end_records(&self, position: u64, comment: &[u8]) -> Vec<u8> {
+        let mut records = Vec::with_capacity(98 + comment.len());
+        if self.entries >= 0xFFFF || self.size >= ZIP64_THRESHOLD || self.offset >= ZIP64_THRESHOLD
+        {
+            records.extend_from_slice(&ZIP64_END_OF_CENTRAL_SIG.to_le_bytes());
+            records.extend_from_slice(&44u64.to_le_bytes());
+            records.extend_from_slice(&[45, 0, 45, 0]);
+            records.extend_from_slice(&self.last_disk.to_le_bytes());
+            records.extend_from_slice(&self.disk.to_le_bytes());
+            records.extend_from_slice(&self.entries_on_last_disk.to_le_bytes());
+            records.extend_from_slice(&self.entries.to_le_bytes());
+            records.extend_from_slice(&self.size.to_le_bytes());
+            records.extend_from_slice(&self.offset.to_le_bytes());
+            records.extend_from_slice(&ZIP64_LOCATOR_SIG.to_le_bytes());
+            records.extend_from_slice(&self.last_disk.to_le_bytes());
+            records.extend_from_slice(&position.to_le_bytes());
+            records.extend_from_slice(&(self.last_disk + 1).to_le_bytes());
+        }
+        let short = |value: u64| value.min(0xFFFF) as u16;
+        let long = |value: u64| value.min(ZIP64_THRESHOLD) as u32;
+        records.extend_from_slice(&END_OF_CENTRAL_SIG.to_le_bytes());
+        records.extend_from_slice(&short(self.last_disk.into()).to_le_bytes());
+        records.extend_from_slice(&short(self.disk.into()).to_le_bytes());
+        records.extend_from_slice(&short(self.entries_on_last_disk).to_le_bytes());
+        records.extend_from_slice(&short(self.entries).to_le_bytes());
+        records.extend_from_slice(&long(self.size).to_le_bytes());
+        records.extend_from_slice(&long(self.offset).to_le_bytes());
+        records.extend_from_slice(&(comment.len() as u16).to_le_bytes());
+        records.extend_from_slice(comment);
+        records
+    }
+}
+
+/// Reads the end records at the end of the joined volumes of `reader`,
+/// which start at `starts`, and returns them with the archive comment.
+fn read_end_records(
+    reader: &mut VolumeReader,
+    starts: &[u64],
+) -> Result<(CentralDirectory, Vec<u8>), ZippingErr> {
+    let malformed = |reason: &'static str| {
+        ZippingErr::ZipOperationFailed(ZipError::InvalidArchive(reason.into()))
+    };
+    let tail_len = reader.len().min(22 + u64::from(u16::MAX));
+    reader.seek(SeekFrom::Start(reader.len() - tail_len))?;
+    let mut tail = vec![0u8; tail_len as usize];
+    reader.read_exact(&mut tail)?;
+    let le16 = |bytes: &[u8], at: usize| u64::from(u16::from_le_bytes([bytes[at], bytes[at + 1]]));
+    let le32 = |bytes: &[u8], at: usize| {
+        u64::from(u32::from_le_bytes([
+            bytes[at],
+            bytes[at + 1],
+            bytes[at + 2],
+            bytes[at + 3],
+        ]))
+    };
+    let le64 = |bytes: &[u8], at: usize| {
+        u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap_or_default())
+    };
+
+    let at = (0..=tail.len().saturating_sub(22))
+        .rev()
+        .find(|&at| {
+            le32(&tail, at) == u64::from(END_OF_CENTRAL_SIG)
+                && at as u64 + 22 + le16(&tail, at + 20) == tail_len
+        })
+        .ok_or_else(|| malformed("missing end of central directory record"))?;
+    let record = &tail[at..];
+    let mut directory = CentralDirectory {
+        last_disk: le16(record, 4) as u32,
+        disk: le16(record, 6) as u32,
+        entries_on_last_disk: le16(record, 8),
+        entries: le16(record, 10),
+        size: le32(record, 12),
+        offset: le32(record, 16),
+    };
+    let comment = record[22..].to_vec();
+
+    if at >= 20 && le32(&tail, at - 20) == u64::from(ZIP64_LOCATOR_SIG) {
+        let locator = &tail[at - 20..at];
+        let start = starts
+            .get(le32(locator, 4) as usize)
+            .ok_or_else(|| malformed("ZIP64 end record outside the split archive"))?;
+        reader.seek(SeekFrom::Start(start + le64(locator, 8)))?;
+        let mut record = [0u8; 56];
+        reader.read_exact(&mut record)?;
+        if le32(&record, 0) != u64::from(ZIP64_END_OF_CENTRAL_SIG) {
+            return Err(malformed("missing ZIP64 end of central directory record"));
+        }
+        directory = CentralDirectory {
+            last_disk: le32(&record, 16) as u32,
+            disk: le32(&record, 20) as u32,
+            entries_on_last_disk: le64(&record, 24),
+            entries: le64(&record, 32),
+            size: le64(&record, 40),
+            offset: le64(&record, 48),
+        };
+    }
+    Ok((directory, comment))
+}
+
+/// Reads a central directory header with its name, extra field and comment.
+fn read_central_record<R: Read>(reader: &mut R) -> Result<Vec<u8>, ZippingErr> {
+    let mut record = vec![0u8; 46];
+    reader.read_exact(&mut record)?;
+    if record[..4] != CENTRAL_HEADER_SIG.to_le_bytes() {
+        return Err(ZippingErr::ZipOperationFailed(ZipError::InvalidArchive(
+            "invalid central directory header".into(),
+        )));
+    }
+    let len = |at: usize| usize::from(u16::from_le_bytes([record[at], record[at + 1]]));
+    let variable = len(28) + len(30) + len(32);
+    record.resize(46 + variable, 0);
+    reader.read_exact(&mut record[46..])?;
+    Ok(record)
+}
+
+/// ZIP64 values of a central directory header: those of the size, offset
+/// and disk fields holding their 0xFFFF... marker, in that order.
+fn central_zip64_values(record: &[u8]) -> Vec<u64> {
+    let len = |at: usize| usize::from(u16::from_le_bytes([record[at], record[at + 1]]));
+    let extra = &record[46 + len(28)..46 + len(28) + len(30)];
+    extra_field(extra, 0x0001)
+        .map(|(at, len)| {
+            extra[at..at + len]
+                .chunks_exact(8)
+                .map(|value| u64::from_le_bytes(value.try_into().unwrap_or_default()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Number of ZIP64 size values in a central directory header.
+fn zip64_sizes(record: &[u8]) -> usize {
+    [24, 20]
+        .iter()
+        .filter(|&&at| record[at..at + 4] == [0xFF; 4])
+        .count()
+}
+
+/// Volume and offset of the local header a central directory header points to.
+fn central_offset(record: &[u8]) -> (u32, u64) {
+    let values = central_zip64_values(record);
+    let mut zip64 = values.iter().skip(zip64_sizes(record)).copied();
+    let offset = match u32::from_le_bytes([record[42], record[43], record[44], record[45]]) {
+        u32::MAX => zip64.next().unwrap_or(ZIP64_THRESHOLD),
+        offset => u64::from(offset),
+    };
+    let disk = match u16::from_le_bytes([record[34], record[35]]) {
+        u16::MAX => zip64.next().map_or(u32::from(u16::MAX), |disk| disk as u32),
+        disk => u32::from(disk),
+    };
+    (disk, offset)
+}
+
+/// Points a central directory header at the local header at `offset` in
+/// volume `disk`, moving the offset into a ZIP64 value when it does not fit.
+fn with_central_offset(mut record: Vec<u8>, disk: u32, offset: u64) -> Result<Vec<u8>, ZippingErr> {
+    let sizes = zip64_sizes(&record);
+    let len =
+        |record: &[u8], at: usize| usize::from(u16::from_le_bytes([record[at], record[at + 1]]));
+    let extra_start = 46 + len(&record, 28);
+    let extra_end = extra_start + len(&record, 30);
+    let mut fields = extra_fields(&record[extra_start..extra_end]);
+    let zip64 = fields.iter_mut().find(|field| field.id == 0x0001);
+    let in_extra = record[42..46] == [0xFF; 4];
+    match zip64 {
+        Some(field) if in_extra && field.data.len() >= 8 * sizes + 8 => {
+            field.data[8 * sizes..8 * sizes + 8].copy_from_slice(&offset.to_le_bytes());
+            // A ZIP64 disk number follows the offset only with the 0xFFFF marker.
+            field.data.truncate(8 * sizes + 8);
+        }
+        _ if offset < ZIP64_THRESHOLD => {
+            record[42..46].copy_from_slice(&(offset as u32).to_le_bytes());
+        }
+        Some(field) => {
+            field.data.truncate(8 * sizes);
+            field.data.extend_from_slice(&offset.to_le_bytes());
+            record[42..46].copy_from_slice(&[0xFF; 4]);
+        }
+        None => {
+            fields.insert(
+                0,
+                ZipExtraField {
+                    id: 0x0001,
+                    data: offset.to_le_bytes().to_vec(),
+                },
+            );
+            record[42..46].copy_from_slice(&[0xFF; 4]);
+        }
+    }
+    record[34..36].copy_from_slice(&(disk.min(0xFFFE) as u16).to_le_bytes());
+
+    let mut extra = Vec::new();
+    for field in &fields {
+        extra.extend_from_slice(&field.id.to_le_bytes());
+        extra.extend_from_slice(&(field.data.len() as u16).to_le_bytes());
+        extra.extend_from_slice(&field.data);
+    }
+    let extra_len = u16::try_from(extra.len())
+        .map_err(|_| ZippingErr::FieldTooLong("The extra field of an entry".to_string()))?;
+    record[30..32].copy_from_slice(&extra_len.to_le_bytes());
+    record.splice(extra_start..extra_end, extra);
+    Ok(record)
+}
+
+/// Header ids of the Info-ZIP Unicode Path and Unicode Comment fields.
+const UNICODE_PATH_ID: u16 = 0x7075;
+const UNICODE_COMMENT_ID: u16 = 0x6375;
+
+/// UTF-8 name and comment from the valid Info-ZIP Unicode fields of a
+/// central directory header, if it has a Unicode Path field.
+fn unicode_fields(central: &[u8]) -> Option<(Vec<u8>, Option<Vec<u8>>)> {
+    let len = |at: usize| usize::from(u16::from_le_bytes([central[at], central[at + 1]]));
+    let name = &central[46..46 + len(28)];
+    let extra = &central[46 + len(28)..46 + len(28) + len(30)];
+    let comment = &central[46 + len(28) + len(30)..];
+    let unicode_name = unicode_field(extra, UNICODE_PATH_ID, name)?;
+    Some((
+        unicode_name,
+        unicode_field(extra, UNICODE_COMMENT_ID, comment),
+    ))
+}
+
+/// UTF-8 text of the Info-ZIP Unicode field `id` in `extra`, if it is
+/// there and its checksum matches the legacy `field` it replaces.
+fn unicode_field(extra: &[u8], id: u16, field: &[u8]) -> Option<Vec<u8>> {
+    let (at, len) = extra_field(extra, id)?;
+    let data = &extra[at..at + len];
+    let mut crc = Crc::new();
+    crc.update(field);
+    (len >= 5 && data[0] == 1 && data[1..5] == crc.sum().to_le_bytes()).then(|| data[5..].to_vec())
+}
+
+/// Rewrites a local or central header with `name`, and `comment` when
+/// given, stored as UTF-8: the language encoding flag is set and the
+/// Info-ZIP Unicode fields are dropped.
+fn utf8_header(header: &[u8], central: bool, name: &[u8], comment: Option<&[u8]>) -> Vec<u8> {
+    let (fixed, flags_at, lengths_at) = match central {
+        true => (46, 8, 28),
+        false => (30, 6, 26),
+    };
+    let le16 = |at: usize| u16::from_le_bytes([header[at], header[at + 1]]);
+    let extra_start = fixed + usize::from(le16(lengths_at));
+    let extra_end = extra_start + usize::from(le16(lengths_at + 2));
+    let mut extra = Vec::new();
+    for field in extra_fields(&header[extra_start..extra_end]) {
+        if field.id != UNICODE_PATH_ID && field.id != UNICODE_COMMENT_ID {
+            extra.extend_from_slice(&field.id.to_le_bytes());
+            extra.extend_from_slice(&(field.data.len() as u16).to_le_bytes());
+            extra.extend_from_slice(&field.data);
+        }
+    }
+    let comment = comment.unwrap_or(&header[extra_end..]);
+
+    let mut rewritten = header[..fixed].to_vec();
+    rewritten[flags_at..flags_at + 2].copy_from_slice(&(le16(flags_at) | 0x0800).to_le_bytes());
+    rewritten[lengths_at..lengths_at + 2].copy_from_slice(&(name.len() as u16).to_le_bytes());
+    rewritten[lengths_at + 2..lengths_at + 4].copy_from_slice(&(extra.len() as u16).to_le_bytes());
+    rewritten.extend_from_slice(name);
+    rewritten.extend_from_slice(&extra);
+    if central {
+        rewritten[32..34].copy_from_slice(&(comment.len() as u16).to_le_bytes());
+        rewritten.extend_from_slice(comment);
+    }
+    rewritten
+}
+
+/// Offset of the data of the first extra field with header `id` in `extra`
+/// and its length.
+fn extra_field(extra: &[u8], id: u16) -> Option<(usize, usize)> {
+    let mut position = 0;
+    while position + 4 <= extra.len() {
+        let field = u16::from_le_bytes([extra[position], extra[position + 1]]);
+        let len = usize::from(u16::from_le_bytes([
+            extra[position + 2],
+            extra[position + 3],
+        ]));
+        if field == id && position + 4 + len <= extra.len() {
+            return Some((position + 4, len));
+        }
+        position += 4 + len;
+    }
+    None
+}
+
+/// Splits an extra field block into its fields.
+fn extra_fields(extra: &[u8]) -> Vec<ZipExtraField> {
+    let mut fields = Vec::new();
+    let mut position = 0;
+    while position + 4 <= extra.len() {
+        let id = u16::from_le_bytes([extra[position], extra[position + 1]]);
+        let len = usize::from(u16::from_le_bytes([
+            extra[position + 2],
+            extra[position + 3],
+        ]));
+        let Some(data) = extra.get(position + 4..position + 4 + len) else {
+            break;
+        };
+        fields.push(ZipExtraField {
+            id,
+            data: data.to_vec(),
+        });
+        position += 4 + len;
+    }
+    fields
+}
+
+/// Vendor version of the AES extra field in `extra`, if there is one.
+fn aes_vendor_version(extra: &[u8]) -> Option<u16> {
+    let (at, len) = extra_field(extra, AES_EXTRA_ID)?;
+    (len >= 2).then(|| u16::from_le_bytes([extra[at], extra[at + 1]]))
+}
+
+/// Zeroes the CRC at `crc_at` of the header starting at `start` and sets
+/// the vendor version of its AES extra field to 2. `lengths_at` is the
+/// offset of the name and extra field lengths, `fixed` the size of the
+/// header before the name.
+fn patch_ae2_header(
+    file: &mut File,
+    start: u64,
+    crc_at: u64,
+    lengths_at: u64,
+    fixed: u64,
+) -> io::Result<()> {
+    let mut lengths = [0u8; 4];
+    file.seek(SeekFrom::Start(start + lengths_at))?;
+    file.read_exact(&mut lengths)?;
+    let name_len = u64::from(u16::from_le_bytes([lengths[0], lengths[1]]));
+    let mut extra = vec![0u8; usize::from(u16::from_le_bytes([lengths[2], lengths[3]]))];
+    let extra_start = start + fixed + name_len;
+    file.seek(SeekFrom::Start(extra_start))?;
+    file.read_exact(&mut extra)?;
+
+    if let Some((at, len)) = extra_field(&extra, AES_EXTRA_ID) {
+        if len >= 2 {
+            file.seek(SeekFrom::Start(extra_start + at as u64))?;
+            file.write_all(&2u16.to_le_bytes())?;
+        }
+    }
+    file.seek(SeekFrom::Start(start + crc_at))?;
+    file.write_all(&[0; 4])
+}
+
+/// Modification time (seconds since the Unix epoch) and unix mode of an entry.
+#[derive(Debug, Clone, Copy)]
+struct EntryStamp {
+    mtime: u64,
+    mode: u32,
+}
+
+impl EntryStamp {
+    /// Stamp of a new entry, normalised for reproducible archives and with
+    /// the permissions override of `options` applied.
+    fn new(mtime: u64, mode: u32, options: &ZipOptions) -> Self {
+        let mut stamp = EntryStamp { mtime, mode };
+        if let Some(reproducible) = options.reproducible {
+            stamp.mtime = reproducible.clamp_mtime(stamp.mtime);
+            stamp.mode = reproducible.normalize_mode(stamp.mode, false);
+        }
+        if let Some(permissions) = options.permissions {
+            stamp.mode = permissions;
+        }
+        stamp
+    }
+
+    /// MS-DOS timestamp for the header fields. Zip readers treat these as
+    /// local time; we store UTC and rely on the extended timestamp field.
+    fn dos_time(&self) -> DateTime {
+        dos_date_time(self.mtime)
+    }
+
+    /// Payload of the Info-ZIP extended timestamp field: a flags byte
+    /// announcing the modification time, then that time in UTC seconds.
+    fn extended_timestamp(&self) -> [u8; 5] {
+        let mut payload = [0x01, 0, 0, 0, 0];
+        let mtime = self.mtime.min(u64::from(u32::MAX)) as u32;
+        payload[1..].copy_from_slice(&mtime.to_le_bytes());
+        payload
+    }
+}
+
+/// Header id of the Info-ZIP extended timestamp extra field ("UT").
+const EXTENDED_TIMESTAMP_ID: u16 = 0x5455;
+
+/// Entries at least this large need ZIP64 size fields.
+const ZIP64_THRESHOLD: u64 = 0xFFFF_FFFF;
+
+/// How much of a file is compressed to decide whether compressing pays off.
+const TRIAL_SAMPLE: u64 = 256 * 1024;
+
+/// Fraction of the first `TRIAL_SAMPLE` bytes of `file_path` saved by `method`.
+fn trial_savings(
+    file_path: &Path,
+    method: ZipMethod,
+    level: Option<i64>,
+) -> Result<f64, ZippingErr> {
+    let mut sample = Vec::new();
+    File::open(file_path)
+        .and_then(|file| file.take(TRIAL_SAMPLE).read_to_end(&mut sample))
+        .map_err(ZippingErr::FileOpenFailed)?;
+    if sample.is_empty() {
+        return Ok(0.0);
+    }
+
+    let compressed = match method {
+        ZipMethod::Lzma => {
+            let stamp = EntryStamp {
+                mtime: 0,
+                mode: 0o644,
+            };
+            let payload = lzma_entry(
+                &mut sample.as_slice(),
+                "sample",
+                stamp,
+                &ZipEntryInfo::default(),
+                level,
+            )?;
+            ZipArchive::new(payload)?.by_index_raw(0)?.compressed_size()
+        }
+        _ => {
+            let mut trial = ZipWriter::new(io::Cursor::new(Vec::new()));
+            let trial_options = SimpleFileOptions::default()
+                .compression_method(method.into())
+                .compression_level(level);
+            trial.start_file("sample", trial_options)?;
+            trial.write_all(&sample).map_err(ZippingErr::WriteFailed)?;
+            let mut archive = trial.finish_into_readable()?;
+            let compressed = archive.by_index_raw(0)?.compressed_size();
+            compressed
+        }
+    };
+    Ok(1.0 - compressed as f64 / sample.len() as f64)
+}
+
+/// Builds a one-entry archive holding `reader` as an LZMA (method 14) entry,
+/// ready to be merged into the real archive; the `zip` crate can read
+/// LZMA entries but not write them. The data is encoded with liblzma's
+/// LZMA-alone encoder and re-framed the way the zip APPNOTE expects: a
+/// 4-byte SDK version and properties size, the 5 property bytes, then the
+/// raw stream terminated by an end marker (general purpose flag bit 1).
+/// Both headers ask for version 6.3 to extract, as the APPNOTE requires.
+/// The archive is streamed to an unnamed temporary file; entries that do
+/// not fit the 32-bit size fields fail with `ZippingErr::EntryTooLarge`.
+fn lzma_entry<R: Read + ?Sized>(
+    reader: &mut R,
+    name: &str,
+    stamp: EntryStamp,
+    info: &ZipEntryInfo,
+    level: Option<i64>,
+) -> Result<File, ZippingErr> {
+    let too_large = || ZippingErr::EntryTooLarge(name.to_string());
+    let name_len = u16::try_from(name.len()).map_err(|_| ZippingErr::InvalidFileName)?;
+    let ascii = name.is_ascii() && info.comment.is_ascii();
+    let flags: u16 = if ascii { 0x0002 } else { 0x0802 };
+
+    let mtime = stamp.dos_time();
+    let mut extra = EXTENDED_TIMESTAMP_ID.to_le_bytes().to_vec();
+    extra.extend_from_slice(&5u16.to_le_bytes());
+    extra.extend_from_slice(&stamp.extended_timestamp());
+    for field in &info.extra_fields {
+        extra.extend_from_slice(&field.id.to_le_bytes());
+        extra.extend_from_slice(&(field.data.len() as u16).to_le_bytes());
+        extra.extend_from_slice(&field.data);
+    }
+    let common = |crc: u32, compressed_size: u32, size: u32| {
+        let mut common = Vec::new();
+        common.extend_from_slice(&63u16.to_le_bytes());
+        common.extend_from_slice(&flags.to_le_bytes());
+        common.extend_from_slice(&14u16.to_le_bytes());
+        common.extend_from_slice(&mtime.timepart().to_le_bytes());
+        common.extend_from_slice(&mtime.datepart().to_le_bytes());
+        common.extend_from_slice(&crc.to_le_bytes());
+        common.extend_from_slice(&compressed_size.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&name_len.to_le_bytes());
+        common.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        common
+    };
+    let local_header = |common: &[u8]| {
+        let mut header = 0x0403_4b50u32.to_le_bytes().to_vec();
+        header.extend_from_slice(common);
+        header.extend_from_slice(name.as_bytes());
+        header.extend_from_slice(&extra);
+        header
+    };
+
+    // The local header is written with zero sizes and patched at the end.
+    let mut file = tempfile::tempfile().map_err(ZippingErr::WriteFailed)?;
+    let data_offset = local_header(&common(0, 0, 0)).len() as u64;
+    file.seek(SeekFrom::Start(data_offset))
+        .map_err(ZippingErr::WriteFailed)?;
+
+    let preset = level.map_or(6, |level| level.clamp(0, 9) as u32);
+    let lzma_options = LzmaOptions::new_preset(preset)
+        .map_err(|e| ZippingErr::WriteFailed(io::Error::other(e)))?;
+    let stream = Stream::new_lzma_encoder(&lzma_options)
+        .map_err(|e| ZippingErr::WriteFailed(io::Error::other(e)))?;
+    let framing = LzmaFraming {
+        inner: BufWriter::new(&mut file),
+        header: Vec::with_capacity(13),
+        written: 0,
+    };
+    let mut encoder = XzEncoder::new_stream(framing, stream);
+    let mut crc = Crc::new();
+    let mut total = 0u64;
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = reader
+            .read(&mut buffer)
+            .map_err(ZippingErr::FileOpenFailed)?;
+        if read == 0 {
+            break;
+        }
+        crc.update(&buffer[..read]);
+        total += read as u64;
+        if total > u64::from(u32::MAX) {
+            return Err(too_large());
+        }
+        encoder
+            .write_all(&buffer[..read])
+            .map_err(ZippingErr::WriteFailed)?;
+    }
+    let mut framing = encoder.finish().map_err(ZippingErr::WriteFailed)?;
+    framing.inner.flush().map_err(ZippingErr::WriteFailed)?;
+    let compressed_size = u32::try_from(framing.written).map_err(|_| too_large())?;
+    let size = u32::try_from(total).map_err(|_| too_large())?;
+    drop(framing);
+
+    let common = common(crc.sum(), compressed_size, size);
+    let central_offset =
+        u32::try_from(data_offset + u64::from(compressed_size)).map_err(|_| too_large())?;
+    let mut central = 0x0201_4b50u32.to_le_bytes().to_vec();
+    central.extend_from_slice(&(3u16 << 8 | 63).to_le_bytes());
+    central.extend_from_slice(&common);
+    central.extend_from_slice(&(info.comment.len() as u16).to_le_bytes());
+    central.extend_from_slice(&[0; 4]); // disk, internal attributes
+    central.extend_from_slice(&((0o100000 | stamp.mode & 0o7777) << 16).to_le_bytes());
+    central.extend_from_slice(&0u32.to_le_bytes());
+    central.extend_from_slice(name.as_bytes());
+    central.extend_from_slice(&extra);
+    central.extend_from_slice(info.comment.as_bytes());
+    let central_size = central.len() as u32;
+    central.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    central.extend_from_slice(&[0, 0, 0, 0, 1, 0, 1, 0]);
+    central.extend_from_slice(&central_size.to_le_bytes());
+    central.extend_from_slice(&central_offset.to_le_bytes());
+    central.extend_from_slice(&0u16.to_le_bytes());
+
+    let mut finish = || -> io::Result<()> {
+        file.write_all(&central)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&local_header(&common))?;
+        file.seek(SeekFrom::Start(0)).map(|_| ())
+    };
+    finish().map_err(ZippingErr::WriteFailed)?;
+    Ok(file)
+}
+
+/// Fails before any data is read when an LZMA entry is known to exceed the
+/// 4 GiB that `lzma_entry` can write.
+fn check_lzma_size(method: ZipMethod, size: Option<u64>, name: &str) -> Result<(), ZippingErr> {
+    match method == ZipMethod::Lzma && size.is_some_and(|size| size > u64::from(u32::MAX)) {
+        true => Err(ZippingErr::EntryTooLarge(name.to_string())),
+        false => Ok(()),
+    }
+}
+
+/// Turns liblzma's LZMA-alone output into the zip framing on the fly:
+/// keeps the 5 property bytes behind the SDK version, drops the 8-byte
+/// size, and counts everything written as the compressed size.
+struct LzmaFraming<W: Write> {
+    inner: W,
+    header: Vec<u8>,
+    written: u64,
+}
+
+impl<W: Write> Write for LzmaFraming<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut data = buf;
+        if self.header.len() < 13 {
+            let take = data.len().min(13 - self.header.len());
+            self.header.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.header.len() < 13 {
+                return Ok(buf.len());
+            }
+            self.inner.write_all(&[9, 20, 5, 0])?;
+            self.inner.write_all(&self.header[..5])?;
+            self.written += 9;
+        }
+        self.inner.write_all(data)?;
+        self.written += data.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Zip entry name for a relative path, always using `/` as separator.
+pub(crate) fn entry_name(relative: &Path) -> Option<String> {
+    let components: Vec<_> = relative
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    (!components.is_empty()).then(|| components.join("/"))
+}
+
+/// Seconds since the Unix epoch of a DOS timestamp, read as UTC the way
+/// `dos_date_time` writes it.
+fn dos_secs(time: DateTime) -> u64 {
+    let (year, month, day) = (
+        i64::from(time.year()),
+        i64::from(time.month()),
+        i64::from(time.day()),
+    );
+
+    // Days-from-civil, after Howard Hinnant's date algorithms.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = (era * 146_097 + doe - 719_468).max(0) as u64;
+
+    days * 86_400
+        + u64::from(time.hour()) * 3600
+        + u64::from(time.minute()) * 60
+        + u64::from(time.second())
+}
+
+/// Converts seconds since the Unix epoch (UTC) into an MS-DOS timestamp,
+/// clamped to the 1980-2107 range the format can represent.
+pub(crate) fn dos_date_time(secs: u64) -> DateTime {
+    let days = (secs / 86_400) as i64;
+    let seconds_of_day = secs % 86_400;
+
+    // Civil-from-days, after Howard Hinnant's date algorithms.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    if year < 1980 {
+        return DateTime::default();
+    }
+    DateTime::from_date_and_time(
+        year.min(2107) as u16,
+        month,
+        day,
+        (seconds_of_day / 3600) as u8,
+        (seconds_of_day % 3600 / 60) as u8,
+        (seconds_of_day % 60) as u8,
+    )
+    .unwrap_or_default()
+}